@@ -86,7 +86,7 @@ async fn main() -> Result<(), Box<dyn StdError>> {
         .with_server_url("http://localhost:52555".to_string())
         .with_prompt_template(prompt_template.to_string())
         .with_temperature(0.7)
-        .build();
+        .build()?;
 
     // Define the system prompt
     let system_prompt = "You are a great summarizer and your task is to summarize the content";
@@ -94,7 +94,7 @@ async fn main() -> Result<(), Box<dyn StdError>> {
     let llm = LLM::builder()
         .with_options(options)
         .with_process_response(|stream| Box::pin(llamacpp_process_stream(stream)))
-        .build();
+        .build()?;
 
     // Define the user prompt
     let user_prompt = aggregated_content;
@@ -107,7 +107,7 @@ async fn main() -> Result<(), Box<dyn StdError>> {
         .with_user_prompt(user_prompt.to_string())
         .with_stream(true)
         .with_llm(llm)
-        .build();
+        .build()?;
 
     if let Err(e) = agent.invoke().await {
         eprintln!("Error during summarization: {}", e);
@@ -26,13 +26,13 @@ async fn main() -> Result<(), Box<dyn StdError>> {
         .with_server_url("http://localhost:52555".to_string())
         .with_prompt_template(prompt_template.to_string())
         .with_temperature(0.7)
-        .build();
+        .build()?;
 
     // Build the LLM instance
     let llm = LLM::builder()
         .with_options(options)
         .with_process_response(|stream| Box::pin(llamacpp_process_stream(stream)))
-        .build();
+        .build()?;
 
     // Define system prompts for each agent
     let system_prompt_1 = "You are an excellent content generator.";
@@ -53,7 +53,7 @@ async fn main() -> Result<(), Box<dyn StdError>> {
                 .with_user_prompt(user_prompt_1.to_string())
                 .with_stream(true)
                 .with_llm(llm.clone())
-                .build()
+                .build()?
         )
     );
 
@@ -65,7 +65,7 @@ async fn main() -> Result<(), Box<dyn StdError>> {
                 .with_user_prompt(user_prompt_2.to_string())
                 .with_stream(true)
                 .with_llm(llm.clone())
-                .build()
+                .build()?
         )
     );
 
@@ -77,7 +77,7 @@ async fn main() -> Result<(), Box<dyn StdError>> {
                 .with_user_prompt(user_prompt_3.to_string())
                 .with_stream(true)
                 .with_llm(llm.clone())
-                .build()
+                .build()?
         )
     );
 
@@ -42,7 +42,7 @@ async fn main() -> Result<(), Box<dyn StdError>> {
                 )
                 .with_stream(true)
                 .with_llm(content_llm)
-                .build()
+                .build()?
         )
     );
     // Get LLM for LLaMA (Qwen will be unloaded if memory is low)
@@ -54,7 +54,7 @@ async fn main() -> Result<(), Box<dyn StdError>> {
                 .with_user_prompt("Analyze the generated content.".to_string())
                 .with_stream(true)
                 .with_llm(llama_llm)
-                .build()
+                .build()?
         )
     );
 
@@ -1,9 +1,15 @@
+use crate::error::PyanoError;
 use crate::llm::llm_builder::LLM;
+use crate::llm::context::TruncationStrategy;
+use crate::llm::error::LLMError;
+use crate::llm::response::LLMEvent;
+use crate::llm::types::CancellationToken;
 use std::error::Error as StdError;
 use std::pin::Pin;
-use super::agent_trait::AgentTrait;
+use super::agent_trait::{ AgentTrait, InvokeFuture };
 use tokio_stream::StreamExt;
 use crate::tools::Tool;
+use log::debug;
 use std::sync::Arc;
 
 pub struct Agent {
@@ -13,6 +19,7 @@ pub struct Agent {
     pub(crate) llm: Option<LLM>,
     pub(crate) name: Option<String>,
     pub(crate) tools: Option<Vec<Arc<dyn Tool>>>, // New field for array of AgentTrait objects
+    pub(crate) truncation: TruncationStrategy,
 }
 
 impl AgentTrait for Agent {
@@ -49,35 +56,46 @@ impl AgentTrait for Agent {
                 '_
         >
     > {
+        self.invoke_with_cancellation(CancellationToken::new())
+    }
+
+    fn invoke_with_cancellation(&self, cancel: CancellationToken) -> InvokeFuture<'_> {
         Box::pin(async move {
-            let llm = self.llm.as_ref().expect("LLM is required");
-            let system_prompt = self.system_prompt.as_ref().expect("System prompt is missing");
-            let user_prompt = self.user_prompt.as_ref().expect("User prompt is missing");
+            let missing = |what: &str| PyanoError::Config(format!("Agent has no {}", what));
+            let llm = self.llm.as_ref().ok_or_else(|| missing("LLM"))?;
+            let system_prompt = self.system_prompt.as_ref().ok_or_else(|| missing("system prompt"))?;
+            let user_prompt = self.user_prompt.as_ref().ok_or_else(|| missing("user prompt"))?;
             let stream = self.stream.unwrap_or(false);
+            let user_prompt = &llm.fit_prompt(system_prompt, user_prompt, self.truncation).await?;
 
             let mut output = String::new(); // Buffer to collect the streamed output
 
             if stream {
-                let mut response_stream = llm.response_stream(user_prompt, system_prompt).await?;
-                while let Some(response) = response_stream.next().await {
-                    match response {
-                        Ok(bytes) => {
-                            let chunk = String::from_utf8_lossy(&bytes).to_string();
-                            print!("{}", chunk); // Stream to the console
-                            output.push_str(&chunk); // Collect into buffer
+                let mut events = llm.stream_with_cancellation(
+                    user_prompt,
+                    system_prompt,
+                    cancel
+                ).await?;
+                while let Some(event) = events.next().await {
+                    match event? {
+                        LLMEvent::Token { content, .. } => {
+                            print!("{}", content); // Stream to the console
+                            output.push_str(&content); // Collect into buffer
                         }
-                        Err(e) => eprintln!("Error streaming response: {}", e),
+                        LLMEvent::Error(message) => {
+                            return Err(LLMError::RequestFailed(message).into());
+                        }
+                        _ => {}
                     }
                 }
             } else {
-                let response = llm.response(user_prompt, system_prompt).await?;
-                // Safely extract and convert `response["content"]` to a string
-                if let Some(content) = response.get("content").and_then(|v| v.as_str()) {
-                    println!("Response: {}", content);
-                    output.push_str(content); // Append content to output buffer
-                } else {
-                    eprintln!("Error: `content` field is missing or not a string in the response");
-                }
+                let response = llm.response_with_cancellation(
+                    user_prompt,
+                    system_prompt,
+                    cancel
+                ).await?;
+                debug!("Response: {}", response.content);
+                output.push_str(&response.content); // Append content to output buffer
             }
 
             Ok(output) // Return the complete output
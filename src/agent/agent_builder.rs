@@ -1,3 +1,5 @@
+use crate::error::PyanoError;
+use crate::llm::context::TruncationStrategy;
 use crate::llm::llm_builder::LLM;
 use super::agent::Agent;
 use crate::tools::Tool;
@@ -9,6 +11,7 @@ pub struct AgentBuilder {
     llm: Option<LLM>,
     name: Option<String>,
     tools: Option<Vec<Arc<dyn Tool>>>, // New field for tools
+    truncation: TruncationStrategy,
 }
 
 impl AgentBuilder {
@@ -20,6 +23,7 @@ impl AgentBuilder {
             llm: None,
             name: None,
             tools: None, // Initialize tools as None
+            truncation: TruncationStrategy::default(),
         }
     }
 
@@ -53,24 +57,35 @@ impl AgentBuilder {
         self
     }
 
-    pub fn build(self) -> Agent {
+    /// How the user prompt is shortened when it does not fit the model's context window.
+    pub fn with_truncation_strategy(mut self, truncation: TruncationStrategy) -> Self {
+        self.truncation = truncation;
+        self
+    }
+
+    pub fn build(self) -> Result<Agent, PyanoError> {
         if self.llm.is_none() {
-            panic!("LLM must be provided before building the Agent");
+            return Err(PyanoError::Config("LLM must be provided before building the Agent".into()));
         }
         if self.user_prompt.is_none() {
-            panic!("User prompt must be provided before building the Agent");
+            return Err(
+                PyanoError::Config("User prompt must be provided before building the Agent".into())
+            );
         }
         if self.system_prompt.is_none() {
-            panic!("System prompt must be provided before building the Agent");
+            return Err(
+                PyanoError::Config("System prompt must be provided before building the Agent".into())
+            );
         }
 
-        Agent {
+        Ok(Agent {
             system_prompt: self.system_prompt,
             user_prompt: self.user_prompt,
             stream: self.stream,
             llm: self.llm,
             name: self.name,
             tools: self.tools, // Set tools field
-        }
+            truncation: self.truncation,
+        })
     }
 }
@@ -4,10 +4,21 @@
 //     pub user_template: String,
 //     pub output_format: Option<String>,
 // }
+use crate::llm::error::LLMError;
 use crate::llm::llm_builder::LLM;
+use crate::llm::types::CancellationToken;
 use std::error::Error as StdError; // Importing the correct trait
 use std::pin::Pin;
 
+/// The future returned by `AgentTrait::invoke_with_cancellation`.
+pub type InvokeFuture<'a> = Pin<
+    Box<
+        dyn std::future::Future<Output = Result<String, Box<dyn StdError + Send + Sync>>> +
+            Send +
+            'a
+    >
+>;
+
 pub trait AgentTrait: Send + Sync {
     fn system_prompt(&self) -> Option<&String>;
     fn user_prompt(&self) -> Option<&String>;
@@ -25,5 +36,18 @@ pub trait AgentTrait: Send + Sync {
                 '_
         >
     >;
+
+    /// Like `invoke`, failing with `LLMError::Cancelled` as soon as `cancel` is cancelled.
+    /// By default the `invoke` future is dropped, which closes its connection to the server.
+    fn invoke_with_cancellation(&self, cancel: CancellationToken) -> InvokeFuture<'_> {
+        Box::pin(async move {
+            tokio::select! {
+                biased;
+                _ = cancel.cancelled() => Err(Box::new(LLMError::Cancelled) as Box<_>),
+                result = self.invoke() => result,
+            }
+        })
+    }
+
     fn get_tools(&self) -> String;
 }
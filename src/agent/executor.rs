@@ -1,162 +1,419 @@
-// use std::{ collections::HashMap, sync::Arc };
-
-// use async_trait::async_trait;
-// use serde_json::json;
-// use tokio::sync::Mutex;
-
-// use super::{ agent::Agent, AgentError };
-// use crate::schemas::{ LogTools, Message };
-// use crate::{
-//     chain::{ chain_trait::Chain, ChainError },
-//     language_models::GenerateResult,
-//     memory::SimpleMemory,
-//     prompt::PromptArgs,
-//     schemas::{ agent::{ AgentAction, AgentEvent }, memory::BaseMemory },
-//     tools::Tool,
-// };
-
-// pub struct AgentExecutor<A> where A: Agent {
-//     agent: A,
-//     max_iterations: Option<i32>,
-//     break_if_error: bool,
-//     pub memory: Option<Arc<Mutex<dyn BaseMemory>>>,
-// }
-
-// impl<A> AgentExecutor<A> where A: Agent {
-//     pub fn from_agent(agent: A) -> Self {
-//         Self {
-//             agent,
-//             max_iterations: Some(10),
-//             break_if_error: false,
-//             memory: None,
-//         }
-//     }
-
-//     pub fn with_max_iterations(mut self, max_iterations: i32) -> Self {
-//         self.max_iterations = Some(max_iterations);
-//         self
-//     }
-
-//     pub fn with_memory(mut self, memory: Arc<Mutex<dyn BaseMemory>>) -> Self {
-//         self.memory = Some(memory);
-//         self
-//     }
-
-//     pub fn with_break_if_error(mut self, break_if_error: bool) -> Self {
-//         self.break_if_error = break_if_error;
-//         self
-//     }
-
-//     fn get_name_to_tools(&self) -> HashMap<String, Arc<dyn Tool>> {
-//         let mut name_to_tool = HashMap::new();
-//         for tool in self.agent.get_tools().iter() {
-//             log::debug!("Loading Tool:{}", tool.name());
-//             name_to_tool.insert(tool.name().trim().replace(" ", "_"), tool.clone());
-//         }
-//         name_to_tool
-//     }
-// }
-
-// #[async_trait]
-// impl<A> Chain for AgentExecutor<A> where A: Agent + Send + Sync {
-//     async fn call(&self, input_variables: PromptArgs) -> Result<GenerateResult, ChainError> {
-//         let mut input_variables = input_variables.clone();
-//         let name_to_tools = self.get_name_to_tools();
-//         let mut steps: Vec<(AgentAction, String)> = Vec::new();
-//         log::debug!("steps: {:?}", steps);
-//         if let Some(memory) = &self.memory {
-//             let memory = memory.lock().await;
-//             input_variables.insert("chat_history".to_string(), json!(memory.messages()));
-//         } else {
-//             input_variables.insert(
-//                 "chat_history".to_string(),
-//                 json!(SimpleMemory::new().messages())
-//             );
-//         }
-
-//         loop {
-//             let agent_event = self.agent
-//                 .plan(&steps, input_variables.clone()).await
-//                 .map_err(|e| ChainError::AgentError(format!("Error in agent planning: {}", e)))?;
-//             match agent_event {
-//                 AgentEvent::Action(actions) => {
-//                     for action in actions {
-//                         log::debug!("Action: {:?}", action.tool_input);
-//                         let tool = name_to_tools
-//                             .get(&action.tool)
-//                             .ok_or_else(|| {
-//                                 AgentError::ToolError(format!("Tool {} not found", action.tool))
-//                             })
-//                             .map_err(|e| ChainError::AgentError(e.to_string()))?;
-
-//                         let observation_result = tool.call(&action.tool_input).await;
-
-//                         let observation = match observation_result {
-//                             Ok(result) => result,
-//                             Err(err) => {
-//                                 log::info!(
-//                                     "The tool return the following error: {}",
-//                                     err.to_string()
-//                                 );
-//                                 if self.break_if_error {
-//                                     return Err(
-//                                         ChainError::AgentError(
-//                                             AgentError::ToolError(err.to_string()).to_string()
-//                                         )
-//                                     );
-//                                 } else {
-//                                     format!("The tool return the following error: {}", err)
-//                                 }
-//                             }
-//                         };
-
-//                         steps.push((action, observation));
-//                     }
-//                 }
-//                 AgentEvent::Finish(finish) => {
-//                     if let Some(memory) = &self.memory {
-//                         let mut memory = memory.lock().await;
-
-//                         memory.add_user_message(match &input_variables["input"] {
-//                             // This avoids adding extra quotes to the user input in the history.
-//                             serde_json::Value::String(s) => s,
-//                             x => x, // this the json encoded value.
-//                         });
-
-//                         let mut tools_ai_message_seen: HashMap<String, ()> = HashMap::default();
-//                         for (action, observation) in steps {
-//                             let LogTools { tool_id, tools } = serde_json::from_str(&action.log)?;
-//                             let tools_value: serde_json::Value = serde_json::from_str(&tools)?;
-//                             if tools_ai_message_seen.insert(tools, ()).is_none() {
-//                                 memory.add_message(
-//                                     Message::new_ai_message("").with_tool_calls(tools_value)
-//                                 );
-//                             }
-//                             memory.add_message(Message::new_tool_message(observation, tool_id));
-//                         }
-
-//                         memory.add_ai_message(&finish.output);
-//                     }
-//                     return Ok(GenerateResult {
-//                         generation: finish.output,
-//                         ..Default::default()
-//                     });
-//                 }
-//             }
-
-//             if let Some(max_iterations) = self.max_iterations {
-//                 if steps.len() >= (max_iterations as usize) {
-//                     return Ok(GenerateResult {
-//                         generation: "Max iterations reached".to_string(),
-//                         ..Default::default()
-//                     });
-//                 }
-//             }
-//         }
-//     }
-
-//     async fn invoke(&self, input_variables: PromptArgs) -> Result<String, ChainError> {
-//         let result = self.call(input_variables).await?;
-//         Ok(result.generation)
-//     }
-// }
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use log::{ debug, warn };
+use serde::{ Deserialize, Serialize };
+use serde_json::Value;
+
+use super::agent::Agent;
+use super::agent_trait::AgentTrait;
+use crate::error::PyanoError;
+use crate::llm::chat::ChatMessage;
+use crate::tools::Tool;
+
+/// Model turns run before the executor gives up, unless `with_max_iterations` says otherwise.
+pub const DEFAULT_MAX_ITERATIONS: usize = 10;
+
+const HERMES_INSTRUCTIONS: &str =
+    "You may call one or more functions to assist with the user query. \
+     Function signatures are within <tools></tools> XML tags:\n{tools}\n\n\
+     For each function call, return a json object with function name and arguments within \
+     <tool_call></tool_call> XML tags:\n\
+     <tool_call>\n{\"name\": <function-name>, \"arguments\": <args-json-object>}\n</tool_call>\n\
+     Answer without tags once you have what you need.";
+
+const REACT_INSTRUCTIONS: &str =
+    "You can use these tools:\n{tools}\n\n\
+     Use the following format:\n\n\
+     Thought: what to do next\n\
+     Action: the tool to use, one of [{names}]\n\
+     Action Input: the input of the tool, as a JSON object\n\
+     Observation: the result of the tool\n\
+     ... (Thought, Action, Action Input and Observation can repeat)\n\
+     Thought: I know the final answer\n\
+     Final Answer: the answer to the question";
+
+/// Cuts off a ReAct answer where the model starts making up the tool's result.
+const REACT_STOP_WORD: &str = "\nObservation:";
+
+/// How the model is asked to call tools, and how its calls are read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ToolCallFormat {
+    /// `<tool_call>{"name": ..., "arguments": {...}}</tool_call>` blocks, as written by Hermes,
+    /// Qwen and other models trained for function calling
+    #[default]
+    Hermes,
+    /// `Action:` and `Action Input:` lines, ending with `Final Answer:`, for other models
+    ReAct,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ToolCall {
+    pub name: String,
+    #[serde(default)]
+    pub arguments: Value,
+}
+
+/// What the model asked for in one turn.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ModelTurn {
+    ToolCalls(Vec<ToolCall>),
+    FinalAnswer(String),
+}
+
+/// One tool call made by the executor, and what came back.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ExecutorStep {
+    /// The model turn the call was made in, starting at 0
+    pub iteration: usize,
+    pub call: ToolCall,
+    pub observation: String,
+    /// The tool failed or does not exist, and `observation` is the error
+    pub is_error: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FinishReason {
+    /// The model gave a final answer
+    Answer,
+    /// `max_iterations` turns went by without one
+    MaxIterations,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ExecutorOutput {
+    /// The final answer, or the model's last output if it never gave one
+    pub output: String,
+    pub steps: Vec<ExecutorStep>,
+    pub finish_reason: FinishReason,
+}
+
+/// Runs an `Agent` with its tools: the model is shown the tool definitions, every tool call
+/// it writes is dispatched to the agent's `Tool`s, and the results are sent back until it
+/// answers without calling a tool.
+///
+/// The conversation goes through `LLM::chat`, so the agent's `LLM` needs a chat template.
+/// A tool that fails or does not exist is reported to the model as an error, unless
+/// `with_break_if_error` makes the run fail instead.
+///
+/// # Usage
+/// ```rust,ignore
+/// let agent = AgentBuilder::new()
+///     .with_system_prompt("You are a research assistant".to_string())
+///     .with_user_prompt("Who maintains the sqlite-vec extension?".to_string())
+///     .with_tools(vec![Arc::new(DuckDuckGoSearchResults::default())])
+///     .with_llm(llm)
+///     .build()?;
+///
+/// let run = AgentExecutor::from_agent(agent).with_max_iterations(5).run().await?;
+/// for step in &run.steps {
+///     println!("{}({}) -> {}", step.call.name, step.call.arguments, step.observation);
+/// }
+/// println!("{}", run.output);
+/// ```
+pub struct AgentExecutor {
+    agent: Agent,
+    format: ToolCallFormat,
+    max_iterations: usize,
+    break_if_error: bool,
+}
+
+impl AgentExecutor {
+    pub fn from_agent(agent: Agent) -> Self {
+        Self {
+            agent,
+            format: ToolCallFormat::default(),
+            max_iterations: DEFAULT_MAX_ITERATIONS,
+            break_if_error: false,
+        }
+    }
+
+    pub fn with_format(mut self, format: ToolCallFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    pub fn with_max_iterations(mut self, max_iterations: usize) -> Self {
+        self.max_iterations = max_iterations;
+        self
+    }
+
+    /// Fail the run with `PyanoError::Tool` on the first tool error or unreadable tool call,
+    /// instead of reporting it to the model.
+    pub fn with_break_if_error(mut self, break_if_error: bool) -> Self {
+        self.break_if_error = break_if_error;
+        self
+    }
+
+    pub fn agent(&self) -> &Agent {
+        &self.agent
+    }
+
+    /// Runs the agent on its user prompt.
+    pub async fn run(&self) -> Result<ExecutorOutput, PyanoError> {
+        let input = self.agent
+            .user_prompt()
+            .ok_or_else(|| PyanoError::Config("Agent has no user prompt".to_string()))?;
+        self.run_with_input(input).await
+    }
+
+    /// Runs the agent on `input` instead of its user prompt.
+    pub async fn run_with_input(&self, input: &str) -> Result<ExecutorOutput, PyanoError> {
+        let mut llm = self.agent
+            .llm()
+            .ok_or_else(|| PyanoError::Config("Agent has no LLM".to_string()))?
+            .clone();
+        if self.format == ToolCallFormat::ReAct {
+            let mut stop_words = llm.options().stop_words.clone().unwrap_or_default();
+            stop_words.push(REACT_STOP_WORD.to_string());
+            llm.set_options(llm.options().clone().with_stop_words(stop_words));
+        }
+
+        let tools = self.tools_by_name();
+        let mut messages = vec![
+            ChatMessage::system(self.system_prompt()),
+            ChatMessage::user(input)
+        ];
+        let mut steps = Vec::new();
+        let mut output = String::new();
+
+        for iteration in 0..self.max_iterations {
+            // Tool results pile up, so the conversation is fitted to the context every turn
+            messages = llm.fit_messages(&messages, self.agent.truncation).await?;
+            let response = llm.chat(&messages).await?;
+            output = response.content.trim().to_string();
+            messages.push(ChatMessage::assistant(response.content.clone()));
+
+            let calls = match parse_tool_calls(self.format, &response.content) {
+                Ok(ModelTurn::FinalAnswer(answer)) => {
+                    let finish_reason = FinishReason::Answer;
+                    return Ok(ExecutorOutput { output: answer, steps, finish_reason });
+                }
+                Ok(ModelTurn::ToolCalls(calls)) => calls,
+                Err(e) if self.break_if_error => {
+                    return Err(PyanoError::Tool(e));
+                }
+                Err(e) => {
+                    warn!("Unreadable tool call: {}", e);
+                    messages.push(ChatMessage::user(format!("Error: {}", e)));
+                    continue;
+                }
+            };
+
+            for call in calls {
+                debug!("Calling tool {} with {}", call.name, call.arguments);
+                let (observation, is_error) = match call_tool(&tools, &call).await {
+                    Ok(observation) => (observation, false),
+                    Err(e) if self.break_if_error => {
+                        return Err(PyanoError::Tool(e));
+                    }
+                    Err(e) => (format!("Error: {}", e), true),
+                };
+                messages.push(match self.format {
+                    ToolCallFormat::Hermes => ChatMessage::tool(call.name.clone(), &observation),
+                    ToolCallFormat::ReAct => {
+                        ChatMessage::user(format!("Observation: {}", observation))
+                    }
+                });
+                steps.push(ExecutorStep { iteration, call, observation, is_error });
+            }
+        }
+
+        warn!("Agent gave no final answer within {} iterations", self.max_iterations);
+        Ok(ExecutorOutput { output, steps, finish_reason: FinishReason::MaxIterations })
+    }
+
+    /// The agent's system prompt followed by the tool instructions of the format.
+    fn system_prompt(&self) -> String {
+        let tools = self.agent.get_tools();
+        let instructions = match self.format {
+            ToolCallFormat::Hermes => HERMES_INSTRUCTIONS.replace("{tools}", &tools),
+            ToolCallFormat::ReAct => {
+                let names: Vec<String> = self.agent.tools
+                    .iter()
+                    .flatten()
+                    .map(|tool| tool_key(&tool.name()))
+                    .collect();
+                REACT_INSTRUCTIONS.replace("{tools}", &tools).replace(
+                    "{names}",
+                    &names.join(", ")
+                )
+            }
+        };
+        match self.agent.system_prompt() {
+            Some(system_prompt) => format!("{}\n\n{}", system_prompt, instructions),
+            None => instructions,
+        }
+    }
+
+    fn tools_by_name(&self) -> HashMap<String, Arc<dyn Tool>> {
+        self.agent.tools
+            .iter()
+            .flatten()
+            .map(|tool| (tool_key(&tool.name()), tool.clone()))
+            .collect()
+    }
+}
+
+/// Tool names are matched ignoring surrounding whitespace, with spaces read as underscores.
+fn tool_key(name: &str) -> String {
+    name.trim().replace(' ', "_")
+}
+
+async fn call_tool(
+    tools: &HashMap<String, Arc<dyn Tool>>,
+    call: &ToolCall
+) -> Result<String, String> {
+    let tool = tools
+        .get(&tool_key(&call.name))
+        .ok_or_else(|| format!("Tool {} not found", call.name))?;
+    let input = match &call.arguments {
+        Value::String(input) => input.clone(),
+        arguments => arguments.to_string(),
+    };
+    match tool.json_call(&input).await {
+        Ok(Value::String(result)) => Ok(result),
+        Ok(result) => Ok(result.to_string()),
+        Err(e) => Err(format!("Tool {} failed: {}", call.name, e)),
+    }
+}
+
+/// Reads the tool calls in a model's output, or its final answer if it calls none.
+pub fn parse_tool_calls(format: ToolCallFormat, text: &str) -> Result<ModelTurn, String> {
+    match format {
+        ToolCallFormat::Hermes => parse_hermes(text),
+        ToolCallFormat::ReAct => parse_react(text),
+    }
+}
+
+fn parse_hermes(text: &str) -> Result<ModelTurn, String> {
+    const OPEN: &str = "<tool_call>";
+    const CLOSE: &str = "</tool_call>";
+
+    let mut calls = Vec::new();
+    let mut rest = text;
+    while let Some(start) = rest.find(OPEN) {
+        let after = &rest[start + OPEN.len()..];
+        // A call cut short by the token limit or a stop word has no closing tag
+        let (body, next) = match after.find(CLOSE) {
+            Some(end) => (&after[..end], &after[end + CLOSE.len()..]),
+            None => (after, ""),
+        };
+        let mut call: ToolCall = serde_json
+            ::from_str(strip_code_fence(body))
+            .map_err(|e| format!("Invalid tool call {}: {}", body.trim(), e))?;
+        // Some models encode the arguments as a JSON string
+        if let Value::String(arguments) = &call.arguments {
+            if let Ok(arguments @ Value::Object(_)) = serde_json::from_str(arguments) {
+                call.arguments = arguments;
+            }
+        }
+        calls.push(call);
+        rest = next;
+    }
+
+    if calls.is_empty() {
+        Ok(ModelTurn::FinalAnswer(text.trim().to_string()))
+    } else {
+        Ok(ModelTurn::ToolCalls(calls))
+    }
+}
+
+fn parse_react(text: &str) -> Result<ModelTurn, String> {
+    const ACTION: &str = "Action:";
+    const INPUT: &str = "Action Input:";
+    const FINAL: &str = "Final Answer:";
+
+    let text = text.split(REACT_STOP_WORD.trim_start()).next().unwrap_or(text);
+    let action = text.find(ACTION);
+    let answer = text.find(FINAL);
+
+    match (action, answer) {
+        (Some(action), answer) if answer.is_none_or(|answer| action < answer) => {
+            let after = &text[action + ACTION.len()..];
+            let (name, input) = match after.find(INPUT) {
+                Some(input) => {
+                    (&after[..input], strip_code_fence(&after[input + INPUT.len()..]))
+                }
+                None => (after, ""),
+            };
+            let name = name.lines().next().unwrap_or_default().trim();
+            if name.is_empty() {
+                return Err(format!("No tool named after `{}`", ACTION));
+            }
+            let arguments = serde_json
+                ::from_str(input)
+                .unwrap_or_else(|_| Value::String(input.to_string()));
+            Ok(ModelTurn::ToolCalls(vec![ToolCall { name: name.to_string(), arguments }]))
+        }
+        (_, Some(answer)) => {
+            Ok(ModelTurn::FinalAnswer(text[answer + FINAL.len()..].trim().to_string()))
+        }
+        (_, None) => Ok(ModelTurn::FinalAnswer(text.trim().to_string())),
+    }
+}
+
+/// Removes a Markdown code fence around a JSON value.
+fn strip_code_fence(text: &str) -> &str {
+    let text = text.trim();
+    match text.strip_prefix("```") {
+        Some(fenced) => {
+            let fenced = fenced.strip_prefix("json").unwrap_or(fenced);
+            fenced.strip_suffix("```").unwrap_or(fenced).trim()
+        }
+        None => text,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_parse_hermes_calls() {
+        let text =
+            "Let me check.\n<tool_call>\n\
+             {\"name\": \"weather\", \"arguments\": {\"city\": \"Oslo\"}}\n</tool_call>\n\
+             <tool_call>{\"name\": \"clock\", \"arguments\": \"{\\\"zone\\\": \\\"CET\\\"}\"}";
+        assert_eq!(
+            parse_tool_calls(ToolCallFormat::Hermes, text).unwrap(),
+            ModelTurn::ToolCalls(vec![
+                ToolCall { name: "weather".to_string(), arguments: json!({ "city": "Oslo" }) },
+                ToolCall { name: "clock".to_string(), arguments: json!({ "zone": "CET" }) }
+            ])
+        );
+        assert_eq!(
+            parse_tool_calls(ToolCallFormat::Hermes, " It is sunny. ").unwrap(),
+            ModelTurn::FinalAnswer("It is sunny.".to_string())
+        );
+        assert!(parse_tool_calls(ToolCallFormat::Hermes, "<tool_call>{\"name\":").is_err());
+    }
+
+    #[test]
+    fn test_parse_react_steps() {
+        let text =
+            "Thought: I need the weather\nAction: weather\n\
+             Action Input: ```json\n{\"city\": \"Oslo\"}\n```\nObservation: made up";
+        assert_eq!(
+            parse_tool_calls(ToolCallFormat::ReAct, text).unwrap(),
+            ModelTurn::ToolCalls(vec![
+                ToolCall { name: "weather".to_string(), arguments: json!({ "city": "Oslo" }) }
+            ])
+        );
+
+        let text = "Action: search\nAction Input: sqlite-vec maintainer";
+        assert_eq!(
+            parse_tool_calls(ToolCallFormat::ReAct, text).unwrap(),
+            ModelTurn::ToolCalls(vec![
+                ToolCall { name: "search".to_string(), arguments: json!("sqlite-vec maintainer") }
+            ])
+        );
+
+        let text = "Thought: I know the final answer\nFinal Answer: Sunny, 21°C";
+        assert_eq!(
+            parse_tool_calls(ToolCallFormat::ReAct, text).unwrap(),
+            ModelTurn::FinalAnswer("Sunny, 21°C".to_string())
+        );
+    }
+}
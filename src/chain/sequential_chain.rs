@@ -2,6 +2,8 @@ use std::error::Error as StdError;
 use log::info;
 
 use crate::agent::agent_trait::AgentTrait;
+use crate::llm::error::LLMError;
+use crate::llm::types::CancellationToken;
 use std::sync::{ Arc, Mutex };
 
 #[derive(Clone)]
@@ -49,9 +51,21 @@ impl Chain {
     /// Run all agents in sequence.
     /// The output of agent i is passed as user_prompt to agent i+1.
     pub async fn run(&mut self) -> Result<(), Box<dyn StdError + Send + Sync>> {
+        self.run_with_cancellation(CancellationToken::new()).await
+    }
+
+    /// Like `run`, stopping with `LLMError::Cancelled` as soon as `cancel` is cancelled. Agents
+    /// that already finished are kept in the memory log.
+    pub async fn run_with_cancellation(
+        &mut self,
+        cancel: CancellationToken
+    ) -> Result<(), Box<dyn StdError + Send + Sync>> {
         let mut previous_output: Option<String> = None;
 
         for agent in &self.agents {
+            if cancel.is_cancelled() {
+                return Err(Box::new(LLMError::Cancelled));
+            }
             let mut agent = agent.lock().unwrap();
             info!("EXECUTING Agent = {:?}", agent.name());
 
@@ -62,7 +76,7 @@ impl Chain {
 
             // Get the current user prompt and execute the agent
             let user_input = agent.user_prompt().cloned().unwrap_or_default();
-            let output = agent.invoke().await?;
+            let output = agent.invoke_with_cancellation(cancel.clone()).await?;
 
             // Store in memory log
             {
@@ -38,7 +38,9 @@ impl EmbeddingBuilder {
     pub async fn build_embedder(&self) -> Result<DefaultEmbedder, EmbedderError> {
         let model_path = dirs
             ::home_dir()
-            .expect("Unable to get home directory")
+            .ok_or_else(|| {
+                EmbedderError::InitializationFailed("Unable to get home directory".into())
+            })?
             .join(self.model.model_path());
 
         let embbedder = DefaultEmbedder::new(
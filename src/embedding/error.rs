@@ -1,5 +1,4 @@
 use thiserror::Error;
-use rust_bert::RustBertError; // Ensure you have this import
 
 #[derive(Error, Debug)]
 pub enum EmbedderError {
@@ -11,7 +10,11 @@ pub enum EmbedderError {
 
     #[error("Request error: {0}")] RequestError(#[from] reqwest::Error),
 
-    #[error("Rust Bert error: {0}")] RustBertError(#[from] RustBertError),
+    #[cfg(feature = "rust-bert")]
+    #[error("Rust Bert error: {0}")]
+    RustBertError(#[from] rust_bert::RustBertError),
+
+    #[error("Model error: {0}")] ModelError(#[from] crate::model::error::ModelError),
 
     #[error("Tokio task error: {0}")] TaskError(#[from] tokio::task::JoinError),
 
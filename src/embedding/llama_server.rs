@@ -0,0 +1,285 @@
+use std::sync::atomic::{ AtomicUsize, Ordering };
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use log::{ info, warn };
+use reqwest::Client;
+use serde_json::{ json, Value };
+use tokio::sync::OnceCell;
+
+use super::embedder_trait::Embedder;
+use super::error::EmbedderError;
+use crate::model::{ ModelManagerInterface, ModelStatus };
+
+/// Texts sent per `/embedding` request unless `with_batch_size` says otherwise.
+pub const DEFAULT_BATCH_SIZE: usize = 32;
+
+/// Computes embeddings with a GGUF embedding model served by llama-server's `/embedding`
+/// endpoint, so no libtorch is needed.
+///
+/// A managed model is loaded through the model manager whenever it is not running, and is
+/// best configured with `ModelType::Embedding` so llama-server is started with `--embedding`.
+/// Call `initialize` before `dimensions`: the size of the vectors is read from the server.
+///
+/// # Usage
+/// ```rust,ignore
+/// let embedder = LlamaServerEmbedder::new(manager.clone(), "nomic-embed-text");
+/// let store = StoreBuilder::new().embedder(embedder).table("documents").build().await?;
+/// store.initialize().await?;
+/// ```
+pub struct LlamaServerEmbedder {
+    client: Client,
+    manager: Option<Arc<dyn ModelManagerInterface>>,
+    model_name: String,
+    server_url: Option<String>,
+    batch_size: usize,
+    normalize: bool,
+    dimensions: OnceCell<usize>,
+    next_replica: AtomicUsize,
+}
+
+impl LlamaServerEmbedder {
+    /// Embeds with the model `model_name` of `manager`.
+    pub fn new(manager: Arc<dyn ModelManagerInterface>, model_name: &str) -> Self {
+        Self {
+            client: Client::new(),
+            manager: Some(manager),
+            model_name: model_name.to_string(),
+            server_url: None,
+            batch_size: DEFAULT_BATCH_SIZE,
+            normalize: true,
+            dimensions: OnceCell::new(),
+            next_replica: AtomicUsize::new(0),
+        }
+    }
+
+    /// Embeds with an already running llama-server that is not managed by pyano.
+    pub fn from_url(server_url: &str) -> Self {
+        Self {
+            client: Client::new(),
+            manager: None,
+            model_name: String::new(),
+            server_url: Some(server_url.trim_end_matches('/').to_string()),
+            batch_size: DEFAULT_BATCH_SIZE,
+            normalize: true,
+            dimensions: OnceCell::new(),
+            next_replica: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    /// Scale vectors to unit length, on by default so cosine and L2 distances agree.
+    pub fn with_normalize(mut self, normalize: bool) -> Self {
+        self.normalize = normalize;
+        self
+    }
+
+    /// Loads the managed model if it is not running, and returns the URL to send requests
+    /// to. Replicas are used in turn.
+    async fn server_url(&self) -> Result<String, EmbedderError> {
+        let Some(manager) = &self.manager else {
+            return Ok(self.server_url.clone().unwrap_or_default());
+        };
+
+        if !matches!(manager.get_model_status(&self.model_name).await, Ok(ModelStatus::Running)) {
+            info!("Loading embedding model {}", self.model_name);
+            manager.load_model_by_name(&self.model_name).await?;
+        }
+
+        let urls = manager.get_model_config(&self.model_name).await?.server_config.replica_urls()?;
+        let replica = self.next_replica.fetch_add(1, Ordering::Relaxed) % urls.len().max(1);
+        urls.get(replica)
+            .cloned()
+            .ok_or_else(|| {
+                EmbedderError::InitializationFailed(
+                    format!("Model {} has no server", self.model_name)
+                )
+            })
+    }
+
+    /// The embedding size the server reports in `/v1/models`, or the length of a probe
+    /// embedding for servers that do not report it.
+    async fn query_dimensions(&self, url: &str) -> Result<usize, EmbedderError> {
+        let reported = match self.client.get(format!("{}/v1/models", url)).send().await {
+            Ok(response) if response.status().is_success() => {
+                response
+                    .json::<Value>().await
+                    .ok()
+                    .and_then(|models| models["data"][0]["meta"]["n_embd"].as_u64())
+            }
+            _ => None,
+        };
+        match reported {
+            Some(dimensions) => Ok(dimensions as usize),
+            None => {
+                warn!("Server at {} does not report n_embd, probing the embedding size", url);
+                let probe = self.request(url, &["dimensions"]).await?;
+                Ok(probe.first().map_or(0, |embedding| embedding.len()))
+            }
+        }
+    }
+
+    async fn request(&self, url: &str, texts: &[&str]) -> Result<Vec<Vec<f32>>, EmbedderError> {
+        let response = self.client
+            .post(format!("{}/embedding", url))
+            .json(&json!({ "content": texts }))
+            .send().await?;
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            return Err(
+                EmbedderError::EmbeddingGenerationFailed(
+                    format!("llama-server answered {}: {}", status, body)
+                )
+            );
+        }
+
+        let embeddings = parse_embeddings(&response.json::<Value>().await?)?;
+        if embeddings.len() != texts.len() {
+            return Err(
+                EmbedderError::EmbeddingGenerationFailed(
+                    format!("Expected {} embeddings, got {}", texts.len(), embeddings.len())
+                )
+            );
+        }
+        Ok(embeddings)
+    }
+
+    async fn embed(&self, texts: &[&str]) -> Result<Vec<Vec<f32>>, EmbedderError> {
+        let mut embeddings = Vec::with_capacity(texts.len());
+        for batch in texts.chunks(self.batch_size) {
+            let url = self.server_url().await?;
+            embeddings.extend(self.request(&url, batch).await?);
+        }
+        if self.normalize {
+            embeddings.iter_mut().for_each(|embedding| normalize(embedding));
+        }
+        Ok(embeddings)
+    }
+}
+
+/// Reads the response of `/embedding`: a list of `{index, embedding}` objects, or a single
+/// `{embedding}` object from older servers. Models without pooling return one vector per
+/// token, which are mean-pooled.
+fn parse_embeddings(json: &Value) -> Result<Vec<Vec<f32>>, EmbedderError> {
+    let mut entries: Vec<&Value> = match json {
+        Value::Array(entries) => entries.iter().collect(),
+        entry => vec![entry],
+    };
+    entries.sort_by_key(|entry| entry["index"].as_u64().unwrap_or(0));
+
+    entries
+        .into_iter()
+        .map(|entry| {
+            let failed = || {
+                EmbedderError::EmbeddingGenerationFailed(format!("No embedding in {}", entry))
+            };
+            let embedding = entry["embedding"].as_array().ok_or_else(failed)?;
+            let rows: Vec<Vec<f32>> = if embedding.iter().all(|row| row.is_array()) {
+                embedding.iter().map(to_vector).collect()
+            } else {
+                vec![to_vector(&entry["embedding"])]
+            };
+            mean(&rows).ok_or_else(failed)
+        })
+        .collect()
+}
+
+fn to_vector(json: &Value) -> Vec<f32> {
+    json.as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|value| value.as_f64())
+        .map(|value| value as f32)
+        .collect()
+}
+
+fn mean(rows: &[Vec<f32>]) -> Option<Vec<f32>> {
+    let first = rows.first()?;
+    let mut sum = vec![0.0; first.len()];
+    for row in rows {
+        sum.iter_mut()
+            .zip(row)
+            .for_each(|(total, value)| {
+                *total += value;
+            });
+    }
+    Some(
+        sum
+            .into_iter()
+            .map(|total| total / (rows.len() as f32))
+            .collect()
+    )
+}
+
+fn normalize(embedding: &mut [f32]) {
+    let norm = embedding
+        .iter()
+        .map(|value| value * value)
+        .sum::<f32>()
+        .sqrt();
+    if norm > 0.0 {
+        embedding.iter_mut().for_each(|value| {
+            *value /= norm;
+        });
+    }
+}
+
+#[async_trait]
+impl Embedder for LlamaServerEmbedder {
+    /// Loads the model and reads the embedding size from the server.
+    async fn initialize(&self) -> Result<(), EmbedderError> {
+        self.dimensions
+            .get_or_try_init(|| async {
+                let url = self.server_url().await?;
+                self.query_dimensions(&url).await
+            }).await
+            .map(|_| ())
+    }
+
+    async fn generate_embeddings_with_cache(
+        &self,
+        text: &[&str]
+    ) -> Result<Vec<Vec<f32>>, EmbedderError> {
+        self.embed(text).await
+    }
+
+    /// Same as `generate_embeddings_with_cache`: the model stays loaded in llama-server, and
+    /// is reloaded if it was unloaded in the meantime.
+    async fn generate_embeddings_on_demand(
+        &self,
+        text: &[&str]
+    ) -> Result<Vec<Vec<f32>>, EmbedderError> {
+        self.embed(text).await
+    }
+
+    /// Zero until `initialize` has read the size from the server.
+    fn dimensions(&self) -> i32 {
+        self.dimensions.get().map_or(0, |dimensions| *dimensions as i32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_current_and_legacy_responses() {
+        let current = json!([
+            { "index": 1, "embedding": [[0.0, 2.0]] },
+            { "index": 0, "embedding": [[1.0, 0.0], [3.0, 2.0]] }
+        ]);
+        assert_eq!(parse_embeddings(&current).unwrap(), vec![vec![2.0, 1.0], vec![0.0, 2.0]]);
+
+        let legacy = json!({ "embedding": [3.0, 4.0] });
+        let mut embeddings = parse_embeddings(&legacy).unwrap();
+        normalize(&mut embeddings[0]);
+        assert_eq!(embeddings, vec![vec![0.6, 0.8]]);
+
+        assert!(parse_embeddings(&json!({ "error": "no embeddings" })).is_err());
+    }
+}
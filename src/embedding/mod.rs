@@ -1,5 +1,8 @@
+#[cfg(feature = "rust-bert")]
 pub mod embedder;
+#[cfg(feature = "rust-bert")]
 pub mod embedder_builder;
 pub mod embedder_trait;
 pub mod embedding_models;
 pub mod error;
+pub mod llama_server;
@@ -0,0 +1,38 @@
+use thiserror::Error;
+
+use crate::embedding::error::EmbedderError;
+use crate::llm::error::LLMError;
+use crate::model::error::ModelError;
+
+/// Boxed error that can cross task and thread boundaries, for user-implemented extension
+/// points such as `Tool::run`.
+pub type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Any error pyano can return. Every module error converts into it, so applications can use
+/// `?` across LLM, model, embedding and vector store calls.
+#[derive(Error, Debug)]
+pub enum PyanoError {
+    #[error(transparent)] LLM(#[from] LLMError),
+
+    #[error(transparent)] Model(#[from] ModelError),
+
+    #[error(transparent)] Embedder(#[from] EmbedderError),
+
+    #[error("Invalid configuration: {0}")] Config(String),
+
+    #[error("Vector store error: {0}")] VectorStore(String),
+
+    #[error("Tool error: {0}")] Tool(String),
+
+    #[cfg(feature = "sqlite-vec")]
+    #[error("Database error: {0}")]
+    Database(#[from] sqlx::Error),
+
+    #[error("IO error: {0}")] Io(#[from] std::io::Error),
+
+    #[error("Serialization error: {0}")] Serialization(#[from] serde_json::Error),
+
+    #[error(transparent)] Other(#[from] BoxError),
+}
+
+pub type PyanoResult<T> = std::result::Result<T, PyanoError>;
@@ -1,4 +1,5 @@
 pub mod agent;
+pub mod error;
 pub mod model;
 pub mod types;
 pub mod llm;
@@ -8,4 +9,7 @@ pub use model::manager::ModelManager;
 pub mod embedding;
 pub mod vectorstore;
 pub mod schemas;
+#[cfg(any(test, feature = "test-support"))]
+pub mod testing;
 pub use types::*;
+pub use error::{ BoxError, PyanoError, PyanoResult };
@@ -0,0 +1,151 @@
+use std::collections::HashMap;
+use std::path::{ Path, PathBuf };
+use std::sync::Arc;
+
+use log::warn;
+use serde::{ Deserialize, Serialize };
+use sha2::{ Digest, Sha256 };
+use tokio::fs::{ File, OpenOptions };
+use tokio::io::AsyncWriteExt;
+
+use super::response::LLMResponse;
+use crate::error::BoxError;
+
+/// Outcome of one prompt of `LLM::batch`. A failed item does not fail the batch.
+pub type BatchResult = Result<LLMResponse, BoxError>;
+
+pub type ProgressCallback = Arc<dyn Fn(BatchProgress) + Send + Sync>;
+
+/// Where a batch stands, reported after every finished item.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BatchProgress {
+    pub total: usize,
+    pub completed: usize,
+    pub failed: usize,
+    /// Items taken from the results file of an earlier run
+    pub skipped: usize,
+}
+
+impl BatchProgress {
+    pub fn is_done(&self) -> bool {
+        self.completed + self.failed + self.skipped >= self.total
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct BatchOptions {
+    pub system_prompt: String,
+    /// Requests in flight at once, capped at the server's slot count; 0 uses the slot count
+    pub concurrency: usize,
+    pub progress: Option<ProgressCallback>,
+    /// JSON Lines file successful items are appended to as they finish. Items already in
+    /// the file for the same prompt are not sent again, so an interrupted batch resumes.
+    pub results_file: Option<PathBuf>,
+}
+
+impl BatchOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_system_prompt(mut self, system_prompt: &str) -> Self {
+        self.system_prompt = system_prompt.to_string();
+        self
+    }
+
+    pub fn with_concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency;
+        self
+    }
+
+    pub fn with_progress(
+        mut self,
+        progress: impl Fn(BatchProgress) + Send + Sync + 'static
+    ) -> Self {
+        self.progress = Some(Arc::new(progress));
+        self
+    }
+
+    pub fn with_results_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.results_file = Some(path.into());
+        self
+    }
+}
+
+/// One line of a results file.
+#[derive(Serialize, Deserialize)]
+struct BatchRecord {
+    index: usize,
+    prompt_hash: String,
+    response: LLMResponse,
+}
+
+/// Identifies a prompt in the results file, so a changed prompt list is not resumed from
+/// stale answers.
+pub(crate) fn prompt_hash(system_prompt: &str, prompt: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(system_prompt.as_bytes());
+    hasher.update([0]);
+    hasher.update(prompt.as_bytes());
+    format!("{:x}", hasher.finalize())
+}
+
+/// Responses recorded by an earlier run, by index, for the prompts they still match. A
+/// missing file is an empty one; unreadable lines, e.g. one cut short by a crash, are skipped.
+pub(crate) async fn load_results(
+    path: &Path,
+    hashes: &[String]
+) -> Result<HashMap<usize, LLMResponse>, BoxError> {
+    let contents = match tokio::fs::read_to_string(path).await {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return Ok(HashMap::new());
+        }
+        Err(e) => {
+            return Err(Box::new(e));
+        }
+    };
+
+    let mut results = HashMap::new();
+    for line in contents.lines().filter(|line| !line.trim().is_empty()) {
+        match serde_json::from_str::<BatchRecord>(line) {
+            Ok(record) if hashes.get(record.index) == Some(&record.prompt_hash) => {
+                results.insert(record.index, record.response);
+            }
+            Ok(record) => {
+                warn!("Ignoring result {}: its prompt has changed", record.index);
+            }
+            Err(e) => warn!("Ignoring unreadable line in {}: {}", path.display(), e),
+        }
+    }
+    Ok(results)
+}
+
+pub(crate) struct ResultsWriter {
+    file: File,
+}
+
+impl ResultsWriter {
+    pub(crate) async fn open(path: &Path) -> Result<Self, BoxError> {
+        let file = OpenOptions::new().create(true).append(true).open(path).await?;
+        Ok(Self { file })
+    }
+
+    pub(crate) async fn append(
+        &mut self,
+        index: usize,
+        prompt_hash: &str,
+        response: &LLMResponse
+    ) -> Result<(), BoxError> {
+        let record = BatchRecord {
+            index,
+            prompt_hash: prompt_hash.to_string(),
+            response: response.clone(),
+        };
+        let mut line = serde_json::to_string(&record)?;
+        line.push('\n');
+        self.file.write_all(line.as_bytes()).await?;
+        self.file.flush().await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,395 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::{ AtomicU64, Ordering };
+use std::time::{ Duration, SystemTime, UNIX_EPOCH };
+
+use futures::StreamExt;
+use log::warn;
+use parking_lot::Mutex;
+use rusqlite::{ params, Connection, OptionalExtension };
+use serde_json::Value;
+use sha2::{ Digest, Sha256 };
+
+use super::error::LLMError;
+use super::options::LLMHTTPCallOptions;
+use super::response::{ LLMEvent, LLMResponse, Timings, Usage };
+use super::types::EventStream;
+
+/// Where and for how long `ResponseCache` keeps responses.
+#[derive(Debug, Clone)]
+pub struct CacheConfig {
+    /// SQLite database file, `~/.pyano/cache/llm_responses.db` by default
+    pub path: PathBuf,
+    /// Entries older than this are treated as missing
+    pub ttl: Option<Duration>,
+    pub max_entries: Option<usize>,
+    /// Upper bound on the total size of the cached responses
+    pub max_bytes: Option<u64>,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        let root = dirs::home_dir().unwrap_or_default().join(".pyano");
+        Self {
+            path: root.join("cache").join("llm_responses.db"),
+            ttl: Some(Duration::from_secs(7 * 24 * 60 * 60)),
+            max_entries: None,
+            max_bytes: Some(256 * 1024 * 1024),
+        }
+    }
+}
+
+impl CacheConfig {
+    pub fn with_path(mut self, path: PathBuf) -> Self {
+        self.path = path;
+        self
+    }
+
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = Some(ttl);
+        self
+    }
+
+    pub fn with_max_entries(mut self, max_entries: usize) -> Self {
+        self.max_entries = Some(max_entries);
+        self
+    }
+
+    pub fn with_max_bytes(mut self, max_bytes: u64) -> Self {
+        self.max_bytes = Some(max_bytes);
+        self
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheMetrics {
+    pub hits: u64,
+    pub misses: u64,
+    pub stores: u64,
+    /// Entries dropped because they expired or to stay within the size limits
+    pub evictions: u64,
+}
+
+/// Persistent cache of LLM responses, for re-running the same chains during development
+/// without paying for generation again.
+///
+/// Only deterministic requests are cached: temperature 0 or a fixed seed. Entries are keyed
+/// by model, endpoint and the full request payload, which holds the rendered prompt and every
+/// sampling option.
+pub struct ResponseCache {
+    // Shared with the blocking tasks that run the queries
+    conn: Arc<Mutex<Connection>>,
+    config: Arc<CacheConfig>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    stores: AtomicU64,
+    evictions: AtomicU64,
+}
+
+impl ResponseCache {
+    pub fn open(config: CacheConfig) -> Result<Self, LLMError> {
+        if let Some(parent) = config.path.parent() {
+            std::fs::create_dir_all(parent).map_err(|e| LLMError::Cache(e.to_string()))?;
+        }
+        let conn = Connection::open(&config.path).map_err(|e| LLMError::Cache(e.to_string()))?;
+        Self::with_connection(conn, config)
+    }
+
+    /// A cache that lives as long as the process, for tests.
+    pub fn in_memory(config: CacheConfig) -> Result<Self, LLMError> {
+        let conn = Connection::open_in_memory().map_err(|e| LLMError::Cache(e.to_string()))?;
+        Self::with_connection(conn, config)
+    }
+
+    fn with_connection(conn: Connection, config: CacheConfig) -> Result<Self, LLMError> {
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS responses (
+                key TEXT PRIMARY KEY,
+                response TEXT NOT NULL,
+                size INTEGER NOT NULL,
+                created_at INTEGER NOT NULL,
+                accessed_at INTEGER NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS responses_accessed_at ON responses (accessed_at);"
+        ).map_err(|e| LLMError::Cache(e.to_string()))?;
+
+        Ok(Self {
+            conn: Arc::new(Mutex::new(conn)),
+            config: Arc::new(config),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            stores: AtomicU64::new(0),
+            evictions: AtomicU64::new(0),
+        })
+    }
+
+    /// Whether a request with these options always produces the same output.
+    pub fn is_cacheable(options: &LLMHTTPCallOptions) -> bool {
+        options.temperature == Some(0.0) || options.seed.is_some()
+    }
+
+    pub fn key(model: &str, endpoint: &str, payload: &Value) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(model.as_bytes());
+        hasher.update([0]);
+        hasher.update(endpoint.as_bytes());
+        hasher.update([0]);
+        hasher.update(payload.to_string().as_bytes());
+        format!("{:x}", hasher.finalize())
+    }
+
+    /// Looks up a response. Cache failures are logged and reported as misses, so a broken
+    /// cache never fails a generation.
+    pub async fn get(&self, key: &str) -> Option<LLMResponse> {
+        let key = key.to_string();
+        let (found, evicted) = self
+            .blocking(move |conn, config| lookup(conn, config, &key)).await
+            .unwrap_or_else(|e| {
+                warn!("Response cache lookup failed: {}", e);
+                (None, 0)
+            });
+        self.evictions.fetch_add(evicted, Ordering::Relaxed);
+        let counter = if found.is_some() { &self.hits } else { &self.misses };
+        counter.fetch_add(1, Ordering::Relaxed);
+        found
+    }
+
+    pub async fn put(&self, key: &str, response: &LLMResponse) {
+        let key = key.to_string();
+        let json = serde_json::to_string(response).unwrap_or_default();
+        match self.blocking(move |conn, config| store(conn, config, &key, &json)).await {
+            Ok(evicted) => {
+                self.stores.fetch_add(1, Ordering::Relaxed);
+                self.evictions.fetch_add(evicted, Ordering::Relaxed);
+            }
+            Err(e) => warn!("Response cache store failed: {}", e),
+        }
+    }
+
+    /// Runs `query` on a blocking task, so SQLite's disk access does not stall the runtime.
+    async fn blocking<T: Send + 'static>(
+        &self,
+        query: impl FnOnce(&Connection, &CacheConfig) -> rusqlite::Result<T> + Send + 'static
+    ) -> Result<T, LLMError> {
+        let conn = self.conn.clone();
+        let config = self.config.clone();
+        tokio::task
+            ::spawn_blocking(move || query(&conn.lock(), &config)).await
+            .map_err(|e| LLMError::Cache(e.to_string()))?
+            .map_err(|e| LLMError::Cache(e.to_string()))
+    }
+
+    pub fn metrics(&self) -> CacheMetrics {
+        CacheMetrics {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            stores: self.stores.load(Ordering::Relaxed),
+            evictions: self.evictions.load(Ordering::Relaxed),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.conn
+            .lock()
+            .query_row("SELECT COUNT(*) FROM responses", [], |row| row.get::<_, i64>(0))
+            .unwrap_or(0) as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn clear(&self) -> Result<(), LLMError> {
+        self.conn
+            .lock()
+            .execute("DELETE FROM responses", [])
+            .map_err(|e| LLMError::Cache(e.to_string()))?;
+        Ok(())
+    }
+
+}
+
+/// The response stored under `key` unless it expired, and the number of entries evicted.
+fn lookup(
+    conn: &Connection,
+    config: &CacheConfig,
+    key: &str
+) -> rusqlite::Result<(Option<LLMResponse>, u64)> {
+    let row: Option<(String, i64)> = conn
+        .query_row(
+            "SELECT response, created_at FROM responses WHERE key = ?1",
+            params![key],
+            |row| Ok((row.get(0)?, row.get(1)?))
+        )
+        .optional()?;
+    let Some((response, created_at)) = row else {
+        return Ok((None, 0));
+    };
+
+    let now = now_millis();
+    if let Some(ttl) = config.ttl {
+        if now - created_at > (ttl.as_millis() as i64) {
+            conn.execute("DELETE FROM responses WHERE key = ?1", params![key])?;
+            return Ok((None, 1));
+        }
+    }
+
+    conn.execute("UPDATE responses SET accessed_at = ?1 WHERE key = ?2", params![now, key])?;
+    Ok((serde_json::from_str(&response).ok(), 0))
+}
+
+/// Stores `json` under `key` and returns the number of entries evicted to make room.
+fn store(conn: &Connection, config: &CacheConfig, key: &str, json: &str) -> rusqlite::Result<u64> {
+    let now = now_millis();
+    conn.execute(
+        "INSERT OR REPLACE INTO responses (key, response, size, created_at, accessed_at)
+         VALUES (?1, ?2, ?3, ?4, ?4)",
+        params![key, json, json.len() as i64, now]
+    )?;
+
+    // Drop least recently used entries until the limits are met again
+    let mut evicted = 0;
+    loop {
+        let (count, size): (i64, i64) = conn.query_row(
+            "SELECT COUNT(*), COALESCE(SUM(size), 0) FROM responses",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?))
+        )?;
+        let too_many = config.max_entries.is_some_and(|max| (count as usize) > max);
+        let too_big = config.max_bytes.is_some_and(|max| (size as u64) > max);
+        if count == 0 || !(too_many || too_big) {
+            return Ok(evicted);
+        }
+
+        conn.execute(
+            "DELETE FROM responses WHERE key =
+             (SELECT key FROM responses ORDER BY accessed_at ASC LIMIT 1)",
+            []
+        )?;
+        evicted += 1;
+    }
+}
+
+/// Replays a cached response as events: its whole content as a single token, then its
+/// timings and stop reason.
+pub fn replay_events(response: LLMResponse) -> EventStream {
+    let mut events = vec![
+        Ok(LLMEvent::Token { content: response.content, logprobs: response.logprobs })
+    ];
+    if let Some(timings) = response.timings {
+        events.push(Ok(LLMEvent::Timings(timings)));
+    }
+    events.push(Ok(LLMEvent::Done { stop_reason: response.stop_reason }));
+    Box::pin(futures::stream::iter(events))
+}
+
+/// Passes `stream` through, storing the generation under `key` once it is done.
+pub fn record_events(stream: EventStream, cache: Arc<ResponseCache>, key: String) -> EventStream {
+    let mut content = String::new();
+    let mut logprobs = Vec::new();
+    let mut timings: Option<Timings> = None;
+    Box::pin(
+        stream.then(move |event| {
+            let mut finished = None;
+            match &event {
+                Ok(LLMEvent::Token { content: token, logprobs: token_logprobs }) => {
+                    content.push_str(token);
+                    logprobs.extend(token_logprobs.iter().cloned());
+                }
+                Ok(LLMEvent::Timings(t)) => {
+                    timings = Some(t.clone());
+                }
+                Ok(LLMEvent::Done { stop_reason }) => {
+                    let usage = timings.as_ref().map_or(Usage::default(), |t| Usage {
+                        prompt_tokens: t.prompt_n as usize,
+                        completion_tokens: t.predicted_n as usize,
+                        cached_tokens: 0,
+                    });
+                    let response = LLMResponse {
+                        content: std::mem::take(&mut content),
+                        stop_reason: stop_reason.clone(),
+                        usage,
+                        timings: timings.take(),
+                        model: None,
+                        logprobs: std::mem::take(&mut logprobs),
+                        raw: Value::Null,
+                    };
+                    finished = Some(response);
+                }
+                _ => {}
+            }
+
+            // Stored before `Done` is passed on, so the entry exists once the stream ends
+            let cache = cache.clone();
+            let key = key.clone();
+            async move {
+                if let Some(response) = finished {
+                    cache.put(&key, &response).await;
+                }
+                event
+            }
+        })
+    )
+}
+
+fn now_millis() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as i64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::llm::response::StopReason;
+    use serde_json::json;
+
+    fn response(content: &str) -> LLMResponse {
+        LLMResponse {
+            content: content.to_string(),
+            stop_reason: StopReason::Eos,
+            usage: Usage::default(),
+            timings: None,
+            model: None,
+            logprobs: Vec::new(),
+            raw: json!({}),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_round_trip_and_metrics() {
+        let cache = ResponseCache::in_memory(CacheConfig::default()).unwrap();
+        let key = ResponseCache::key("qwen", "/completion", &json!({ "prompt": "Hi" }));
+        assert_ne!(key, ResponseCache::key("llama", "/completion", &json!({ "prompt": "Hi" })));
+
+        assert!(cache.get(&key).await.is_none());
+        cache.put(&key, &response("Hello")).await;
+        assert_eq!(cache.get(&key).await.unwrap().content, "Hello");
+        assert_eq!(cache.metrics(), CacheMetrics { hits: 1, misses: 1, stores: 1, evictions: 0 });
+    }
+
+    #[tokio::test]
+    async fn test_ttl_and_size_limits() {
+        let expiring = ResponseCache::in_memory(
+            CacheConfig::default().with_ttl(Duration::ZERO)
+        ).unwrap();
+        expiring.put("a", &response("old")).await;
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        assert!(expiring.get("a").await.is_none());
+        assert!(expiring.is_empty());
+
+        let small = ResponseCache::in_memory(CacheConfig::default().with_max_entries(2)).unwrap();
+        small.put("a", &response("1")).await;
+        tokio::time::sleep(Duration::from_millis(2)).await;
+        small.put("b", &response("2")).await;
+        tokio::time::sleep(Duration::from_millis(2)).await;
+        small.get("a").await;
+        small.put("c", &response("3")).await;
+
+        // "b" was the least recently used
+        assert_eq!(small.len(), 2);
+        assert!(small.get("b").await.is_none());
+        assert_eq!(small.metrics().evictions, 1);
+    }
+}
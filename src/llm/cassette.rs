@@ -0,0 +1,362 @@
+use std::collections::HashMap;
+use std::path::{ Path, PathBuf };
+use std::str::FromStr;
+use std::sync::{ Arc, OnceLock };
+
+use bytes::Bytes;
+use futures::StreamExt;
+use log::warn;
+use parking_lot::Mutex;
+use serde::{ Deserialize, Serialize };
+use serde_json::Value;
+
+use super::error::LLMError;
+use super::types::AccumulatedStream;
+
+/// Path of a cassette attached to every `LLM` built without one.
+pub const CASSETTE_ENV: &str = "PYANO_CASSETTE";
+/// `record` or `replay`, overriding the mode of every cassette.
+pub const CASSETTE_MODE_ENV: &str = "PYANO_CASSETTE_MODE";
+
+/// Values longer than this are shortened in mismatch reports.
+const DIFF_VALUE_CHARS: usize = 80;
+
+/// Cassettes being recorded, by path, so every `LLM` recording to a file shares one.
+static RECORDING: OnceLock<Mutex<HashMap<PathBuf, Arc<Cassette>>>> = OnceLock::new();
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CassetteMode {
+    /// Send requests to the server and write every exchange to the cassette
+    Record,
+    /// Answer from the cassette without contacting any server
+    Replay,
+}
+
+impl FromStr for CassetteMode {
+    type Err = LLMError;
+
+    fn from_str(mode: &str) -> Result<Self, Self::Err> {
+        match mode.trim().to_lowercase().as_str() {
+            "record" => Ok(CassetteMode::Record),
+            "replay" => Ok(CassetteMode::Replay),
+            other => Err(LLMError::Cassette(format!("Unknown cassette mode {:?}", other))),
+        }
+    }
+}
+
+/// What the server answered: a JSON body, or the chunks of a stream.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RecordedResponse {
+    Body(Value),
+    Stream(Vec<String>),
+}
+
+/// One request sent to the server and its response.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Interaction {
+    pub endpoint: String,
+    pub request: Value,
+    #[serde(flatten)]
+    pub response: RecordedResponse,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct CassetteFile {
+    interactions: Vec<Interaction>,
+}
+
+/// Records generation requests and their responses to a JSON file, and serves them back
+/// later so chains can be tested deterministically without a model.
+///
+/// In replay mode a request is answered by the first recorded interaction with the same
+/// endpoint and body that has not been replayed yet, falling back to one that has. A request
+/// matching none fails with `LLMError::CassetteMismatch`, listing how it differs from the
+/// closest recording. Tokenization and slot requests are not recorded. All `LLM`s recording
+/// to one file in a process write to the same cassette.
+///
+/// # Usage
+/// ```rust,ignore
+/// // Record once against a running server, then replay in CI
+/// let llm = LLM::builder()
+///     .with_options(options)
+///     .with_cassette("tests/cassettes/summarize.json", CassetteMode::Replay)
+///     .build()?;
+/// ```
+/// Setting `PYANO_CASSETTE_MODE=record` re-records every cassette.
+pub struct Cassette {
+    path: PathBuf,
+    mode: CassetteMode,
+    interactions: Mutex<Vec<Interaction>>,
+    replayed: Mutex<Vec<bool>>,
+}
+
+impl Cassette {
+    /// Opens the cassette at `path`, in the mode set by `PYANO_CASSETTE_MODE` if any. A
+    /// cassette being recorded starts empty; one being replayed must exist.
+    pub fn open(path: impl Into<PathBuf>, mode: CassetteMode) -> Result<Self, LLMError> {
+        let path = path.into();
+        let mode = Self::mode_from_env(mode)?;
+
+        let interactions = match mode {
+            CassetteMode::Record => Vec::new(),
+            CassetteMode::Replay => {
+                let contents = std::fs::read_to_string(&path).map_err(|e| {
+                    LLMError::Cassette(format!("Cannot read {}: {}", path.display(), e))
+                })?;
+                serde_json::from_str::<CassetteFile>(&contents)
+                    .map_err(|e| {
+                        LLMError::Cassette(format!("Cannot parse {}: {}", path.display(), e))
+                    })?.interactions
+            }
+        };
+        Ok(Self {
+            path,
+            mode,
+            replayed: Mutex::new(vec![false; interactions.len()]),
+            interactions: Mutex::new(interactions),
+        })
+    }
+
+    /// Like `open`, but a cassette being recorded is opened once per process: every `LLM`
+    /// recording to `path` appends to the same cassette instead of overwriting the others'
+    /// interactions.
+    pub fn shared(path: impl Into<PathBuf>, mode: CassetteMode) -> Result<Arc<Self>, LLMError> {
+        let path = path.into();
+        if Self::mode_from_env(mode)? == CassetteMode::Replay {
+            return Ok(Arc::new(Self::open(path, mode)?));
+        }
+
+        let key = std::path::absolute(&path).unwrap_or_else(|_| path.clone());
+        let mut recording = RECORDING.get_or_init(Default::default).lock();
+        if let Some(cassette) = recording.get(&key) {
+            return Ok(cassette.clone());
+        }
+        let cassette = Arc::new(Self::open(path, mode)?);
+        recording.insert(key, cassette.clone());
+        Ok(cassette)
+    }
+
+    /// The cassette named by `PYANO_CASSETTE`, replayed unless `PYANO_CASSETTE_MODE` says
+    /// otherwise.
+    pub fn from_env() -> Result<Option<Arc<Self>>, LLMError> {
+        match std::env::var(CASSETTE_ENV) {
+            Ok(path) if !path.trim().is_empty() => {
+                Ok(Some(Self::shared(path, CassetteMode::Replay)?))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    fn mode_from_env(mode: CassetteMode) -> Result<CassetteMode, LLMError> {
+        match std::env::var(CASSETTE_MODE_ENV) {
+            Ok(mode) if !mode.trim().is_empty() => mode.parse(),
+            _ => Ok(mode),
+        }
+    }
+
+    pub fn mode(&self) -> CassetteMode {
+        self.mode
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn interactions(&self) -> Vec<Interaction> {
+        self.interactions.lock().clone()
+    }
+
+    /// The recorded response to `request`.
+    pub(crate) fn replay(
+        &self,
+        endpoint: &str,
+        request: &Value
+    ) -> Result<RecordedResponse, LLMError> {
+        let interactions = self.interactions.lock();
+        let mut replayed = self.replayed.lock();
+
+        let matching: Vec<usize> = interactions
+            .iter()
+            .enumerate()
+            .filter(|(_, i)| i.endpoint == endpoint && &i.request == request)
+            .map(|(index, _)| index)
+            .collect();
+        let chosen = matching
+            .iter()
+            .find(|index| !replayed[**index])
+            .or(matching.last());
+        match chosen {
+            Some(&index) => {
+                replayed[index] = true;
+                Ok(interactions[index].response.clone())
+            }
+            None => Err(self.mismatch(&interactions, &replayed, endpoint, request)),
+        }
+    }
+
+    /// Describes how `request` differs from the closest interaction not replayed yet.
+    fn mismatch(
+        &self,
+        interactions: &[Interaction],
+        replayed: &[bool],
+        endpoint: &str,
+        request: &Value
+    ) -> LLMError {
+        let closest = interactions
+            .iter()
+            .zip(replayed)
+            .filter(|(interaction, _)| interaction.endpoint == endpoint)
+            .map(|(interaction, replayed)| {
+                let mut differences = Vec::new();
+                json_diff("", &interaction.request, request, &mut differences);
+                (differences, *replayed)
+            })
+            .min_by_key(|(differences, replayed)| (*replayed, differences.len()));
+
+        let mut message = format!(
+            "No interaction in {} matches the request to {}",
+            self.path.display(),
+            endpoint
+        );
+        match closest {
+            Some((differences, _)) => {
+                message.push_str("; the closest recording differs at:");
+                for difference in differences {
+                    message.push_str("\n  ");
+                    message.push_str(&difference);
+                }
+            }
+            None => message.push_str("; nothing was recorded for this endpoint"),
+        }
+        LLMError::CassetteMismatch(message)
+    }
+
+    pub(crate) fn record(&self, endpoint: &str, request: &Value, response: RecordedResponse) {
+        let interaction = Interaction {
+            endpoint: endpoint.to_string(),
+            request: request.clone(),
+            response,
+        };
+        let mut interactions = self.interactions.lock();
+        interactions.push(interaction);
+        if let Err(e) = self.save(&interactions) {
+            warn!("Cannot write cassette {}: {}", self.path.display(), e);
+        }
+    }
+
+    fn save(&self, interactions: &[Interaction]) -> Result<(), Box<dyn std::error::Error>> {
+        if let Some(parent) = self.path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+            std::fs::create_dir_all(parent)?;
+        }
+        let file = CassetteFile { interactions: interactions.to_vec() };
+        std::fs::write(&self.path, serde_json::to_string_pretty(&file)?)?;
+        Ok(())
+    }
+}
+
+/// Serves recorded chunks as a stream.
+pub(crate) fn replay_stream(chunks: Vec<String>) -> AccumulatedStream {
+    Box::pin(futures::stream::iter(chunks.into_iter().map(|chunk| Ok(Bytes::from(chunk)))))
+}
+
+/// Passes `stream` through, recording its chunks once it has ended. A chunk that ends in
+/// the middle of a UTF-8 character is joined with the next one.
+pub(crate) fn record_stream(
+    cassette: Arc<Cassette>,
+    endpoint: &str,
+    request: &Value,
+    stream: AccumulatedStream
+) -> AccumulatedStream {
+    let chunks = Arc::new(Mutex::new((Vec::<String>::new(), Vec::<u8>::new())));
+    let recorder = chunks.clone();
+    let endpoint = endpoint.to_string();
+    let request = request.clone();
+
+    let finish = futures::stream
+        ::once(async move {
+            let (mut chunks, pending) = std::mem::take(&mut *recorder.lock());
+            if !pending.is_empty() {
+                chunks.push(String::from_utf8_lossy(&pending).into_owned());
+            }
+            cassette.record(&endpoint, &request, RecordedResponse::Stream(chunks));
+        })
+        .filter_map(|_| async { None });
+
+    Box::pin(
+        stream
+            .map(move |chunk| {
+                if let Ok(bytes) = &chunk {
+                    let (chunks, pending) = &mut *chunks.lock();
+                    pending.extend_from_slice(bytes);
+                    if let Ok(text) = std::str::from_utf8(pending) {
+                        chunks.push(text.to_string());
+                        pending.clear();
+                    }
+                }
+                chunk
+            })
+            .chain(finish)
+    )
+}
+
+/// Lists the JSON paths where `recorded` and `requested` differ.
+fn json_diff(path: &str, recorded: &Value, requested: &Value, differences: &mut Vec<String>) {
+    match (recorded, requested) {
+        (Value::Object(a), Value::Object(b)) => {
+            let mut keys: Vec<&String> = a.keys().chain(b.keys()).collect();
+            keys.sort();
+            keys.dedup();
+            for key in keys {
+                let path = if path.is_empty() { key.clone() } else { format!("{}.{}", path, key) };
+                json_diff(
+                    &path,
+                    a.get(key).unwrap_or(&Value::Null),
+                    b.get(key).unwrap_or(&Value::Null),
+                    differences
+                );
+            }
+        }
+        (Value::Array(a), Value::Array(b)) if a.len() == b.len() => {
+            for (index, (a, b)) in a.iter().zip(b).enumerate() {
+                json_diff(&format!("{}[{}]", path, index), a, b, differences);
+            }
+        }
+        (a, b) if a != b => {
+            let difference = format!("{}: recorded {}, requested {}", path, shorten(a), shorten(b));
+            differences.push(difference);
+        }
+        _ => {}
+    }
+}
+
+fn shorten(value: &Value) -> String {
+    let text = value.to_string();
+    if text.chars().count() <= DIFF_VALUE_CHARS {
+        return text;
+    }
+    let short: String = text.chars().take(DIFF_VALUE_CHARS).collect();
+    format!("{}...", short)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_diff_lists_changed_paths() {
+        let mut differences = Vec::new();
+        json_diff(
+            "",
+            &json!({ "prompt": "Hi", "stop": ["a"], "temperature": 0.4 }),
+            &json!({ "prompt": "Hello", "stop": ["a"], "seed": 1 }),
+            &mut differences
+        );
+        assert_eq!(differences, vec![
+            "prompt: recorded \"Hi\", requested \"Hello\"",
+            "seed: recorded null, requested 1",
+            "temperature: recorded 0.4, requested null"
+        ]);
+    }
+}
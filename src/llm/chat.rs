@@ -0,0 +1,379 @@
+use minijinja::{ context, Environment, Error, ErrorKind };
+use serde::{ Deserialize, Serialize };
+use serde_json::Value;
+
+use super::error::LLMError;
+use super::image::ImageInput;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChatRole {
+    System,
+    User,
+    Assistant,
+    Tool,
+}
+
+/// One turn of a conversation passed to `LLM::chat`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChatMessage {
+    pub role: ChatRole,
+    pub content: String,
+    /// Name of the tool that produced a `Tool` message
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// Id of the tool call a `Tool` message answers, for APIs that match results to calls
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_call_id: Option<String>,
+    /// Images for multimodal models, usually on a `User` message
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub images: Vec<ImageInput>,
+}
+
+impl ChatMessage {
+    pub fn new(role: ChatRole, content: impl Into<String>) -> Self {
+        Self { role, content: content.into(), name: None, tool_call_id: None, images: Vec::new() }
+    }
+
+    pub fn with_image(mut self, image: ImageInput) -> Self {
+        self.images.push(image);
+        self
+    }
+
+    pub fn system(content: impl Into<String>) -> Self {
+        Self::new(ChatRole::System, content)
+    }
+
+    pub fn user(content: impl Into<String>) -> Self {
+        Self::new(ChatRole::User, content)
+    }
+
+    pub fn assistant(content: impl Into<String>) -> Self {
+        Self::new(ChatRole::Assistant, content)
+    }
+
+    /// The result of calling tool `name`.
+    pub fn tool(name: impl Into<String>, content: impl Into<String>) -> Self {
+        Self {
+            name: Some(name.into()),
+            ..Self::new(ChatRole::Tool, content)
+        }
+    }
+
+    pub fn with_tool_call_id(mut self, id: impl Into<String>) -> Self {
+        self.tool_call_id = Some(id.into());
+        self
+    }
+}
+
+/// Turns a list of `ChatMessage`s into a single prompt for a specific model family.
+///
+/// `Jinja` templates are Hugging Face / GGUF `tokenizer.chat_template`s, used verbatim.
+/// `Roles` templates are a simpler per-role format for models without one.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChatTemplate {
+    Roles(RoleTemplate),
+    Jinja(JinjaChatTemplate),
+}
+
+impl ChatTemplate {
+    /// A Jinja chat template, e.g. the `tokenizer.chat_template` of a GGUF file.
+    pub fn jinja(source: &str, bos_token: &str, eos_token: &str) -> Self {
+        ChatTemplate::Jinja(JinjaChatTemplate {
+            source: source.to_string(),
+            bos_token: bos_token.to_string(),
+            eos_token: eos_token.to_string(),
+        })
+    }
+
+    /// Llama 3 Instruct.
+    pub fn llama3() -> Self {
+        Self::jinja(include_str!("chat_templates/llama3.jinja"), "<|begin_of_text|>", "<|eot_id|>")
+    }
+
+    /// ChatML as used by Qwen 2 / 2.5, with tool calling.
+    pub fn chatml() -> Self {
+        Self::jinja(include_str!("chat_templates/qwen2.jinja"), "", "<|im_end|>")
+    }
+
+    /// IBM Granite 3, with tool calling.
+    pub fn granite() -> Self {
+        Self::jinja(include_str!("chat_templates/granite.jinja"), "", "<|end_of_text|>")
+    }
+
+    /// Mistral Instruct; a leading system message is merged into the first user turn.
+    pub fn mistral() -> Self {
+        Self::jinja(include_str!("chat_templates/mistral.jinja"), "<s>", "</s>")
+    }
+
+    pub fn plain() -> Self {
+        ChatTemplate::Roles(RoleTemplate::plain())
+    }
+
+    pub fn render(&self, messages: &[ChatMessage]) -> Result<String, LLMError> {
+        self.render_with_tools(messages, &[])
+    }
+
+    /// Renders the conversation and offers `tools` (OpenAI-style function definitions) to
+    /// the model. `Roles` templates have no place for tool definitions and ignore them.
+    pub fn render_with_tools(
+        &self,
+        messages: &[ChatMessage],
+        tools: &[Value]
+    ) -> Result<String, LLMError> {
+        match self {
+            ChatTemplate::Roles(template) => Ok(template.render(messages)),
+            ChatTemplate::Jinja(template) => template.render(messages, tools),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JinjaChatTemplate {
+    pub source: String,
+    #[serde(default)]
+    pub bos_token: String,
+    #[serde(default)]
+    pub eos_token: String,
+}
+
+impl JinjaChatTemplate {
+    /// Renders with the same settings as `transformers`: blocks are trimmed, Python string
+    /// methods are available and the template may call `raise_exception`.
+    pub fn render(&self, messages: &[ChatMessage], tools: &[Value]) -> Result<String, LLMError> {
+        let mut env = Environment::new();
+        env.set_trim_blocks(true);
+        env.set_lstrip_blocks(true);
+        env.set_unknown_method_callback(minijinja_contrib::pycompat::unknown_method_callback);
+        env.add_function("raise_exception", |message: String| -> Result<String, Error> {
+            Err(Error::new(ErrorKind::InvalidOperation, message))
+        });
+
+        let template = env
+            .template_from_str(&self.source)
+            .map_err(|e| LLMError::InvalidPrompt(format!("Invalid chat template: {}", e)))?;
+
+        template
+            .render(
+                context! {
+                    messages => messages,
+                    tools => tools,
+                    add_generation_prompt => true,
+                    bos_token => self.bos_token,
+                    eos_token => self.eos_token,
+                }
+            )
+            .map_err(|e| LLMError::InvalidPrompt(format!("Failed to render chat template: {}", e)))
+    }
+}
+
+/// A chat template built from one turn format per role, each with a `{content}` placeholder
+/// (tool turns may also use `{name}`). The rendered prompt is `prefix`, every turn in order,
+/// then `generation_prompt` to cue the assistant's reply.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RoleTemplate {
+    pub prefix: String,
+    pub system: String,
+    pub user: String,
+    pub assistant: String,
+    pub tool: String,
+    pub generation_prompt: String,
+    /// For models without a system role: system messages are prepended to the next user turn
+    #[serde(default)]
+    pub system_in_user: bool,
+}
+
+impl RoleTemplate {
+    /// Role-prefixed plain text, for models without a special chat format.
+    pub fn plain() -> Self {
+        Self {
+            prefix: String::new(),
+            system: "{content}\n\n".to_string(),
+            user: "User: {content}\n".to_string(),
+            assistant: "Assistant: {content}\n".to_string(),
+            tool: "Tool ({name}): {content}\n".to_string(),
+            generation_prompt: "Assistant: ".to_string(),
+            system_in_user: false,
+        }
+    }
+
+    pub fn render(&self, messages: &[ChatMessage]) -> String {
+        let mut prompt = self.prefix.clone();
+        let mut pending_system: Vec<&str> = Vec::new();
+
+        for message in messages {
+            let (turn, content) = match message.role {
+                ChatRole::System if self.system_in_user => {
+                    pending_system.push(&message.content);
+                    continue;
+                }
+                ChatRole::System => (&self.system, message.content.clone()),
+                ChatRole::User if !pending_system.is_empty() => {
+                    let content = format!("{}\n\n{}", pending_system.join("\n\n"), message.content);
+                    pending_system.clear();
+                    (&self.user, content)
+                }
+                ChatRole::User => (&self.user, message.content.clone()),
+                ChatRole::Assistant => (&self.assistant, message.content.clone()),
+                ChatRole::Tool => (&self.tool, message.content.clone()),
+            };
+
+            // `{name}` first, so placeholders inside the content are left alone
+            let name = message.name.as_deref().unwrap_or("tool");
+            prompt.push_str(&turn.replace("{name}", name).replace("{content}", &content));
+        }
+
+        // A system prompt with no user turn after it still has to reach the model
+        if !pending_system.is_empty() {
+            prompt.push_str(&self.user.replace("{content}", &pending_system.join("\n\n")));
+        }
+
+        prompt.push_str(&self.generation_prompt);
+        prompt
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::llm::llm_builder::LLM;
+    use crate::llm::options::LLMHTTPCallOptions;
+    use crate::testing::{ FakeCompletion, FakeLlamaServer };
+    use serde_json::json;
+
+    fn conversation() -> Vec<ChatMessage> {
+        vec![
+            ChatMessage::system("You are terse."),
+            ChatMessage::user("Weather in Paris?"),
+            ChatMessage::assistant("Let me check."),
+            ChatMessage::user("So?")
+        ]
+    }
+
+    fn tool_conversation() -> Vec<ChatMessage> {
+        vec![
+            ChatMessage::system("You are terse."),
+            ChatMessage::user("Weather in Paris?"),
+            ChatMessage::tool("weather", "{\"temp\": 21}")
+        ]
+    }
+
+    fn weather_tool() -> Value {
+        json!({
+            "type": "function",
+            "function": {
+                "name": "weather",
+                "description": "Current weather for a city",
+                "parameters": { "type": "object", "properties": { "city": { "type": "string" } } }
+            }
+        })
+    }
+
+    #[test]
+    fn test_llama3_golden() {
+        assert_eq!(
+            ChatTemplate::llama3().render(&conversation()).unwrap(),
+            "<|begin_of_text|><|start_header_id|>system<|end_header_id|>\n\nYou are terse.<|eot_id|>\
+             <|start_header_id|>user<|end_header_id|>\n\nWeather in Paris?<|eot_id|>\
+             <|start_header_id|>assistant<|end_header_id|>\n\nLet me check.<|eot_id|>\
+             <|start_header_id|>user<|end_header_id|>\n\nSo?<|eot_id|>\
+             <|start_header_id|>assistant<|end_header_id|>\n\n"
+        );
+    }
+
+    #[test]
+    fn test_chatml_golden() {
+        assert_eq!(
+            ChatTemplate::chatml().render(&tool_conversation()).unwrap(),
+            "<|im_start|>system\nYou are terse.<|im_end|>\n\
+             <|im_start|>user\nWeather in Paris?<|im_end|>\n\
+             <|im_start|>user\n<tool_response>\n{\"temp\": 21}\n</tool_response><|im_end|>\n\
+             <|im_start|>assistant\n"
+        );
+
+        // Without a system message Qwen adds its own
+        let prompt = ChatTemplate::chatml().render(&[ChatMessage::user("Hi")]).unwrap();
+        assert!(prompt.starts_with("<|im_start|>system\nYou are Qwen"));
+    }
+
+    #[test]
+    fn test_chatml_tools_golden() {
+        assert_eq!(
+            ChatTemplate::chatml()
+                .render_with_tools(&[ChatMessage::user("Weather in Paris?")], &[weather_tool()])
+                .unwrap(),
+            "<|im_start|>system\nYou are Qwen, created by Alibaba Cloud. You are a helpful assistant.\n\n\
+             # Tools\n\nYou may call one or more functions to assist with the user query.\n\n\
+             You are provided with function signatures within <tools></tools> XML tags:\n<tools>\n\
+             {\"function\":{\"description\":\"Current weather for a city\",\"name\":\"weather\",\
+             \"parameters\":{\"properties\":{\"city\":{\"type\":\"string\"}},\"type\":\"object\"}},\
+             \"type\":\"function\"}\n</tools>\n\n\
+             For each function call, return a json object with function name and arguments within \
+             <tool_call></tool_call> XML tags:\n<tool_call>\n\
+             {\"name\": <function-name>, \"arguments\": <args-json-object>}\n</tool_call><|im_end|>\n\
+             <|im_start|>user\nWeather in Paris?<|im_end|>\n\
+             <|im_start|>assistant\n"
+        );
+    }
+
+    #[test]
+    fn test_granite_golden() {
+        assert_eq!(
+            ChatTemplate::granite().render(&tool_conversation()).unwrap(),
+            "<|start_of_role|>system<|end_of_role|>You are terse.<|end_of_text|>\n\
+             <|start_of_role|>user<|end_of_role|>Weather in Paris?<|end_of_text|>\n\
+             <|start_of_role|>tool_response<|end_of_role|>{\"temp\": 21}<|end_of_text|>\n\
+             <|start_of_role|>assistant<|end_of_role|>"
+        );
+
+        let prompt = ChatTemplate::granite()
+            .render_with_tools(&[ChatMessage::user("Hi")], &[weather_tool()])
+            .unwrap();
+        assert!(prompt.starts_with("<|start_of_role|>available_tools<|end_of_role|>\n{\n"));
+        assert!(
+            prompt.ends_with(
+                "<|start_of_role|>user<|end_of_role|>Hi<|end_of_text|>\n<|start_of_role|>assistant<|end_of_role|>"
+            )
+        );
+    }
+
+    #[test]
+    fn test_mistral_golden() {
+        assert_eq!(
+            ChatTemplate::mistral().render(&conversation()).unwrap(),
+            "<s> [INST] You are terse.\n\nWeather in Paris? [/INST] Let me check.</s> [INST] So? [/INST]"
+        );
+
+        // The template rejects roles it does not know
+        assert!(ChatTemplate::mistral().render(&tool_conversation()).is_err());
+    }
+
+    #[test]
+    fn test_role_template() {
+        let messages = vec![ChatMessage::system("Be brief."), ChatMessage::tool("clock", "noon")];
+        assert_eq!(
+            ChatTemplate::plain().render(&messages).unwrap(),
+            "Be brief.\n\nTool (clock): noon\nAssistant: "
+        );
+    }
+
+    #[tokio::test]
+    async fn test_llm_chat_uses_chat_template() {
+        let server = FakeLlamaServer::start().await;
+        server.push_completion(FakeCompletion::text("Sunny"));
+
+        let options = LLMHTTPCallOptions::new()
+            .with_server_url(server.url())
+            .with_prompt_template("{system_prompt}\n{user_prompt}".to_string())
+            .with_chat_template(ChatTemplate::granite())
+            .build().unwrap();
+        let llm = LLM::builder().with_options(options).build().unwrap();
+
+        let response = llm.chat(&tool_conversation()).await.unwrap();
+
+        assert_eq!(response.content, "Sunny");
+        let prompt = server.last_request().unwrap()["prompt"].as_str().unwrap().to_string();
+        assert_eq!(prompt, ChatTemplate::granite().render(&tool_conversation()).unwrap());
+    }
+}
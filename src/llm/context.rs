@@ -0,0 +1,251 @@
+use async_trait::async_trait;
+use log::warn;
+
+use super::chat::{ ChatMessage, ChatRole };
+use super::error::LLMError;
+
+/// Tokens reserved for the answer when the options do not set `max_tokens`.
+pub const DEFAULT_OUTPUT_RESERVE: usize = 512;
+
+/// Tokens added per message or prompt part by role markers and template text.
+pub(crate) const PART_OVERHEAD_TOKENS: usize = 4;
+
+/// Marks where text was cut out by `TruncationStrategy::TrimMiddle`.
+const TRIM_MARKER: &str = "\n...\n";
+
+/// Rough token count for when the server cannot tokenize: BPE tokenizers average about four
+/// characters per token on English text.
+pub fn estimate_tokens(text: &str) -> usize {
+    text.chars().count().div_ceil(4)
+}
+
+#[async_trait]
+pub trait TokenCounter: Send + Sync {
+    async fn count_tokens(&self, text: &str) -> usize;
+}
+
+/// Counts with `estimate_tokens`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct EstimatingCounter;
+
+#[async_trait]
+impl TokenCounter for EstimatingCounter {
+    async fn count_tokens(&self, text: &str) -> usize {
+        estimate_tokens(text)
+    }
+}
+
+/// What to do when a prompt does not fit the context window.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TruncationStrategy {
+    /// Drop the oldest conversation turns; for a plain prompt, its leading paragraphs
+    DropOldestTurns,
+    /// Cut text out of the middle, keeping the beginning and the end
+    #[default]
+    TrimMiddle,
+    /// Fail with `LLMError::ContextOverflow`
+    Fail,
+}
+
+/// The part of a model's context window that is left for the prompt once room for the
+/// answer has been reserved.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ContextBudget {
+    pub context_size: usize,
+    pub reserved_for_output: usize,
+}
+
+impl ContextBudget {
+    pub fn new(context_size: usize, reserved_for_output: usize) -> Self {
+        Self { context_size, reserved_for_output }
+    }
+
+    /// Tokens available to the prompt.
+    pub fn available(&self) -> usize {
+        self.context_size.saturating_sub(self.reserved_for_output)
+    }
+
+    fn overflow(&self, needed: usize) -> LLMError {
+        LLMError::ContextOverflow { needed, available: self.available() }
+    }
+
+    /// Fits a system and user prompt into the budget by shortening the user prompt, which
+    /// is returned. The system prompt is never shortened.
+    pub async fn fit_prompt(
+        &self,
+        counter: &dyn TokenCounter,
+        system_prompt: &str,
+        user_prompt: &str,
+        strategy: TruncationStrategy
+    ) -> Result<String, LLMError> {
+        let fixed = counter.count_tokens(system_prompt).await + 2 * PART_OVERHEAD_TOKENS;
+        let needed = fixed + counter.count_tokens(user_prompt).await;
+        if needed <= self.available() {
+            return Ok(user_prompt.to_string());
+        }
+
+        warn!(
+            "Prompt needs {} tokens but only {} are available, applying {:?}",
+            needed,
+            self.available(),
+            strategy
+        );
+        let target = self.available().checked_sub(fixed).ok_or_else(|| self.overflow(needed))?;
+        let fitted = match strategy {
+            TruncationStrategy::Fail => None,
+            TruncationStrategy::TrimMiddle => trim_middle(counter, user_prompt, target).await,
+            TruncationStrategy::DropOldestTurns => {
+                drop_leading_paragraphs(counter, user_prompt, target).await
+            }
+        };
+        fitted.ok_or_else(|| self.overflow(needed))
+    }
+
+    /// Fits a conversation into the budget. System messages and the last message are kept;
+    /// `TrimMiddle` shortens the longest of the other messages, or the last message if needed.
+    pub async fn fit_messages(
+        &self,
+        counter: &dyn TokenCounter,
+        messages: &[ChatMessage],
+        strategy: TruncationStrategy
+    ) -> Result<Vec<ChatMessage>, LLMError> {
+        let mut counts = Vec::with_capacity(messages.len());
+        for message in messages {
+            counts.push(counter.count_tokens(&message.content).await + PART_OVERHEAD_TOKENS);
+        }
+        let needed: usize = counts.iter().sum();
+        if needed <= self.available() {
+            return Ok(messages.to_vec());
+        }
+
+        warn!(
+            "Conversation needs {} tokens but only {} are available, applying {:?}",
+            needed,
+            self.available(),
+            strategy
+        );
+        let mut messages = messages.to_vec();
+        let mut total = needed;
+        match strategy {
+            TruncationStrategy::Fail => {
+                return Err(self.overflow(needed));
+            }
+            TruncationStrategy::DropOldestTurns => {
+                let mut index = 0;
+                while total > self.available() && index + 1 < messages.len() {
+                    if messages[index].role == ChatRole::System {
+                        index += 1;
+                        continue;
+                    }
+                    total -= counts.remove(index);
+                    messages.remove(index);
+                }
+            }
+            TruncationStrategy::TrimMiddle => {
+                let last = messages.len().saturating_sub(1);
+                let candidate = (0..last)
+                    .filter(|i| messages[*i].role != ChatRole::System)
+                    .max_by_key(|i| counts[*i])
+                    .filter(|i| counts[*i] > total - self.available())
+                    .unwrap_or(last);
+
+                let target = counts[candidate]
+                    .saturating_sub(total - self.available())
+                    .saturating_sub(PART_OVERHEAD_TOKENS);
+                let trimmed = trim_middle(counter, &messages[candidate].content, target).await;
+                let trimmed = trimmed.ok_or_else(|| self.overflow(needed))?;
+                total -= counts[candidate];
+                total += counter.count_tokens(&trimmed).await + PART_OVERHEAD_TOKENS;
+                messages[candidate].content = trimmed;
+            }
+        }
+
+        if total > self.available() {
+            return Err(self.overflow(needed));
+        }
+        Ok(messages)
+    }
+}
+
+/// Keeps the beginning and end of `text`, shrinking the kept share until it fits `target`.
+async fn trim_middle(counter: &dyn TokenCounter, text: &str, target: usize) -> Option<String> {
+    let chars: Vec<char> = text.chars().collect();
+    let count = counter.count_tokens(text).await.max(1);
+    let mut ratio = (target as f64) / (count as f64);
+
+    for _ in 0..8 {
+        let keep = ((chars.len() as f64) * ratio) as usize / 2;
+        if keep == 0 {
+            return None;
+        }
+        let head: String = chars[..keep].iter().collect();
+        let tail: String = chars[chars.len() - keep..].iter().collect();
+        let trimmed = format!("{}{}{}", head, TRIM_MARKER, tail);
+        if counter.count_tokens(&trimmed).await <= target {
+            return Some(trimmed);
+        }
+        ratio *= 0.9;
+    }
+    None
+}
+
+/// Drops paragraphs from the start of `text` until it fits `target`.
+async fn drop_leading_paragraphs(
+    counter: &dyn TokenCounter,
+    text: &str,
+    target: usize
+) -> Option<String> {
+    let paragraphs: Vec<&str> = text.split("\n\n").collect();
+    for start in 1..paragraphs.len() {
+        let rest = paragraphs[start..].join("\n\n");
+        if counter.count_tokens(&rest).await <= target {
+            return Some(rest);
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_prompt_strategies() {
+        let budget = ContextBudget::new(60, 20);
+        let prompt = format!("{}\n\n{}\n\n{}", "a".repeat(80), "b".repeat(80), "question?");
+
+        let trimmed = budget
+            .fit_prompt(&EstimatingCounter, "", &prompt, TruncationStrategy::TrimMiddle).await
+            .unwrap();
+        assert!(trimmed.starts_with("aaa") && trimmed.ends_with("question?"));
+        assert!(estimate_tokens(&trimmed) + 8 <= budget.available());
+
+        let dropped = budget
+            .fit_prompt(&EstimatingCounter, "", &prompt, TruncationStrategy::DropOldestTurns).await
+            .unwrap();
+        assert!(dropped.starts_with("bbb"));
+
+        let error = budget
+            .fit_prompt(&EstimatingCounter, "", &prompt, TruncationStrategy::Fail).await
+            .unwrap_err();
+        assert!(matches!(error, LLMError::ContextOverflow { available: 40, .. }));
+    }
+
+    #[tokio::test]
+    async fn test_drop_oldest_turns_keeps_system_and_last_message() {
+        let budget = ContextBudget::new(40, 0);
+        let messages = vec![
+            ChatMessage::system("Be brief"),
+            ChatMessage::user("old question ".repeat(6)),
+            ChatMessage::assistant("old answer ".repeat(6)),
+            ChatMessage::user("What now?")
+        ];
+
+        let fitted = budget
+            .fit_messages(&EstimatingCounter, &messages, TruncationStrategy::DropOldestTurns).await
+            .unwrap();
+        assert_eq!(fitted.first().unwrap().content, "Be brief");
+        assert_eq!(fitted.last().unwrap().content, "What now?");
+        assert!(fitted.len() < messages.len());
+    }
+}
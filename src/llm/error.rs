@@ -5,5 +5,20 @@ use thiserror::Error; // For custom error handling
 pub enum LLMError {
     #[error("Server unavailable: {0}")] ServerUnavailable(String),
     #[error("Request failed: {0}")] RequestFailed(String),
+    #[error("Invalid prompt: {0}")] InvalidPrompt(String),
+    #[error("Invalid options: {0}")] InvalidOptions(String),
+    #[error("Invalid response: {0}")] InvalidResponse(String),
+    #[error("Timed out: {0}")] Timeout(String),
+    #[error("Circuit open for {0}")] CircuitOpen(String),
+    #[error("Generation was cancelled")] Cancelled,
+    #[error("Cache error: {0}")] Cache(String),
+    #[error("Cassette error: {0}")] Cassette(String),
+    #[error("{0}")] CassetteMismatch(String),
+    #[error("No model could answer: {0}")] RoutingFailed(String),
+    #[error("Prompt needs {needed} tokens but only {available} fit in the context window")]
+    ContextOverflow {
+        needed: usize,
+        available: usize,
+    },
     #[error("Unexpected error: {0}")] Unexpected(String),
 }
@@ -0,0 +1,110 @@
+use std::path::PathBuf;
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use serde::{ Deserialize, Serialize };
+
+use super::error::LLMError;
+
+/// An image sent along with a prompt to a multimodal model.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ImageInput {
+    /// Read when the request is sent
+    Path(PathBuf),
+    Bytes {
+        data: Vec<u8>,
+        mime_type: String,
+    },
+    /// Already base64 encoded, without a `data:` URL prefix
+    Base64 {
+        data: String,
+        mime_type: String,
+    },
+}
+
+impl ImageInput {
+    pub fn from_path(path: impl Into<PathBuf>) -> Self {
+        ImageInput::Path(path.into())
+    }
+
+    pub fn from_bytes(data: Vec<u8>, mime_type: &str) -> Self {
+        ImageInput::Bytes { data, mime_type: mime_type.to_string() }
+    }
+
+    /// Accepts plain base64 or a `data:<mime type>;base64,` URL.
+    pub fn from_base64(data: &str, mime_type: &str) -> Self {
+        match data.strip_prefix("data:").and_then(|url| url.split_once(";base64,")) {
+            Some((mime_type, data)) => {
+                ImageInput::Base64 { data: data.to_string(), mime_type: mime_type.to_string() }
+            }
+            None => ImageInput::Base64 { data: data.to_string(), mime_type: mime_type.to_string() },
+        }
+    }
+
+    /// The MIME type, guessed from the file extension for paths.
+    pub fn mime_type(&self) -> String {
+        match self {
+            ImageInput::Path(path) => {
+                let extension = path
+                    .extension()
+                    .and_then(|e| e.to_str())
+                    .unwrap_or_default()
+                    .to_lowercase();
+                match extension.as_str() {
+                    "jpg" | "jpeg" => "image/jpeg",
+                    "gif" => "image/gif",
+                    "webp" => "image/webp",
+                    "bmp" => "image/bmp",
+                    _ => "image/png",
+                }.to_string()
+            }
+            ImageInput::Bytes { mime_type, .. } | ImageInput::Base64 { mime_type, .. } => {
+                mime_type.clone()
+            }
+        }
+    }
+
+    /// The image data, base64 encoded.
+    pub fn to_base64(&self) -> Result<String, LLMError> {
+        match self {
+            ImageInput::Path(path) => {
+                let data = std::fs::read(path).map_err(|e| {
+                    LLMError::InvalidPrompt(format!("Cannot read image {}: {}", path.display(), e))
+                })?;
+                Ok(STANDARD.encode(data))
+            }
+            ImageInput::Bytes { data, .. } => Ok(STANDARD.encode(data)),
+            ImageInput::Base64 { data, .. } => Ok(data.clone()),
+        }
+    }
+
+    /// A `data:` URL, as used in OpenAI `image_url` content parts.
+    pub fn to_data_url(&self) -> Result<String, LLMError> {
+        Ok(format!("data:{};base64,{}", self.mime_type(), self.to_base64()?))
+    }
+}
+
+/// The marker llama.cpp replaces with the image whose `image_data` id is `id`.
+pub fn image_placeholder(id: usize) -> String {
+    format!("[img-{}]", id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encodings() {
+        let image = ImageInput::from_bytes(b"png".to_vec(), "image/png");
+        assert_eq!(image.to_base64().unwrap(), "cG5n");
+        assert_eq!(image.to_data_url().unwrap(), "data:image/png;base64,cG5n");
+
+        let image = ImageInput::from_base64("data:image/jpeg;base64,cG5n", "image/png");
+        assert_eq!(image.mime_type(), "image/jpeg");
+        assert_eq!(image.to_base64().unwrap(), "cG5n");
+
+        assert_eq!(ImageInput::from_path("cat.JPG").mime_type(), "image/jpeg");
+        assert!(ImageInput::from_path("/does/not/exist.png").to_base64().is_err());
+    }
+}
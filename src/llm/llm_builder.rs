@@ -2,13 +2,46 @@ use crate::model::error::ModelError;
 use crate::model::{ ModelManagerInterface, ModelStatus };
 
 use super::{ options::LLMHTTPCallOptions, error::LLMError };
+use super::cache::{ self, ResponseCache };
+use super::cassette::{ self, Cassette, CassetteMode, RecordedResponse };
+use super::middleware::{ LLMMiddleware, LLMRequest, MiddlewareStack };
+use super::chat::ChatMessage;
+use super::context::{
+    estimate_tokens,
+    ContextBudget,
+    TokenCounter,
+    TruncationStrategy,
+    DEFAULT_OUTPUT_RESERVE,
+};
+use super::provider::{ LLMProvider, LlamaCppProvider, ProviderRequest };
+use super::response::LLMResponse;
+use super::image::ImageInput;
+use super::batch::{ self, BatchOptions, BatchProgress, BatchResult };
+use super::session::{ self, LLMSession };
+use super::logprobs::{ self, LabelScore };
+use super::structured::{ self, STRUCTURED_OUTPUT_RETRIES };
+use super::resilience::{ CircuitBreaker, CircuitBreakerConfig, RetryPolicy, Timeouts };
+use super::router::{ LLMRouter, Routed };
+use super::stream_processing::{ cancellable_bytes, cancellable_events };
+use super::types::{ AccumulatedStream, CancellationToken, EventStream };
+use super::load_balancer::{ EndpointGuard, LoadBalancer };
+use std::collections::HashMap;
+use std::path::PathBuf;
 use std::error::Error as StdError; // Importing the correct trait
+use std::future::Future;
 use std::pin::Pin;
+use std::time::Duration;
 use bytes::Bytes;
-use futures::Stream;
-use log::info; // Ensure StreamExt is imported
+use schemars::JsonSchema;
+use serde::de::DeserializeOwned;
+use futures::{ Stream, StreamExt };
+use async_trait::async_trait;
+use log::{ debug, info, warn }; // Ensure StreamExt is imported
 use std::sync::Arc;
 
+/// How long a replica that refused a connection is kept out of rotation.
+const REPLICA_FAILURE_COOLDOWN: Duration = Duration::from_secs(5);
+
 #[derive(Clone)]
 pub struct LLM {
     client: reqwest::Client,
@@ -25,6 +58,15 @@ pub struct LLM {
     model_manager: Option<Arc<dyn ModelManagerInterface>>,
     model_name: Option<String>,
     auto_load: bool,
+    load_balancer: Option<Arc<LoadBalancer>>,
+    provider: Arc<dyn LLMProvider>,
+    timeouts: Timeouts,
+    retry_policy: RetryPolicy,
+    circuit_breaker: Option<Arc<CircuitBreaker>>,
+    cache: Option<Arc<ResponseCache>>,
+    cassette: Option<Arc<Cassette>>,
+    middlewares: MiddlewareStack,
+    router: Option<Arc<LLMRouter>>,
 }
 
 impl LLM {
@@ -32,80 +74,178 @@ impl LLM {
         LLMBuilder::default()
     }
 
-    async fn prepare_request(
+    pub fn options(&self) -> &LLMHTTPCallOptions {
+        &self.options
+    }
+
+    pub(crate) fn set_options(&mut self, options: LLMHTTPCallOptions) {
+        self.options = options;
+    }
+
+    /// Renders a conversation into the prompt sent to a llama.cpp server. Without a chat
+    /// template only a system prompt followed by a single user message can be rendered,
+    /// through the prompt template.
+    pub fn render_chat(&self, messages: &[ChatMessage]) -> Result<String, LLMError> {
+        self.options.render_chat(messages, &[])
+    }
+
+    /// Like `render_chat`, offering `tools` to the model if the chat template supports it.
+    pub fn render_chat_with_tools(
         &self,
-        prompt_with_context: &str,
-        system_prompt: &str,
+        messages: &[ChatMessage],
+        tools: &[serde_json::Value]
+    ) -> Result<String, LLMError> {
+        self.options.render_chat(messages, tools)
+    }
+
+    /// Sends the request, or replays it from the cassette.
+    async fn send_request(
+        &self,
+        payload: &serde_json::Value,
         stream: bool
-    ) -> Result<reqwest::Response, Box<dyn StdError + Send + Sync + 'static>> {
-        let server_url = self.options.server_url.as_ref().expect("Server URL is missing");
+    ) -> Result<Reply, LLMError> {
+        let endpoint = self.provider.endpoint();
 
-        let prompt_template = self.options.prompt_template
-            .as_ref()
-            .expect("Prompt template is missing");
-
-        let full_prompt = prompt_template
-            .replace("{system_prompt}", system_prompt)
-            .replace("{user_prompt}", prompt_with_context);
-
-        let mut json_payload = serde_json::Map::new();
-        json_payload.insert("prompt".to_string(), serde_json::Value::String(full_prompt));
-        json_payload.insert("stream".to_string(), serde_json::Value::Bool(stream));
-        json_payload.insert("cache_prompt".to_string(), serde_json::Value::Bool(true));
-
-        if let Some(temperature) = self.options.temperature {
-            json_payload.insert(
-                "temperature".to_string(),
-                serde_json::Value::Number(serde_json::Number::from_f64(temperature as f64).unwrap())
-            );
-        }
-        if let Some(top_k) = self.options.top_k {
-            json_payload.insert(
-                "top_k".to_string(),
-                serde_json::Value::Number(serde_json::Number::from(top_k as i64))
-            );
-        }
-        if let Some(top_p) = self.options.top_p {
-            json_payload.insert(
-                "top_p".to_string(),
-                serde_json::Value::Number(serde_json::Number::from_f64(top_p as f64).unwrap())
-            );
-        }
-        if let Some(seed) = self.options.seed {
-            json_payload.insert(
-                "seed".to_string(),
-                serde_json::Value::Number(serde_json::Number::from(seed as i64))
-            );
-        }
-        if let Some(min_length) = self.options.min_length {
-            json_payload.insert(
-                "min_length".to_string(),
-                serde_json::Value::Number(serde_json::Number::from(min_length as i64))
-            );
-        }
-        if let Some(max_length) = self.options.max_length {
-            json_payload.insert(
-                "max_length".to_string(),
-                serde_json::Value::Number(serde_json::Number::from(max_length as i64))
-            );
-        }
-        if let Some(repetition_penalty) = self.options.repetition_penalty {
-            json_payload.insert(
-                "repetition_penalty".to_string(),
-                serde_json::Value::Number(
-                    serde_json::Number::from_f64(repetition_penalty as f64).unwrap()
-                )
-            );
-        }
-
-        let resp = self.client
-            .post(&format!("{}/completion", server_url))
-            .json(&serde_json::Value::Object(json_payload))
+        let Some(cassette) = &self.cassette else {
+            return self.send_with_retries(payload, stream).await;
+        };
+        if cassette.mode() == CassetteMode::Replay {
+            return Ok(match cassette.replay(endpoint, payload)? {
+                RecordedResponse::Body(body) => Reply::Body(body),
+                RecordedResponse::Stream(chunks) => Reply::Stream(cassette::replay_stream(chunks)),
+            });
+        }
+
+        Ok(match self.send_with_retries(payload, stream).await? {
+            Reply::Body(body) => {
+                cassette.record(endpoint, payload, RecordedResponse::Body(body.clone()));
+                Reply::Body(body)
+            }
+            Reply::Stream(chunks) => {
+                let chunks = cassette::record_stream(cassette.clone(), endpoint, payload, chunks);
+                Reply::Stream(chunks)
+            }
+        })
+    }
+
+    /// Sends the request, retrying retryable failures with backoff.
+    async fn send_with_retries(
+        &self,
+        payload: &serde_json::Value,
+        stream: bool
+    ) -> Result<Reply, LLMError> {
+        let mut retry = 0;
+        loop {
+            let result = match &self.load_balancer {
+                Some(balancer) => self.send_balanced(balancer, payload, stream).await,
+                None => {
+                    let server_url = self.options.server_url
+                        .as_ref()
+                        .ok_or_else(|| LLMError::InvalidOptions("server_url is missing".into()))?;
+                    self.attempt(server_url, payload, stream, None).await
+                }
+            };
+
+            match result {
+                Err(e) if
+                    RetryPolicy::is_retryable(&e) &&
+                    retry < self.retry_policy.max_retries
+                => {
+                    let backoff = self.retry_policy.backoff(retry);
+                    warn!("Request failed ({}), retrying in {:?}", e, backoff);
+                    tokio::time::sleep(backoff).await;
+                    retry += 1;
+                }
+                result => {
+                    return result;
+                }
+            }
+        }
+    }
+
+    /// One attempt against `server_url`, guarded by the circuit breaker. For streams, the
+    /// attempt only succeeds once the first chunk has arrived.
+    async fn attempt(
+        &self,
+        server_url: &str,
+        payload: &serde_json::Value,
+        stream: bool,
+        guard: Option<EndpointGuard>
+    ) -> Result<Reply, LLMError> {
+        let _probe = match &self.circuit_breaker {
+            Some(breaker) => breaker.admit(server_url)?,
+            None => None,
+        };
+
+        let result = if stream {
+            self.open_stream(server_url, payload, guard).await
+        } else {
+            with_timeout(self.timeouts.request, "waiting for the response", async {
+                let resp = self.send_completion(server_url, payload).await?;
+                let body = resp
+                    .json::<serde_json::Value>().await
+                    .map_err(|e| LLMError::RequestFailed(e.to_string()))?;
+                Ok(Reply::Body(body))
+            }).await
+        };
+
+        // Any answer, even a client error, shows the server is up
+        if let Some(breaker) = &self.circuit_breaker {
+            match &result {
+                Err(e) if RetryPolicy::is_retryable(e) => breaker.record_failure(server_url),
+                _ => breaker.record_success(server_url),
+            }
+        }
+        result
+    }
+
+    async fn open_stream(
+        &self,
+        server_url: &str,
+        payload: &serde_json::Value,
+        guard: Option<EndpointGuard>
+    ) -> Result<Reply, LLMError> {
+        let resp = with_timeout(
+            self.timeouts.request,
+            "waiting for the response",
+            self.send_completion(server_url, payload)
+        ).await?;
+
+        // A server that accepted the request but stays silent is treated like one that is down
+        let mut chunks = resp.bytes_stream();
+        let first = with_timeout(self.timeouts.first_token, "waiting for the first token", async {
+            Ok(chunks.next().await)
+        }).await?;
+
+        // Keep the replica's outstanding-request count until the stream is finished
+        let stream = futures::stream::iter(first).chain(chunks).map(move |chunk| {
+            let _ = &guard;
+            chunk
+        });
+        Ok(Reply::Stream(Box::pin(stream)))
+    }
+
+    async fn send_completion(
+        &self,
+        server_url: &str,
+        payload: &serde_json::Value
+    ) -> Result<reqwest::Response, LLMError> {
+        let request = self.client
+            .post(format!("{}{}", server_url, self.provider.endpoint()))
+            .json(payload);
+        let resp = self.provider
+            .authorize(request)
             .send().await
-            .map_err(|e| LLMError::RequestFailed(e.to_string()))?
+            .map_err(|e| {
+                if e.is_connect() {
+                    LLMError::ServerUnavailable(e.to_string())
+                } else {
+                    LLMError::RequestFailed(e.to_string())
+                }
+            })?
             .error_for_status()
             .map_err(|e| {
-                if e.status().map_or(false, |status| status.is_server_error()) {
+                if e.status().is_some_and(|status| status.is_server_error()) {
                     LLMError::ServerUnavailable(e.to_string())
                 } else {
                     LLMError::RequestFailed(e.to_string())
@@ -115,6 +255,47 @@ impl LLM {
         Ok(resp)
     }
 
+    /// Sends the request to a healthy replica, moving on to the next one when a replica
+    /// refuses the connection or reports itself unavailable (e.g. while restarting).
+    async fn send_balanced(
+        &self,
+        balancer: &LoadBalancer,
+        payload: &serde_json::Value,
+        stream: bool
+    ) -> Result<Reply, LLMError> {
+        let mut tried: Vec<String> = Vec::new();
+        let mut last_error = None;
+
+        while let Some(guard) = balancer.pick(&tried) {
+            let url = guard.url().to_string();
+            match self.attempt(&url, payload, stream, Some(guard)).await {
+                Ok(reply) => {
+                    return Ok(reply);
+                }
+                Err(LLMError::ServerUnavailable(e)) => {
+                    warn!("Replica {} unavailable: {}", url, e);
+                    balancer.mark_failed(&url, REPLICA_FAILURE_COOLDOWN);
+                    last_error = Some(LLMError::ServerUnavailable(e));
+                    tried.push(url);
+                }
+                Err(e @ (LLMError::Timeout(_) | LLMError::CircuitOpen(_))) => {
+                    warn!("Skipping replica {}: {}", url, e);
+                    last_error = Some(e);
+                    tried.push(url);
+                }
+                Err(e) => {
+                    return Err(e);
+                }
+            }
+        }
+
+        Err(
+            last_error.unwrap_or_else(|| {
+                LLMError::ServerUnavailable("No healthy replicas available".to_string())
+            })
+        )
+    }
+
     pub async fn response_stream(
         &self,
         prompt_with_context: &str,
@@ -123,34 +304,625 @@ impl LLM {
         Pin<Box<dyn Stream<Item = Result<Bytes, reqwest::Error>> + Send>>,
         Box<dyn StdError + Send + Sync + 'static>
     > {
-        info!("Response stream not wating");
+        let request = ProviderRequest::Prompt {
+            system_prompt,
+            user_prompt: prompt_with_context,
+            images: &[],
+        };
+        self.stream_bytes(request).await
+    }
+
+    pub async fn response(
+        &self,
+        prompt_with_context: &str,
+        system_prompt: &str
+    ) -> Result<LLMResponse, Box<dyn StdError + Send + Sync + 'static>> {
+        let request = ProviderRequest::Prompt {
+            system_prompt,
+            user_prompt: prompt_with_context,
+            images: &[],
+        };
+        self.complete(request).await
+    }
+
+    /// Like `response`, with images for a multimodal model to look at.
+    pub async fn response_with_images(
+        &self,
+        prompt_with_context: &str,
+        system_prompt: &str,
+        images: &[ImageInput]
+    ) -> Result<LLMResponse, Box<dyn StdError + Send + Sync + 'static>> {
+        let request = ProviderRequest::Prompt {
+            system_prompt,
+            user_prompt: prompt_with_context,
+            images,
+        };
+        self.complete(request).await
+    }
+
+    /// Like `response`, for a whole conversation rendered through the chat template.
+    pub async fn chat(
+        &self,
+        messages: &[ChatMessage]
+    ) -> Result<LLMResponse, Box<dyn StdError + Send + Sync + 'static>> {
+        self.complete(ProviderRequest::Chat { messages, tools: &[] }).await
+    }
+
+    /// Like `chat`, with OpenAI-style function definitions the model may call.
+    pub async fn chat_with_tools(
+        &self,
+        messages: &[ChatMessage],
+        tools: &[serde_json::Value]
+    ) -> Result<LLMResponse, Box<dyn StdError + Send + Sync + 'static>> {
+        self.complete(ProviderRequest::Chat { messages, tools }).await
+    }
+
+    /// Like `response_stream`, for a whole conversation rendered through the chat template.
+    pub async fn chat_stream(
+        &self,
+        messages: &[ChatMessage]
+    ) -> Result<
+        Pin<Box<dyn Stream<Item = Result<Bytes, reqwest::Error>> + Send>>,
+        Box<dyn StdError + Send + Sync + 'static>
+    > {
+        self.stream_bytes(ProviderRequest::Chat { messages, tools: &[] }).await
+    }
+
+    /// Streams the generation as typed events, ending with `LLMEvent::Done`.
+    pub async fn stream(
+        &self,
+        prompt_with_context: &str,
+        system_prompt: &str
+    ) -> Result<EventStream, Box<dyn StdError + Send + Sync + 'static>> {
+        let request = ProviderRequest::Prompt {
+            system_prompt,
+            user_prompt: prompt_with_context,
+            images: &[],
+        };
+        self.stream_events(request).await
+    }
+
+    /// Like `stream`, with images for a multimodal model to look at.
+    pub async fn stream_with_images(
+        &self,
+        prompt_with_context: &str,
+        system_prompt: &str,
+        images: &[ImageInput]
+    ) -> Result<EventStream, Box<dyn StdError + Send + Sync + 'static>> {
+        let request = ProviderRequest::Prompt {
+            system_prompt,
+            user_prompt: prompt_with_context,
+            images,
+        };
+        self.stream_events(request).await
+    }
+
+    /// Like `stream`, for a whole conversation rendered through the chat template.
+    pub async fn stream_chat(
+        &self,
+        messages: &[ChatMessage]
+    ) -> Result<EventStream, Box<dyn StdError + Send + Sync + 'static>> {
+        self.stream_events(ProviderRequest::Chat { messages, tools: &[] }).await
+    }
+
+    /// Number of tokens in `text`, counted by the server's tokenizer when the provider has
+    /// one and estimated otherwise.
+    pub async fn count_tokens(&self, text: &str) -> usize {
+        match self.tokenize(text).await {
+            Ok(count) => count,
+            Err(e) => {
+                debug!("Estimating token count, tokenizer unavailable: {}", e);
+                estimate_tokens(text)
+            }
+        }
+    }
+
+    async fn tokenize(&self, text: &str) -> Result<usize, LLMError> {
+        let endpoint = self.provider
+            .tokenize_endpoint()
+            .ok_or_else(|| LLMError::Unexpected("Provider has no tokenizer".to_string()))?;
+        // The tokenizer is the model's own, so it is only there once the model is up
+        self.ensure_model_loaded().await.map_err(|e| LLMError::ServerUnavailable(e.to_string()))?;
+
+        let server_url = match &self.load_balancer {
+            Some(balancer) => balancer.pick(&[]).map(|guard| guard.url().to_string()),
+            None => self.options.server_url.clone(),
+        };
+        let server_url = server_url.ok_or_else(|| {
+            LLMError::ServerUnavailable("No server to tokenize with".to_string())
+        })?;
+
+        let body = with_timeout(self.timeouts.request, "waiting for the tokenizer", async {
+            self.client
+                .post(format!("{}{}", server_url, endpoint))
+                .json(&serde_json::json!({ "content": text }))
+                .send().await
+                .and_then(|resp| resp.error_for_status())
+                .map_err(|e| LLMError::RequestFailed(e.to_string()))?
+                .json::<serde_json::Value>().await
+                .map_err(|e| LLMError::InvalidResponse(e.to_string()))
+        }).await?;
+
+        body["tokens"]
+            .as_array()
+            .map(|tokens| tokens.len())
+            .ok_or_else(|| LLMError::InvalidResponse("`tokens` is missing".to_string()))
+    }
+
+    /// Room for the prompt in the model's context window, if its size is known. Room for
+    /// `max_tokens` (or `DEFAULT_OUTPUT_RESERVE`) is kept free for the answer.
+    pub fn context_budget(&self) -> Option<ContextBudget> {
+        let reserved = self.options.max_tokens.map_or(DEFAULT_OUTPUT_RESERVE, |max| max as usize);
+        self.options.context_size.map(|size| ContextBudget::new(size, reserved))
+    }
+
+    /// Shortens `user_prompt` with `strategy` if the prompts do not fit the context budget.
+    pub async fn fit_prompt(
+        &self,
+        system_prompt: &str,
+        user_prompt: &str,
+        strategy: TruncationStrategy
+    ) -> Result<String, LLMError> {
+        match self.context_budget() {
+            Some(budget) => budget.fit_prompt(self, system_prompt, user_prompt, strategy).await,
+            None => Ok(user_prompt.to_string()),
+        }
+    }
+
+    /// Shortens the conversation with `strategy` if it does not fit the context budget.
+    pub async fn fit_messages(
+        &self,
+        messages: &[ChatMessage],
+        strategy: TruncationStrategy
+    ) -> Result<Vec<ChatMessage>, LLMError> {
+        match self.context_budget() {
+            Some(budget) => budget.fit_messages(self, messages, strategy).await,
+            None => Ok(messages.to_vec()),
+        }
+    }
+
+    /// Generates a `T`. The output is constrained by the JSON Schema derived from `T`, and an
+    /// answer that still fails to deserialize is sent back to the model with the error, up to
+    /// `STRUCTURED_OUTPUT_RETRIES` times.
+    pub async fn generate_structured<T>(
+        &self,
+        prompt_with_context: &str,
+        system_prompt: &str
+    ) -> Result<T, Box<dyn StdError + Send + Sync + 'static>>
+        where T: DeserializeOwned + JsonSchema
+    {
+        let schema = structured::schema_for::<T>();
+        let system_prompt = structured::structured_system_prompt(system_prompt, &schema);
+        let mut llm = self.clone();
+        llm.options = llm.options.with_json_schema(schema);
+
+        let mut prompt = prompt_with_context.to_string();
+        let mut attempt = 0;
+        loop {
+            let response = llm.response(&prompt, &system_prompt).await?;
+            match structured::parse_structured::<T>(&response.content) {
+                Ok(value) => {
+                    return Ok(value);
+                }
+                Err(e) if attempt < STRUCTURED_OUTPUT_RETRIES => {
+                    warn!("Structured output did not parse ({}), asking again", e);
+                    prompt = structured::feedback_prompt(
+                        prompt_with_context,
+                        &response.content,
+                        &e
+                    );
+                    attempt += 1;
+                }
+                Err(e) => {
+                    let message = format!(
+                        "Structured output did not parse after {} attempts: {}",
+                        attempt + 1,
+                        e
+                    );
+                    return Err(Box::new(LLMError::InvalidResponse(message)));
+                }
+            }
+        }
+    }
+
+    /// Scores each of `labels` as the answer to the prompt, e.g. for classification. Every
+    /// label is forced through a grammar and its token log-probabilities are summed, so
+    /// labels of any length compare fairly; this needs a server that supports grammars and
+    /// `n_probs`, like llama-server. Scores are returned most likely first.
+    pub async fn score_labels(
+        &self,
+        prompt_with_context: &str,
+        system_prompt: &str,
+        labels: &[&str]
+    ) -> Result<Vec<LabelScore>, Box<dyn StdError + Send + Sync + 'static>> {
+        let mut scores = Vec::with_capacity(labels.len());
+        for label in labels {
+            let mut llm = self.clone();
+            llm.options = llm.options
+                .with_grammar(logprobs::label_grammar(label))
+                .with_n_probs(1)
+                .with_temperature(0.0);
+
+            // The prompt is the same for every label, so the server reuses its KV cache
+            let response = llm.response(prompt_with_context, system_prompt).await?;
+            if response.logprobs.is_empty() {
+                let message = "The server did not report token log-probabilities".to_string();
+                return Err(Box::new(LLMError::InvalidResponse(message)));
+            }
+            let logprob: f64 = response.logprobs
+                .iter()
+                .map(|token| token.logprob)
+                .sum();
+            scores.push((label.to_string(), logprob));
+        }
+        Ok(logprobs::rank_labels(scores))
+    }
+
+    /// Requests the server can work on at once: the sum of the `total_slots` of every
+    /// replica (see `ServerConfig::parallel`), or `None` if the server does not report it.
+    pub async fn slot_count(&self) -> Option<usize> {
+        let endpoint = self.provider.props_endpoint()?;
+        let urls: Vec<String> = match &self.load_balancer {
+            Some(balancer) => {
+                balancer
+                    .endpoints()
+                    .iter()
+                    .map(|endpoint| endpoint.url.clone())
+                    .collect()
+            }
+            None => vec![self.options.server_url.clone()?],
+        };
+
+        let mut total = 0;
+        for url in urls {
+            let request = self.client.get(format!("{}{}", url, endpoint));
+            let props = self.provider
+                .authorize(request)
+                .send().await
+                .and_then(|resp| resp.error_for_status())
+                .ok()?
+                .json::<serde_json::Value>().await
+                .ok()?;
+            total += props["total_slots"].as_u64()? as usize;
+        }
+        Some(total)
+    }
+
+    /// Completes every prompt, at most `concurrency` at a time, and returns the results in
+    /// the order of `prompts`. See `batch_with_options`.
+    pub async fn batch(
+        &self,
+        prompts: &[&str],
+        concurrency: usize
+    ) -> Result<Vec<BatchResult>, Box<dyn StdError + Send + Sync + 'static>> {
+        self.batch_with_options(prompts, BatchOptions::new().with_concurrency(concurrency)).await
+    }
+
+    /// Completes every prompt concurrently and returns the results in the order of
+    /// `prompts`. Concurrency is capped at `slot_count`, as requests beyond the server's
+    /// slots would only queue there. A failed item is reported in its result and does not
+    /// stop the others; the batch itself only fails if the results file cannot be used.
+    pub async fn batch_with_options(
+        &self,
+        prompts: &[&str],
+        options: BatchOptions
+    ) -> Result<Vec<BatchResult>, Box<dyn StdError + Send + Sync + 'static>> {
+        let hashes: Vec<String> = prompts
+            .iter()
+            .map(|prompt| batch::prompt_hash(&options.system_prompt, prompt))
+            .collect();
+        let (mut previous, mut writer) = match &options.results_file {
+            Some(path) => {
+                let previous = batch::load_results(path, &hashes).await?;
+                (previous, Some(batch::ResultsWriter::open(path).await?))
+            }
+            None => (HashMap::new(), None),
+        };
+
+        let slots = self.slot_count().await;
+        let concurrency = (match (options.concurrency, slots) {
+            (0, slots) => slots.unwrap_or(1),
+            (requested, Some(slots)) => requested.min(slots),
+            (requested, None) => requested,
+        }).max(1);
+
+        let mut results: Vec<Option<BatchResult>> = (0..prompts.len())
+            .map(|index| previous.remove(&index).map(Ok))
+            .collect();
+        let pending: Vec<usize> = (0..prompts.len()).filter(|i| results[*i].is_none()).collect();
+        let mut progress = BatchProgress {
+            total: prompts.len(),
+            completed: 0,
+            failed: 0,
+            skipped: prompts.len() - pending.len(),
+        };
+        info!(
+            "Batch of {} prompts, {} already done, {} at a time",
+            progress.total,
+            progress.skipped,
+            concurrency
+        );
+        if let (Some(callback), true) = (&options.progress, progress.skipped > 0) {
+            callback(progress);
+        }
+
+        let system_prompt = options.system_prompt.as_str();
+        let mut responses = futures::stream
+            ::iter(pending)
+            .map(|index| async move { (index, self.response(prompts[index], system_prompt).await) })
+            .buffer_unordered(concurrency);
+        while let Some((index, result)) = responses.next().await {
+            match &result {
+                Ok(response) => {
+                    progress.completed += 1;
+                    if let Some(writer) = &mut writer {
+                        writer.append(index, &hashes[index], response).await?;
+                    }
+                }
+                Err(e) => {
+                    warn!("Batch item {} failed: {}", index, e);
+                    progress.failed += 1;
+                }
+            }
+            results[index] = Some(result);
+            if let Some(callback) = &options.progress {
+                callback(progress);
+            }
+        }
+
+        Ok(results.into_iter().flatten().collect())
+    }
+
+    /// Starts a session in llama-server slot `id_slot`, which keeps its prompt cache between
+    /// requests. A replicated model is pinned to one replica, as slots are per process.
+    pub fn session(&self, id_slot: usize) -> Result<LLMSession, LLMError> {
+        if self.provider.slots_endpoint().is_none() {
+            return Err(LLMError::InvalidOptions("The provider has no server slots".to_string()));
+        }
+
+        let mut llm = self.clone();
+        if let Some(balancer) = llm.load_balancer.take() {
+            let guard = balancer
+                .pick(&[])
+                .ok_or_else(|| {
+                    LLMError::ServerUnavailable("No healthy replicas available".to_string())
+                })?;
+            llm.options.server_url = Some(guard.url().to_string());
+        }
+        llm.options = llm.options.with_id_slot(id_slot).with_cache_prompt(true);
+        Ok(LLMSession::new(llm, id_slot))
+    }
+
+    /// Runs a llama-server slot action: `save` or `restore` with a file name, or `erase`.
+    pub(crate) async fn slot_action(
+        &self,
+        id_slot: usize,
+        action: &str,
+        filename: Option<&str>
+    ) -> Result<serde_json::Value, LLMError> {
+        let endpoint = self.provider
+            .slots_endpoint()
+            .ok_or_else(|| LLMError::InvalidOptions("The provider has no server slots".into()))?;
+        let server_url = self.options.server_url
+            .as_ref()
+            .ok_or_else(|| LLMError::InvalidOptions("server_url is missing".into()))?;
+        if let Some(filename) = filename {
+            session::validate_slot_filename(filename)?;
+        }
+        self.ensure_model_loaded().await.map_err(|e| LLMError::ServerUnavailable(e.to_string()))?;
+
+        let body = match filename {
+            Some(filename) => serde_json::json!({ "filename": filename }),
+            None => serde_json::json!({}),
+        };
+        let request = self.client
+            .post(format!("{}{}/{}?action={}", server_url, endpoint, id_slot, action))
+            .json(&body);
+        let resp = self.provider
+            .authorize(request)
+            .send().await
+            .map_err(|e| LLMError::ServerUnavailable(e.to_string()))?;
+
+        let status = resp.status();
+        let body = resp
+            .json::<serde_json::Value>().await
+            .map_err(|e| LLMError::InvalidResponse(e.to_string()))?;
+        if !status.is_success() {
+            // llama-server explains e.g. a missing `--slot-save-path` in `error.message`
+            let reason = body["error"]["message"].as_str().unwrap_or_default();
+            let message = format!("Slot {} {} failed ({}): {}", id_slot, action, status, reason);
+            return Err(LLMError::RequestFailed(message));
+        }
+        Ok(body)
+    }
+
+    /// Like `response`, giving up as soon as `cancel` is cancelled. The request is dropped,
+    /// which closes the connection so llama-server stops generating and frees its slot.
+    pub async fn response_with_cancellation(
+        &self,
+        prompt_with_context: &str,
+        system_prompt: &str,
+        cancel: CancellationToken
+    ) -> Result<LLMResponse, Box<dyn StdError + Send + Sync + 'static>> {
+        with_cancellation(&cancel, self.response(prompt_with_context, system_prompt)).await
+    }
+
+    /// Like `response_stream`; the stream ends as soon as `cancel` is cancelled.
+    pub async fn response_stream_with_cancellation(
+        &self,
+        prompt_with_context: &str,
+        system_prompt: &str,
+        cancel: CancellationToken
+    ) -> Result<
+        Pin<Box<dyn Stream<Item = Result<Bytes, reqwest::Error>> + Send>>,
+        Box<dyn StdError + Send + Sync + 'static>
+    > {
+        let stream = with_cancellation(
+            &cancel,
+            self.response_stream(prompt_with_context, system_prompt)
+        ).await?;
+        Ok(cancellable_bytes(stream, cancel))
+    }
+
+    /// Like `stream`; the stream ends with `LLMError::Cancelled` as soon as `cancel` is
+    /// cancelled.
+    pub async fn stream_with_cancellation(
+        &self,
+        prompt_with_context: &str,
+        system_prompt: &str,
+        cancel: CancellationToken
+    ) -> Result<EventStream, Box<dyn StdError + Send + Sync + 'static>> {
+        let stream = with_cancellation(
+            &cancel,
+            self.stream(prompt_with_context, system_prompt)
+        ).await?;
+        Ok(cancellable_events(stream, cancel))
+    }
+
+    pub(crate) async fn stream_events(
+        &self,
+        request: ProviderRequest<'_>
+    ) -> Result<EventStream, Box<dyn StdError + Send + Sync + 'static>> {
+        if let Some(router) = &self.router {
+            return Ok(log_route(router.stream_events(request, router.hints()).await?));
+        }
+        let mut request = self.llm_request(&request, true)?;
+        let (entered, canned) = self.middlewares.before(&mut request).await?;
+        let events = match canned {
+            Some(response) => cache::replay_events(response),
+            None => self.stream_payload(&request.payload).await?,
+        };
+        Ok(self.middlewares.wrap(entered, &request, events))
+    }
+
+    async fn stream_payload(
+        &self,
+        payload: &serde_json::Value
+    ) -> Result<EventStream, Box<dyn StdError + Send + Sync + 'static>> {
+        let cache_key = self.cache_key(payload);
+        if let Some(response) = self.cached(&cache_key).await {
+            return Ok(cache::replay_events(response));
+        }
         self.ensure_model_loaded().await?;
 
-        let resp = self.prepare_request(prompt_with_context, system_prompt, true).await?;
+        let stream = self.send_request(payload, true).await?.into_stream();
+        let events = self.provider.decode_stream(stream);
+        match (&self.cache, cache_key) {
+            (Some(cache), Some(key)) => Ok(cache::record_events(events, cache.clone(), key)),
+            _ => Ok(events),
+        }
+    }
 
-        let stream = resp.bytes_stream();
+    pub(crate) async fn stream_bytes(
+        &self,
+        request: ProviderRequest<'_>
+    ) -> Result<
+        Pin<Box<dyn Stream<Item = Result<Bytes, reqwest::Error>> + Send>>,
+        Box<dyn StdError + Send + Sync + 'static>
+    > {
+        if let Some(router) = &self.router {
+            return Ok(log_route(router.stream_bytes(request, router.hints()).await?));
+        }
+        // Encoded from events, so middleware applies and cached and live answers come out alike
+        let events = self.stream_events(request).await?;
+        let stream = self.provider.encode_stream(events);
         let processed_stream = if let Some(process_fn) = &self.process_response {
-            process_fn(Box::pin(stream))
+            process_fn(stream)
         } else {
-            Box::pin(stream)
+            stream
         };
 
         Ok(processed_stream)
     }
 
-    pub async fn response(
+    pub(crate) async fn complete(
         &self,
-        prompt_with_context: &str,
-        system_prompt: &str
-    ) -> Result<serde_json::Value, Box<dyn StdError + Send + Sync + 'static>> {
+        request: ProviderRequest<'_>
+    ) -> Result<LLMResponse, Box<dyn StdError + Send + Sync + 'static>> {
+        if let Some(router) = &self.router {
+            return Ok(log_route(router.complete(request, router.hints()).await?));
+        }
+        let mut request = self.llm_request(&request, false)?;
+        let (entered, canned) = self.middlewares.before(&mut request).await?;
+        let mut response = match canned {
+            Some(response) => response,
+            None => self.complete_payload(&request.payload).await?,
+        };
+        self.middlewares.after(entered, &request, &mut response).await?;
+        Ok(response)
+    }
+
+    async fn complete_payload(
+        &self,
+        payload: &serde_json::Value
+    ) -> Result<LLMResponse, Box<dyn StdError + Send + Sync + 'static>> {
+        let cache_key = self.cache_key(payload);
+        if let Some(response) = self.cached(&cache_key).await {
+            return Ok(response);
+        }
         self.ensure_model_loaded().await?;
 
-        let resp = self.prepare_request(prompt_with_context, system_prompt, false).await?;
-        let response_json = resp.json::<serde_json::Value>().await?;
-        Ok(response_json)
+        let body = self.send_request(payload, false).await?.into_body();
+        let response = self.provider.parse_response(body)?;
+        if let (Some(cache), Some(key)) = (&self.cache, &cache_key) {
+            cache.put(key, &response).await;
+        }
+        Ok(response)
+    }
+
+    /// The request body, once the options have been checked.
+    fn payload(
+        &self,
+        request: &ProviderRequest<'_>,
+        stream: bool
+    ) -> Result<serde_json::Value, LLMError> {
+        self.options.validate()?;
+        self.provider.build_payload(request, &self.options, stream)
+    }
+
+    /// The request as passed through middleware.
+    fn llm_request(
+        &self,
+        request: &ProviderRequest<'_>,
+        stream: bool
+    ) -> Result<LLMRequest, LLMError> {
+        Ok(LLMRequest {
+            endpoint: self.provider.endpoint().to_string(),
+            payload: self.payload(request, stream)?,
+            stream,
+            model: self.model_name.clone(),
+        })
+    }
+
+    /// The cache key of `payload`, when a cache is configured and the request is deterministic.
+    fn cache_key(&self, payload: &serde_json::Value) -> Option<String> {
+        if self.cache.is_none() || !ResponseCache::is_cacheable(&self.options) {
+            return None;
+        }
+
+        // Streaming does not change the output, so both kinds of request share the key
+        let mut payload = payload.clone();
+        if let Some(stream) = payload.get_mut("stream") {
+            *stream = serde_json::Value::Bool(false);
+        }
+        let model = self.model_name
+            .as_deref()
+            .or(self.options.server_url.as_deref())
+            .unwrap_or_default();
+        Some(ResponseCache::key(model, self.provider.endpoint(), &payload))
+    }
+
+    async fn cached(&self, cache_key: &Option<String>) -> Option<LLMResponse> {
+        match (&self.cache, cache_key) {
+            (Some(cache), Some(key)) => cache.get(key).await,
+            _ => None,
+        }
     }
 
     async fn ensure_model_loaded(&self) -> Result<(), Box<dyn StdError + Send + Sync>> {
+        if self.cassette.as_ref().is_some_and(|cassette| cassette.mode() == CassetteMode::Replay) {
+            return Ok(());
+        }
         info!("Checking model status");
         if let (Some(manager), Some(name)) = (&self.model_manager, &self.model_name) {
             let should_load = match manager.get_model_status(name).await {
@@ -200,6 +972,69 @@ impl LLM {
     }
 }
 
+/// What a successful attempt produced.
+enum Reply {
+    Body(serde_json::Value),
+    Stream(AccumulatedStream),
+}
+
+impl Reply {
+    fn into_body(self) -> serde_json::Value {
+        match self {
+            Reply::Body(body) => body,
+            Reply::Stream(_) => unreachable!("streamed reply to a non-streaming request"),
+        }
+    }
+
+    fn into_stream(self) -> AccumulatedStream {
+        match self {
+            Reply::Stream(stream) => stream,
+            Reply::Body(_) => unreachable!("complete reply to a streaming request"),
+        }
+    }
+}
+
+/// Records which model a routing `LLM` used and the ones it passed over on the way.
+fn log_route<T>(routed: Routed<T>) -> T {
+    for failure in &routed.failures {
+        warn!("Route passed over {}: {}", failure.model, failure.reason);
+    }
+    info!("Routed to {}", routed.model);
+    routed.output
+}
+
+async fn with_cancellation<T>(
+    cancel: &CancellationToken,
+    future: impl Future<Output = Result<T, Box<dyn StdError + Send + Sync + 'static>>>
+) -> Result<T, Box<dyn StdError + Send + Sync + 'static>> {
+    tokio::select! {
+        biased;
+        _ = cancel.cancelled() => Err(Box::new(LLMError::Cancelled)),
+        result = future => result,
+    }
+}
+
+async fn with_timeout<T>(
+    limit: Option<Duration>,
+    what: &str,
+    future: impl Future<Output = Result<T, LLMError>>
+) -> Result<T, LLMError> {
+    match limit {
+        Some(limit) =>
+            tokio::time::timeout(limit, future).await.map_err(|_| {
+                LLMError::Timeout(format!("{} after {:?}", what, limit))
+            })?,
+        None => future.await,
+    }
+}
+
+#[async_trait]
+impl TokenCounter for LLM {
+    async fn count_tokens(&self, text: &str) -> usize {
+        LLM::count_tokens(self, text).await
+    }
+}
+
 pub struct LLMBuilder {
     options: LLMHTTPCallOptions,
     process_response: Option<
@@ -215,6 +1050,15 @@ pub struct LLMBuilder {
     model_manager: Option<Arc<dyn ModelManagerInterface>>,
     model_name: Option<String>,
     auto_load: bool,
+    load_balancer: Option<Arc<LoadBalancer>>,
+    provider: Arc<dyn LLMProvider>,
+    timeouts: Timeouts,
+    retry_policy: RetryPolicy,
+    circuit_breaker: Option<Arc<CircuitBreaker>>,
+    cache: Option<Arc<ResponseCache>>,
+    cassette: Option<(PathBuf, CassetteMode)>,
+    middlewares: MiddlewareStack,
+    router: Option<Arc<LLMRouter>>,
 }
 
 impl Default for LLMBuilder {
@@ -225,6 +1069,15 @@ impl Default for LLMBuilder {
             auto_load: false,
             model_manager: None,
             model_name: None,
+            load_balancer: None,
+            provider: Arc::new(LlamaCppProvider::new()),
+            timeouts: Timeouts::default(),
+            retry_policy: RetryPolicy::default(),
+            circuit_breaker: None,
+            cache: None,
+            cassette: None,
+            middlewares: MiddlewareStack::default(),
+            router: None,
         }
     }
 }
@@ -242,6 +1095,80 @@ impl LLMBuilder {
         self
     }
 
+    /// Spreads requests across several replicas of the same model instead of `server_url`.
+    pub fn with_load_balancer(mut self, load_balancer: Arc<LoadBalancer>) -> Self {
+        self.load_balancer = Some(load_balancer);
+        self
+    }
+
+    /// The API spoken by the server, llama.cpp's native one by default.
+    pub fn with_provider(mut self, provider: impl LLMProvider + 'static) -> Self {
+        self.provider = Arc::new(provider);
+        self
+    }
+
+    pub fn with_timeouts(mut self, timeouts: Timeouts) -> Self {
+        self.timeouts = timeouts;
+        self
+    }
+
+    pub fn with_connect_timeout(mut self, timeout: Duration) -> Self {
+        self.timeouts.connect = Some(timeout);
+        self
+    }
+
+    /// Limits a whole non-streaming generation, or the wait for a stream's response headers.
+    pub fn with_request_timeout(mut self, timeout: Duration) -> Self {
+        self.timeouts.request = Some(timeout);
+        self
+    }
+
+    /// Retries a stream whose first chunk takes longer than `timeout`.
+    pub fn with_first_token_timeout(mut self, timeout: Duration) -> Self {
+        self.timeouts.first_token = Some(timeout);
+        self
+    }
+
+    /// Defaults to `RetryPolicy::default()`; `RetryPolicy::none()` disables retries.
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    /// Fails fast on endpoints that keep failing. Off by default.
+    pub fn with_circuit_breaker(mut self, config: CircuitBreakerConfig) -> Self {
+        self.circuit_breaker = Some(Arc::new(CircuitBreaker::new(config)));
+        self
+    }
+
+    /// Serves deterministic requests (temperature 0 or a fixed seed) from `cache`. Streams
+    /// are stored too, and replayed from it as a single token.
+    pub fn with_cache(mut self, cache: Arc<ResponseCache>) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    /// Adds a middleware around every generation; the first one added is the outermost.
+    pub fn with_middleware(mut self, middleware: impl LLMMiddleware + 'static) -> Self {
+        self.middlewares.push(Arc::new(middleware));
+        self
+    }
+
+    /// Records generations to, or replays them from, the cassette at `path`; see `Cassette`.
+    /// Without one, the cassette named by `PYANO_CASSETTE` is used if set.
+    pub fn with_cassette(mut self, path: impl Into<PathBuf>, mode: CassetteMode) -> Self {
+        self.cassette = Some((path.into(), mode));
+        self
+    }
+
+    /// Sends every generation to `router`, which picks the model, so the router can be used
+    /// wherever an `LLM` is, e.g. by an `Agent`. The options, middleware, cache and cassette
+    /// of each routed model apply, not those of this builder.
+    pub fn with_router(mut self, router: LLMRouter) -> Self {
+        self.router = Some(Arc::new(router));
+        self
+    }
+
     pub fn with_options(mut self, options: LLMHTTPCallOptions) -> Self {
         self.options = options;
         self
@@ -260,14 +1187,36 @@ impl LLMBuilder {
         self
     }
 
-    pub fn build(self) -> LLM {
-        LLM {
-            client: reqwest::Client::new(),
-            options: self.options.build(),
+    pub fn build(self) -> Result<LLM, LLMError> {
+        let mut client = reqwest::Client::builder();
+        if let Some(connect) = self.timeouts.connect {
+            client = client.connect_timeout(connect);
+        }
+        let client = client
+            .build()
+            .map_err(|e| LLMError::Unexpected(format!("Failed to build HTTP client: {}", e)))?;
+        let cassette = match self.cassette {
+            Some((path, mode)) => Some(Cassette::shared(path, mode)?),
+            None => Cassette::from_env()?,
+        };
+
+        Ok(LLM {
+            client,
+            // A routing `LLM` sends nothing itself, so it needs no server
+            options: if self.router.is_some() { self.options } else { self.options.build()? },
             process_response: self.process_response,
             model_manager: self.model_manager,
             model_name: self.model_name,
             auto_load: self.auto_load,
-        }
+            load_balancer: self.load_balancer,
+            provider: self.provider,
+            timeouts: self.timeouts,
+            retry_policy: self.retry_policy,
+            circuit_breaker: self.circuit_breaker,
+            cache: self.cache,
+            cassette,
+            middlewares: self.middlewares,
+            router: self.router,
+        })
     }
 }
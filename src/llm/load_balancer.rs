@@ -0,0 +1,204 @@
+use std::sync::atomic::{ AtomicBool, AtomicUsize, Ordering };
+use std::sync::Arc;
+use std::time::{ Duration, Instant };
+
+use log::{ info, warn };
+use parking_lot::Mutex;
+use serde::{ Deserialize, Serialize };
+
+/// How a `LoadBalancer` chooses between healthy replicas.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+pub enum LoadBalanceStrategy {
+    #[default]
+    RoundRobin,
+    LeastOutstanding,
+}
+
+/// A single replica endpoint, e.g. `http://localhost:8001`.
+pub struct Endpoint {
+    pub url: String,
+    outstanding: AtomicUsize,
+    healthy: AtomicBool,
+    retry_after: Mutex<Option<Instant>>,
+}
+
+impl Endpoint {
+    fn new(url: String) -> Self {
+        Self {
+            url,
+            outstanding: AtomicUsize::new(0),
+            healthy: AtomicBool::new(true),
+            retry_after: Mutex::new(None),
+        }
+    }
+
+    /// Number of requests currently in flight against this endpoint.
+    pub fn outstanding(&self) -> usize {
+        self.outstanding.load(Ordering::SeqCst)
+    }
+
+    /// An endpoint is available when it is marked healthy and any failure cooldown has expired.
+    pub fn is_available(&self) -> bool {
+        if !self.healthy.load(Ordering::SeqCst) {
+            return false;
+        }
+        let mut retry_after = self.retry_after.lock();
+        match *retry_after {
+            Some(until) if Instant::now() < until => false,
+            Some(_) => {
+                *retry_after = None;
+                true
+            }
+            None => true,
+        }
+    }
+}
+
+/// Tracks an in-flight request. The outstanding count is released when the guard is dropped,
+/// so streaming callers should keep it alive until the stream ends.
+pub struct EndpointGuard {
+    endpoint: Arc<Endpoint>,
+}
+
+impl EndpointGuard {
+    pub fn url(&self) -> &str {
+        &self.endpoint.url
+    }
+}
+
+impl Drop for EndpointGuard {
+    fn drop(&mut self) {
+        self.endpoint.outstanding.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Spreads requests for one model across several llama-server replicas and routes
+/// around replicas that are restarting or failing.
+pub struct LoadBalancer {
+    endpoints: Vec<Arc<Endpoint>>,
+    strategy: LoadBalanceStrategy,
+    next: AtomicUsize,
+}
+
+impl LoadBalancer {
+    pub fn new(urls: Vec<String>, strategy: LoadBalanceStrategy) -> Self {
+        Self {
+            endpoints: urls.into_iter().map(Endpoint::new).map(Arc::new).collect(),
+            strategy,
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn endpoints(&self) -> &[Arc<Endpoint>] {
+        &self.endpoints
+    }
+
+    pub fn len(&self) -> usize {
+        self.endpoints.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.endpoints.is_empty()
+    }
+
+    /// Picks an available endpoint according to the strategy, skipping any url in `exclude`.
+    /// Returns `None` when every replica is unavailable.
+    pub fn pick(&self, exclude: &[String]) -> Option<EndpointGuard> {
+        let candidates: Vec<&Arc<Endpoint>> = self.endpoints
+            .iter()
+            .filter(|endpoint| !exclude.contains(&endpoint.url) && endpoint.is_available())
+            .collect();
+
+        if candidates.is_empty() {
+            return None;
+        }
+
+        let endpoint = match self.strategy {
+            LoadBalanceStrategy::RoundRobin => {
+                let index = self.next.fetch_add(1, Ordering::SeqCst) % candidates.len();
+                candidates[index]
+            }
+            LoadBalanceStrategy::LeastOutstanding =>
+                candidates
+                    .iter()
+                    .min_by_key(|endpoint| endpoint.outstanding())
+                    .copied()
+                    .unwrap_or(candidates[0]),
+        };
+
+        endpoint.outstanding.fetch_add(1, Ordering::SeqCst);
+        Some(EndpointGuard { endpoint: endpoint.clone() })
+    }
+
+    /// Takes an endpoint out of rotation for `cooldown` after a failed request.
+    pub fn mark_failed(&self, url: &str, cooldown: Duration) {
+        if let Some(endpoint) = self.find(url) {
+            warn!("Replica {} failed, skipping it for {:?}", url, cooldown);
+            *endpoint.retry_after.lock() = Some(Instant::now() + cooldown);
+        }
+    }
+
+    /// Explicitly marks a replica healthy or unhealthy, e.g. while it is being restarted.
+    pub fn set_healthy(&self, url: &str, healthy: bool) {
+        if let Some(endpoint) = self.find(url) {
+            info!("Replica {} marked {}", url, if healthy { "healthy" } else { "unhealthy" });
+            endpoint.healthy.store(healthy, Ordering::SeqCst);
+            if healthy {
+                *endpoint.retry_after.lock() = None;
+            }
+        }
+    }
+
+    fn find(&self, url: &str) -> Option<&Arc<Endpoint>> {
+        self.endpoints.iter().find(|endpoint| endpoint.url == url)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn urls() -> Vec<String> {
+        vec!["http://a".to_string(), "http://b".to_string(), "http://c".to_string()]
+    }
+
+    #[test]
+    fn test_round_robin_skips_unhealthy_replica() {
+        let balancer = LoadBalancer::new(urls(), LoadBalanceStrategy::RoundRobin);
+        balancer.set_healthy("http://b", false);
+
+        let picked: Vec<String> = (0..4)
+            .map(|_| balancer.pick(&[]).unwrap().url().to_string())
+            .collect();
+        assert!(!picked.contains(&"http://b".to_string()));
+        assert!(picked.contains(&"http://a".to_string()));
+        assert!(picked.contains(&"http://c".to_string()));
+    }
+
+    #[test]
+    fn test_least_outstanding_prefers_idle_replica() {
+        let balancer = LoadBalancer::new(urls(), LoadBalanceStrategy::LeastOutstanding);
+        let first = balancer.pick(&[]).unwrap();
+        let second = balancer.pick(&[]).unwrap();
+        assert_ne!(first.url(), second.url());
+
+        let third = balancer.pick(&[]).unwrap();
+        assert_ne!(third.url(), first.url());
+        assert_ne!(third.url(), second.url());
+
+        drop(second);
+        assert_eq!(balancer.pick(&[]).unwrap().url(), "http://b");
+    }
+
+    #[test]
+    fn test_failed_replica_is_skipped_until_cooldown_expires() {
+        let balancer = LoadBalancer::new(urls(), LoadBalanceStrategy::RoundRobin);
+        balancer.mark_failed("http://a", Duration::from_secs(60));
+        balancer.mark_failed("http://c", Duration::from_millis(0));
+
+        for _ in 0..3 {
+            assert_ne!(balancer.pick(&[]).unwrap().url(), "http://a");
+        }
+        assert!(balancer.pick(&["http://b".to_string(), "http://c".to_string()]).is_none());
+    }
+}
@@ -0,0 +1,182 @@
+use serde::{ Deserialize, Serialize };
+use serde_json::Value;
+
+/// A token the model could have generated instead, with its log-probability.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TokenAlternative {
+    pub token: String,
+    pub logprob: f64,
+}
+
+/// Log-probability of one generated token, requested with `LLMHTTPCallOptions::with_n_probs`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TokenLogprob {
+    pub token: String,
+    pub logprob: f64,
+    /// The most likely tokens at this position, most likely first
+    pub top_logprobs: Vec<TokenAlternative>,
+}
+
+impl TokenLogprob {
+    pub fn probability(&self) -> f64 {
+        self.logprob.exp()
+    }
+
+    /// Reads llama.cpp's `completion_probabilities`, in the current format (`logprob` and
+    /// `top_logprobs`) or the format of older servers (`content` and `probs`).
+    pub fn from_llamacpp(json: &Value) -> Vec<TokenLogprob> {
+        let Some(entries) = json.get("completion_probabilities").and_then(|p| p.as_array()) else {
+            return Vec::new();
+        };
+        entries
+            .iter()
+            .filter_map(|entry| {
+                if entry.get("logprob").is_some() {
+                    return Self::from_openai_entry(entry);
+                }
+
+                let token = entry["content"].as_str()?.to_string();
+                let top_logprobs: Vec<TokenAlternative> = entry["probs"]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|alternative| {
+                        Some(TokenAlternative {
+                            token: alternative["tok_str"].as_str()?.to_string(),
+                            logprob: alternative["prob"].as_f64()?.ln(),
+                        })
+                    })
+                    .collect();
+                let logprob = top_logprobs
+                    .iter()
+                    .find(|alternative| alternative.token == token)
+                    .map_or(f64::NEG_INFINITY, |alternative| alternative.logprob);
+                Some(TokenLogprob { token, logprob, top_logprobs })
+            })
+            .collect()
+    }
+
+    /// Reads the `logprobs.content` of an OpenAI choice.
+    pub fn from_openai(choice: &Value) -> Vec<TokenLogprob> {
+        choice["logprobs"]["content"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(Self::from_openai_entry)
+            .collect()
+    }
+
+    fn from_openai_entry(entry: &Value) -> Option<TokenLogprob> {
+        let alternative = |json: &Value| {
+            Some(TokenAlternative {
+                token: json["token"].as_str()?.to_string(),
+                logprob: json["logprob"].as_f64()?,
+            })
+        };
+        let chosen = alternative(entry)?;
+        Some(TokenLogprob {
+            token: chosen.token,
+            logprob: chosen.logprob,
+            top_logprobs: entry["top_logprobs"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(alternative)
+                .collect(),
+        })
+    }
+}
+
+/// How likely the model finds one of the labels passed to `LLM::score_labels`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LabelScore {
+    pub label: String,
+    /// Sum of the log-probabilities of the label's tokens
+    pub logprob: f64,
+    /// Probability among the candidate labels, which add up to 1
+    pub probability: f64,
+}
+
+/// GBNF grammar that only accepts `label`.
+pub fn label_grammar(label: &str) -> String {
+    let escaped: String = label
+        .chars()
+        .map(|c| {
+            match c {
+                '"' => "\\\"".to_string(),
+                '\\' => "\\\\".to_string(),
+                '\n' => "\\n".to_string(),
+                '\r' => "\\r".to_string(),
+                '\t' => "\\t".to_string(),
+                c => c.to_string(),
+            }
+        })
+        .collect();
+    format!("root ::= \"{}\"", escaped)
+}
+
+/// Normalizes the log-probability of each label into a distribution over the labels, most
+/// likely label first.
+pub fn rank_labels(logprobs: Vec<(String, f64)>) -> Vec<LabelScore> {
+    let max = logprobs
+        .iter()
+        .map(|(_, logprob)| *logprob)
+        .fold(f64::NEG_INFINITY, f64::max);
+    let total: f64 = logprobs
+        .iter()
+        .map(|(_, logprob)| (logprob - max).exp())
+        .sum();
+
+    let mut scores: Vec<LabelScore> = logprobs
+        .into_iter()
+        .map(|(label, logprob)| {
+            let probability = if total > 0.0 { (logprob - max).exp() / total } else { 0.0 };
+            LabelScore { label, logprob, probability }
+        })
+        .collect();
+    scores.sort_by(|a, b| b.logprob.total_cmp(&a.logprob));
+    scores
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_parse_current_and_legacy_llamacpp_formats() {
+        let current = json!({
+            "completion_probabilities": [{
+                "id": 12, "token": "Yes", "logprob": -0.1,
+                "top_logprobs": [
+                    { "id": 12, "token": "Yes", "logprob": -0.1 },
+                    { "id": 13, "token": "No", "logprob": -2.4 }
+                ]
+            }]
+        });
+        let logprobs = TokenLogprob::from_llamacpp(&current);
+        assert_eq!(logprobs[0].token, "Yes");
+        assert_eq!(logprobs[0].top_logprobs[1].token, "No");
+
+        let legacy = json!({
+            "completion_probabilities": [{
+                "content": "Yes",
+                "probs": [{ "tok_str": "Yes", "prob": 0.5 }, { "tok_str": "No", "prob": 0.25 }]
+            }]
+        });
+        let logprobs = TokenLogprob::from_llamacpp(&legacy);
+        assert!((logprobs[0].probability() - 0.5).abs() < 1e-9);
+        assert!((logprobs[0].top_logprobs[1].logprob - (0.25f64).ln()).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_rank_labels() {
+        let scores = rank_labels(vec![
+            ("negative".to_string(), (0.1f64).ln()),
+            ("positive".to_string(), (0.3f64).ln())
+        ]);
+        assert_eq!(scores[0].label, "positive");
+        assert!((scores[0].probability - 0.75).abs() < 1e-9);
+        assert_eq!(label_grammar("say \"hi\""), r#"root ::= "say \"hi\"""#);
+    }
+}
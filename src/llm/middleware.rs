@@ -0,0 +1,183 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use serde_json::Value;
+
+use super::error::LLMError;
+use super::response::LLMResponse;
+use super::types::EventStream;
+
+/// A generation request as middleware sees it, once the prompt has been rendered and the
+/// options validated.
+#[derive(Debug, Clone)]
+pub struct LLMRequest {
+    /// Path the provider sends the request to, e.g. `/completion`
+    pub endpoint: String,
+    /// The body sent to the server: the rendered prompt (`prompt` for llama.cpp, `messages`
+    /// for chat APIs) and the options under the provider's names
+    pub payload: Value,
+    pub stream: bool,
+    /// Name of the managed model, if any
+    pub model: Option<String>,
+}
+
+impl LLMRequest {
+    /// The rendered prompt of a llama.cpp request.
+    pub fn prompt(&self) -> Option<&str> {
+        self.payload["prompt"].as_str()
+    }
+
+    /// Rewrites every piece of prompt text: llama.cpp's `prompt`, and the content of each
+    /// chat message, including the text parts of multimodal messages.
+    pub fn map_text(&mut self, f: impl Fn(&str) -> String) {
+        if let Some(prompt) = self.payload["prompt"].as_str() {
+            self.payload["prompt"] = Value::String(f(prompt));
+        }
+        let messages = self.payload.get_mut("messages").and_then(|m| m.as_array_mut());
+        let Some(messages) = messages else {
+            return;
+        };
+        for message in messages {
+            match &mut message["content"] {
+                Value::String(content) => {
+                    *content = f(content);
+                }
+                Value::Array(parts) => {
+                    for part in parts {
+                        if let Some(text) = part["text"].as_str() {
+                            part["text"] = Value::String(f(text));
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Sets a body field, e.g. `set_option("temperature", 0.0)`.
+    pub fn set_option(&mut self, name: &str, value: impl Into<Value>) {
+        if let Some(payload) = self.payload.as_object_mut() {
+            payload.insert(name.to_string(), value.into());
+        }
+    }
+}
+
+pub enum MiddlewareAction {
+    /// Pass the request on to the next middleware and the server
+    Continue,
+    /// Answer with this response without contacting the server
+    Respond(Box<LLMResponse>),
+}
+
+/// A layer around every generation of an `LLM`, added with `LLMBuilder::with_middleware`.
+///
+/// Middlewares run in the order they were added on the way in, and in reverse order on
+/// the way out, so the first one added sees the request first and the response last. One
+/// that responds itself skips the later middlewares and the server; the earlier ones still
+/// see its response. Requests answered by the response cache pass through middleware too.
+///
+/// `wrap_stream` applies to every streamed response. The byte streams of `response_stream`
+/// and `chat_stream` are encoded from the wrapped events, so they carry its changes.
+#[async_trait]
+pub trait LLMMiddleware: Send + Sync {
+    /// Inspects or rewrites the request, or answers it.
+    async fn before_request(
+        &self,
+        _request: &mut LLMRequest
+    ) -> Result<MiddlewareAction, LLMError> {
+        Ok(MiddlewareAction::Continue)
+    }
+
+    /// Inspects or rewrites a complete (non-streaming) response.
+    async fn after_response(
+        &self,
+        _request: &LLMRequest,
+        _response: &mut LLMResponse
+    ) -> Result<(), LLMError> {
+        Ok(())
+    }
+
+    /// Wraps the events of a streamed response.
+    fn wrap_stream(&self, _request: &LLMRequest, stream: EventStream) -> EventStream {
+        stream
+    }
+}
+
+#[derive(Clone, Default)]
+pub(crate) struct MiddlewareStack {
+    layers: Vec<Arc<dyn LLMMiddleware>>,
+}
+
+impl MiddlewareStack {
+    pub(crate) fn push(&mut self, middleware: Arc<dyn LLMMiddleware>) {
+        self.layers.push(middleware);
+    }
+
+    /// Runs `before_request` in order. Returns how many middlewares took the request, and
+    /// the response if one of them answered it.
+    pub(crate) async fn before(
+        &self,
+        request: &mut LLMRequest
+    ) -> Result<(usize, Option<LLMResponse>), LLMError> {
+        for (index, layer) in self.layers.iter().enumerate() {
+            if let MiddlewareAction::Respond(response) = layer.before_request(request).await? {
+                return Ok((index, Some(*response)));
+            }
+        }
+        Ok((self.layers.len(), None))
+    }
+
+    /// Runs `after_response` of the first `entered` middlewares, last one first.
+    pub(crate) async fn after(
+        &self,
+        entered: usize,
+        request: &LLMRequest,
+        response: &mut LLMResponse
+    ) -> Result<(), LLMError> {
+        for layer in self.layers[..entered].iter().rev() {
+            layer.after_response(request, response).await?;
+        }
+        Ok(())
+    }
+
+    /// Wraps `stream` in the first `entered` middlewares, the first one outermost.
+    pub(crate) fn wrap(
+        &self,
+        entered: usize,
+        request: &LLMRequest,
+        stream: EventStream
+    ) -> EventStream {
+        self.layers[..entered]
+            .iter()
+            .rev()
+            .fold(stream, |stream, layer| layer.wrap_stream(request, stream))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_map_text_covers_prompts_and_messages() {
+        let mut request = LLMRequest {
+            endpoint: "/v1/chat/completions".to_string(),
+            payload: json!({
+                "messages": [
+                    { "role": "system", "content": "call 555-0100" },
+                    { "role": "user", "content": [{ "type": "text", "text": "or 555-0199" }] }
+                ]
+            }),
+            stream: false,
+            model: None,
+        };
+        request.map_text(|text| text.replace("555-", "XXX-"));
+        request.set_option("temperature", 0.0);
+
+        assert_eq!(request.payload["messages"][0]["content"], "call XXX-0100");
+        assert_eq!(request.payload["messages"][1]["content"][0]["text"], "or XXX-0199");
+        assert_eq!(request.payload["temperature"], 0.0);
+        assert_eq!(request.prompt(), None);
+    }
+}
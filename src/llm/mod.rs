@@ -1,5 +1,20 @@
 pub mod types;
+pub mod cache;
 pub mod options;
+pub mod chat;
+pub mod context;
+pub mod response;
+pub mod provider;
 pub mod llm_builder;
 pub mod error;
 pub mod stream_processing;
+pub mod load_balancer;
+pub mod resilience;
+pub mod structured;
+pub mod logprobs;
+pub mod image;
+pub mod batch;
+pub mod session;
+pub mod cassette;
+pub mod middleware;
+pub mod router;
@@ -1,3 +1,8 @@
+use serde_json::Value;
+
+use super::chat::{ ChatMessage, ChatRole, ChatTemplate };
+use super::error::LLMError;
+
 pub struct LLMServerOptions {
     pub max_tokens: Option<u32>,
     pub temperature: Option<f32>,
@@ -10,19 +15,83 @@ pub struct LLMServerOptions {
     pub repetition_penalty: Option<f32>,
 }
 
+/// Mirostat sampling, which targets a constant perplexity instead of using top-k/top-p.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mirostat {
+    V1 {
+        tau: f32,
+        eta: f32,
+    },
+    V2 {
+        tau: f32,
+        eta: f32,
+    },
+}
+
+impl Mirostat {
+    /// llama.cpp's `mirostat` value.
+    pub fn mode(&self) -> u8 {
+        match self {
+            Mirostat::V1 { .. } => 1,
+            Mirostat::V2 { .. } => 2,
+        }
+    }
+
+    /// Target entropy
+    pub fn tau(&self) -> f32 {
+        match self {
+            Mirostat::V1 { tau, .. } | Mirostat::V2 { tau, .. } => *tau,
+        }
+    }
+
+    /// Learning rate
+    pub fn eta(&self) -> f32 {
+        match self {
+            Mirostat::V1 { eta, .. } | Mirostat::V2 { eta, .. } => *eta,
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct LLMHTTPCallOptions {
+    /// Sent to llama.cpp as `n_predict`
     pub max_tokens: Option<u32>,
     pub temperature: Option<f32>,
+    /// Sent to llama.cpp as `stop`
     pub stop_words: Option<Vec<String>>,
     pub top_k: Option<usize>,
     pub top_p: Option<f32>,
+    pub min_p: Option<f32>,
+    pub typical_p: Option<f32>,
+    pub mirostat: Option<Mirostat>,
     pub seed: Option<usize>,
+    /// Not supported by llama.cpp and not sent
     pub min_length: Option<usize>,
+    /// Not supported by llama.cpp and not sent; use `max_tokens`
     pub max_length: Option<usize>,
+    /// Sent to llama.cpp as `repeat_penalty`
     pub repetition_penalty: Option<f32>,
+    pub presence_penalty: Option<f32>,
+    pub frequency_penalty: Option<f32>,
+    /// Token id and bias pairs; a bias of `f32::NEG_INFINITY` bans the token
+    pub logit_bias: Option<Vec<(u32, f32)>>,
+    /// Number of most likely tokens to report with each generated token
+    pub n_probs: Option<usize>,
+    /// Reuse the KV cache of a previous request with the same prefix, on by default
+    pub cache_prompt: Option<bool>,
+    /// Keep generating past the end-of-sequence token
+    pub ignore_eos: Option<bool>,
+    /// llama-server slot to run in, so its KV cache is reused; see `LLM::session`
+    pub id_slot: Option<usize>,
     pub server_url: Option<String>,
     pub prompt_template: Option<String>,
+    pub chat_template: Option<ChatTemplate>,
+    /// The model's context window in tokens, `ServerConfig.ctx_size` for managed models
+    pub context_size: Option<usize>,
+    /// GBNF grammar the output must follow
+    pub grammar: Option<String>,
+    /// JSON Schema the output must follow, converted to a grammar by llama.cpp
+    pub json_schema: Option<Value>,
     initialized_fields: Vec<String>,
 }
 
@@ -34,12 +103,26 @@ impl Default for LLMHTTPCallOptions {
             stop_words: None,
             top_k: None,
             top_p: None,
+            min_p: None,
+            typical_p: None,
+            mirostat: None,
             seed: None,
             min_length: None,
             max_length: None,
             repetition_penalty: None,
+            presence_penalty: None,
+            frequency_penalty: None,
+            logit_bias: None,
+            n_probs: None,
+            cache_prompt: Some(true),
+            ignore_eos: None,
+            id_slot: None,
             server_url: None,
             prompt_template: None,
+            chat_template: None,
+            context_size: None,
+            grammar: None,
+            json_schema: None,
             initialized_fields: Vec::new(),
         }
     }
@@ -80,6 +163,24 @@ impl LLMHTTPCallOptions {
         self
     }
 
+    pub fn with_min_p(mut self, min_p: f32) -> Self {
+        self.min_p = Some(min_p);
+        self.initialized_fields.push("min_p".to_string());
+        self
+    }
+
+    pub fn with_typical_p(mut self, typical_p: f32) -> Self {
+        self.typical_p = Some(typical_p);
+        self.initialized_fields.push("typical_p".to_string());
+        self
+    }
+
+    pub fn with_mirostat(mut self, mirostat: Mirostat) -> Self {
+        self.mirostat = Some(mirostat);
+        self.initialized_fields.push("mirostat".to_string());
+        self
+    }
+
     pub fn with_seed(mut self, seed: usize) -> Self {
         self.seed = Some(seed);
         self.initialized_fields.push("seed".to_string());
@@ -104,6 +205,48 @@ impl LLMHTTPCallOptions {
         self
     }
 
+    pub fn with_presence_penalty(mut self, presence_penalty: f32) -> Self {
+        self.presence_penalty = Some(presence_penalty);
+        self.initialized_fields.push("presence_penalty".to_string());
+        self
+    }
+
+    pub fn with_frequency_penalty(mut self, frequency_penalty: f32) -> Self {
+        self.frequency_penalty = Some(frequency_penalty);
+        self.initialized_fields.push("frequency_penalty".to_string());
+        self
+    }
+
+    pub fn with_logit_bias(mut self, logit_bias: Vec<(u32, f32)>) -> Self {
+        self.logit_bias = Some(logit_bias);
+        self.initialized_fields.push("logit_bias".to_string());
+        self
+    }
+
+    pub fn with_n_probs(mut self, n_probs: usize) -> Self {
+        self.n_probs = Some(n_probs);
+        self.initialized_fields.push("n_probs".to_string());
+        self
+    }
+
+    pub fn with_cache_prompt(mut self, cache_prompt: bool) -> Self {
+        self.cache_prompt = Some(cache_prompt);
+        self.initialized_fields.push("cache_prompt".to_string());
+        self
+    }
+
+    pub fn with_ignore_eos(mut self, ignore_eos: bool) -> Self {
+        self.ignore_eos = Some(ignore_eos);
+        self.initialized_fields.push("ignore_eos".to_string());
+        self
+    }
+
+    pub fn with_id_slot(mut self, id_slot: usize) -> Self {
+        self.id_slot = Some(id_slot);
+        self.initialized_fields.push("id_slot".to_string());
+        self
+    }
+
     pub fn with_server_url(mut self, server_url: String) -> Self {
         self.server_url = Some(server_url);
         self.initialized_fields.push("server_url".to_string());
@@ -116,7 +259,38 @@ impl LLMHTTPCallOptions {
         self
     }
 
-    pub fn build(mut self) -> Self {
+    /// Template used by `LLM::chat` to render multi-turn conversations.
+    pub fn with_chat_template(mut self, chat_template: ChatTemplate) -> Self {
+        self.chat_template = Some(chat_template);
+        self.initialized_fields.push("chat_template".to_string());
+        self
+    }
+
+    pub fn with_context_size(mut self, context_size: usize) -> Self {
+        self.context_size = Some(context_size);
+        self.initialized_fields.push("context_size".to_string());
+        self
+    }
+
+    /// Constrains the output with a GBNF grammar. Replaces any JSON Schema.
+    pub fn with_grammar(mut self, grammar: String) -> Self {
+        self.grammar = Some(grammar);
+        self.json_schema = None;
+        self.initialized_fields.push("grammar".to_string());
+        self
+    }
+
+    /// Constrains the output to JSON matching `json_schema`. Replaces any grammar.
+    pub fn with_json_schema(mut self, json_schema: Value) -> Self {
+        self.json_schema = Some(json_schema);
+        self.grammar = None;
+        self.initialized_fields.push("json_schema".to_string());
+        self
+    }
+
+    /// Fills unset fields with their defaults. Fails when `server_url` is missing or a
+    /// sampling parameter is out of range.
+    pub fn build(mut self) -> Result<Self, LLMError> {
         // Initialize only fields that have been explicitly set
         let defaults = LLMHTTPCallOptions::default();
 
@@ -149,13 +323,113 @@ impl LLMHTTPCallOptions {
         }
 
         if !self.initialized_fields.contains(&"server_url".to_string()) {
-            panic!("server_url must be provided before calling build()");
+            return Err(
+                LLMError::InvalidOptions("server_url must be provided before calling build()".into())
+            );
+        }
+        self.validate()?;
+
+        Ok(self)
+    }
+
+    /// Whether `field` was set explicitly rather than left at its default.
+    pub fn is_set(&self, field: &str) -> bool {
+        self.initialized_fields.iter().any(|f| f == field)
+    }
+
+    /// Checks that every sampling parameter is in the range the server accepts.
+    pub fn validate(&self) -> Result<(), LLMError> {
+        fn check(name: &str, value: Option<f32>, min: f32, max: f32) -> Result<(), LLMError> {
+            match value {
+                Some(value) if !(min..=max).contains(&value) => {
+                    Err(
+                        LLMError::InvalidOptions(
+                            format!("{} must be between {} and {}, got {}", name, min, max, value)
+                        )
+                    )
+                }
+                _ => Ok(()),
+            }
         }
 
-        if !self.initialized_fields.contains(&"prompt_template".to_string()) {
-            panic!("prompt_template must be provided before calling build()");
+        check("temperature", self.temperature, 0.0, f32::MAX)?;
+        check("top_p", self.top_p, 0.0, 1.0)?;
+        check("min_p", self.min_p, 0.0, 1.0)?;
+        check("typical_p", self.typical_p, 0.0, 1.0)?;
+        check("repetition_penalty", self.repetition_penalty, 0.0, f32::MAX)?;
+        check("presence_penalty", self.presence_penalty, -2.0, 2.0)?;
+        check("frequency_penalty", self.frequency_penalty, -2.0, 2.0)?;
+        if let Some(mirostat) = self.mirostat {
+            check("mirostat tau", Some(mirostat.tau()), f32::MIN_POSITIVE, f32::MAX)?;
+            check("mirostat eta", Some(mirostat.eta()), f32::MIN_POSITIVE, f32::MAX)?;
         }
+        if self.max_tokens == Some(0) {
+            return Err(LLMError::InvalidOptions("max_tokens must be at least 1".to_string()));
+        }
+        if let Some(bias) = self.logit_bias.iter().flatten().find(|(_, bias)| bias.is_nan()) {
+            return Err(
+                LLMError::InvalidOptions(format!("logit_bias for token {} is NaN", bias.0))
+            );
+        }
+        Ok(())
+    }
+}
 
-        self
+impl LLMHTTPCallOptions {
+    /// Splices the prompts into the prompt template.
+    pub fn render_prompt(
+        &self,
+        prompt_with_context: &str,
+        system_prompt: &str
+    ) -> Result<String, LLMError> {
+        let prompt_template = self.prompt_template
+            .as_ref()
+            .ok_or_else(|| LLMError::InvalidPrompt("No prompt template configured".to_string()))?;
+
+        if !prompt_template.contains("{user_prompt}") {
+            return Err(
+                LLMError::InvalidPrompt(
+                    "Prompt template has no {user_prompt} placeholder".to_string()
+                )
+            );
+        }
+
+        Ok(
+            prompt_template
+                .replace("{system_prompt}", system_prompt)
+                .replace("{user_prompt}", prompt_with_context)
+        )
+    }
+
+    /// Renders a conversation into a single prompt, offering `tools` to the model if the chat
+    /// template supports it. Without a chat template only a system prompt followed by a single
+    /// user message can be rendered, through the prompt template.
+    pub fn render_chat(
+        &self,
+        messages: &[ChatMessage],
+        tools: &[Value]
+    ) -> Result<String, LLMError> {
+        if let Some(chat_template) = &self.chat_template {
+            return chat_template.render_with_tools(messages, tools);
+        }
+
+        let (system, rest): (Vec<&ChatMessage>, Vec<&ChatMessage>) = messages
+            .iter()
+            .partition(|message| message.role == ChatRole::System);
+        match rest.as_slice() {
+            [user] if user.role == ChatRole::User => {
+                let system_prompt: Vec<&str> = system
+                    .iter()
+                    .map(|message| message.content.as_str())
+                    .collect();
+                self.render_prompt(&user.content, &system_prompt.join("\n\n"))
+            }
+            _ =>
+                Err(
+                    LLMError::InvalidPrompt(
+                        "Multi-turn conversations need a chat template".to_string()
+                    )
+                ),
+        }
     }
 }
@@ -0,0 +1,230 @@
+use serde_json::{ Map, Number, Value };
+
+use super::{ LLMProvider, ProviderRequest };
+use crate::llm::chat::ChatMessage;
+use crate::llm::error::LLMError;
+use crate::llm::image::{ image_placeholder, ImageInput };
+use crate::llm::options::LLMHTTPCallOptions;
+use crate::llm::response::LLMResponse;
+use crate::llm::stream_processing::{ llamacpp_body, llamacpp_events };
+use crate::llm::types::{ AccumulatedStream, EventStream };
+
+/// llama.cpp's native `/completion` API. Prompts are rendered client side, through the
+/// prompt template or, for conversations, the chat template.
+///
+/// Options are sent under llama.cpp's own names: `max_tokens` as `n_predict`, `stop_words`
+/// as `stop` and `repetition_penalty` as `repeat_penalty`. `min_length` and `max_length`
+/// have no llama.cpp equivalent and are not sent.
+///
+/// Images are sent as `image_data`, each referenced by an `[img-N]` placeholder put in front
+/// of the text it belongs to. The server must have been started with a projector (`--mmproj`).
+#[derive(Debug, Clone, Default)]
+pub struct LlamaCppProvider;
+
+impl LlamaCppProvider {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+/// Puts a placeholder for each of `images` in front of `text`, adding the images to
+/// `image_data` with ids following those already there.
+fn with_placeholders(
+    text: &str,
+    images: &[ImageInput],
+    image_data: &mut Vec<Value>
+) -> Result<String, LLMError> {
+    if images.is_empty() {
+        return Ok(text.to_string());
+    }
+
+    let mut placeholders = Vec::with_capacity(images.len());
+    for image in images {
+        let id = image_data.len();
+        let mut entry = Map::new();
+        entry.insert("data".to_string(), Value::String(image.to_base64()?));
+        entry.insert("id".to_string(), Value::from(id));
+        image_data.push(Value::Object(entry));
+        placeholders.push(image_placeholder(id));
+    }
+    Ok(format!("{}\n{}", placeholders.join(" "), text))
+}
+
+fn float(value: f32) -> Value {
+    Number::from_f64(value as f64).map(Value::Number).unwrap_or(Value::Null)
+}
+
+impl LLMProvider for LlamaCppProvider {
+    fn endpoint(&self) -> &str {
+        "/completion"
+    }
+
+    fn build_payload(
+        &self,
+        request: &ProviderRequest<'_>,
+        options: &LLMHTTPCallOptions,
+        stream: bool
+    ) -> Result<Value, LLMError> {
+        let mut image_data = Vec::new();
+        let full_prompt = match request {
+            ProviderRequest::Prompt { system_prompt, user_prompt, images } => {
+                let user_prompt = with_placeholders(user_prompt, images, &mut image_data)?;
+                options.render_prompt(&user_prompt, system_prompt)?
+            }
+            ProviderRequest::Chat { messages, tools } => {
+                let messages = messages
+                    .iter()
+                    .map(|message| {
+                        let content = with_placeholders(
+                            &message.content,
+                            &message.images,
+                            &mut image_data
+                        )?;
+                        Ok(ChatMessage { content, images: Vec::new(), ..message.clone() })
+                    })
+                    .collect::<Result<Vec<_>, LLMError>>()?;
+                options.render_chat(&messages, tools)?
+            }
+        };
+
+        let mut json_payload = Map::new();
+        json_payload.insert("prompt".to_string(), Value::String(full_prompt));
+        if !image_data.is_empty() {
+            json_payload.insert("image_data".to_string(), Value::Array(image_data));
+        }
+        json_payload.insert("stream".to_string(), Value::Bool(stream));
+        json_payload.insert(
+            "cache_prompt".to_string(),
+            Value::Bool(options.cache_prompt.unwrap_or(true))
+        );
+
+        if let Some(max_tokens) = options.max_tokens {
+            json_payload.insert("n_predict".to_string(), Value::from(max_tokens));
+        }
+        if let Some(stop_words) = &options.stop_words {
+            json_payload.insert("stop".to_string(), Value::from(stop_words.clone()));
+        }
+        if let Some(temperature) = options.temperature {
+            json_payload.insert("temperature".to_string(), float(temperature));
+        }
+        if let Some(top_k) = options.top_k {
+            json_payload.insert("top_k".to_string(), Value::from(top_k));
+        }
+        if let Some(top_p) = options.top_p {
+            json_payload.insert("top_p".to_string(), float(top_p));
+        }
+        if let Some(min_p) = options.min_p {
+            json_payload.insert("min_p".to_string(), float(min_p));
+        }
+        if let Some(typical_p) = options.typical_p {
+            json_payload.insert("typical_p".to_string(), float(typical_p));
+        }
+        if let Some(mirostat) = options.mirostat {
+            json_payload.insert("mirostat".to_string(), Value::from(mirostat.mode()));
+            json_payload.insert("mirostat_tau".to_string(), float(mirostat.tau()));
+            json_payload.insert("mirostat_eta".to_string(), float(mirostat.eta()));
+        }
+        if let Some(seed) = options.seed {
+            json_payload.insert("seed".to_string(), Value::from(seed));
+        }
+        if let Some(id_slot) = options.id_slot {
+            json_payload.insert("id_slot".to_string(), Value::from(id_slot));
+        }
+        if let Some(repetition_penalty) = options.repetition_penalty {
+            json_payload.insert("repeat_penalty".to_string(), float(repetition_penalty));
+        }
+        if let Some(presence_penalty) = options.presence_penalty {
+            json_payload.insert("presence_penalty".to_string(), float(presence_penalty));
+        }
+        if let Some(frequency_penalty) = options.frequency_penalty {
+            json_payload.insert("frequency_penalty".to_string(), float(frequency_penalty));
+        }
+        if let Some(logit_bias) = &options.logit_bias {
+            // llama.cpp bans a token when its bias is `false`
+            let pairs = logit_bias
+                .iter()
+                .map(|(token, bias)| {
+                    let bias = if *bias == f32::NEG_INFINITY {
+                        Value::Bool(false)
+                    } else {
+                        float(*bias)
+                    };
+                    Value::Array(vec![Value::from(*token), bias])
+                })
+                .collect();
+            json_payload.insert("logit_bias".to_string(), Value::Array(pairs));
+        }
+        if let Some(n_probs) = options.n_probs {
+            json_payload.insert("n_probs".to_string(), Value::from(n_probs));
+        }
+        if let Some(ignore_eos) = options.ignore_eos {
+            json_payload.insert("ignore_eos".to_string(), Value::Bool(ignore_eos));
+        }
+        if let Some(grammar) = &options.grammar {
+            json_payload.insert("grammar".to_string(), Value::String(grammar.clone()));
+        }
+        if let Some(json_schema) = &options.json_schema {
+            json_payload.insert("json_schema".to_string(), json_schema.clone());
+        }
+
+        Ok(Value::Object(json_payload))
+    }
+
+    fn tokenize_endpoint(&self) -> Option<&str> {
+        Some("/tokenize")
+    }
+
+    fn props_endpoint(&self) -> Option<&str> {
+        Some("/props")
+    }
+
+    fn slots_endpoint(&self) -> Option<&str> {
+        Some("/slots")
+    }
+
+    fn parse_response(&self, body: Value) -> Result<LLMResponse, LLMError> {
+        LLMResponse::from_llamacpp(body)
+    }
+
+    fn decode_stream(&self, stream: AccumulatedStream) -> EventStream {
+        llamacpp_events(stream)
+    }
+
+    fn encode_stream(&self, events: EventStream) -> AccumulatedStream {
+        llamacpp_body(events)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::llm::options::Mirostat;
+
+    #[test]
+    fn test_options_use_llamacpp_names() {
+        let options = LLMHTTPCallOptions::new()
+            .with_server_url("http://localhost:8000".to_string())
+            .with_prompt_template("{system_prompt}{user_prompt}".to_string())
+            .with_max_tokens(64)
+            .with_stop_words(vec!["</s>".to_string()])
+            .with_repetition_penalty(1.1)
+            .with_max_length(100)
+            .with_mirostat(Mirostat::V2 { tau: 5.0, eta: 0.1 })
+            .with_logit_bias(vec![(15, f32::NEG_INFINITY), (42, 1.5)])
+            .with_cache_prompt(false)
+            .build().unwrap();
+        let request = ProviderRequest::Prompt { system_prompt: "", user_prompt: "Hi", images: &[] };
+        let payload = LlamaCppProvider.build_payload(&request, &options, false).unwrap();
+
+        assert_eq!(payload["n_predict"], 64);
+        assert_eq!(payload["stop"][0], "</s>");
+        assert!(payload["repeat_penalty"].as_f64().unwrap() > 1.0);
+        assert_eq!(payload["mirostat"], 2);
+        assert_eq!(payload["logit_bias"][0][1], false);
+        assert_eq!(payload["logit_bias"][1][1], 1.5);
+        assert_eq!(payload["cache_prompt"], false);
+        for unsupported in ["max_tokens", "max_length", "repetition_penalty", "stop_words"] {
+            assert!(payload.get(unsupported).is_none(), "{} was sent", unsupported);
+        }
+    }
+}
@@ -0,0 +1,149 @@
+use reqwest::RequestBuilder;
+use serde_json::Value;
+
+use super::chat::ChatMessage;
+use super::error::LLMError;
+use super::image::ImageInput;
+use super::options::LLMHTTPCallOptions;
+use super::response::LLMResponse;
+use super::stream_processing::llamacpp_body;
+use super::types::{ AccumulatedStream, EventStream };
+
+mod llamacpp;
+mod ollama;
+mod openai;
+
+pub use llamacpp::LlamaCppProvider;
+pub use ollama::OllamaProvider;
+pub use openai::OpenAIProvider;
+
+/// What the caller asked `LLM` to generate from.
+#[derive(Debug, Clone, Copy)]
+pub enum ProviderRequest<'a> {
+    /// `LLM::response` and `LLM::stream`: a system prompt and a user prompt, with any images
+    /// the user prompt refers to
+    Prompt {
+        system_prompt: &'a str,
+        user_prompt: &'a str,
+        images: &'a [ImageInput],
+    },
+    /// `LLM::chat` and friends: a whole conversation, optionally with tool definitions
+    Chat {
+        messages: &'a [ChatMessage],
+        tools: &'a [Value],
+    },
+}
+
+/// The HTTP API spoken by the server behind an `LLM`.
+///
+/// A provider only maps requests and responses; `LLM` does the HTTP calls, so load
+/// balancing, model auto-loading and the other `LLM` features work the same for every
+/// provider.
+pub trait LLMProvider: Send + Sync {
+    /// Path of the generation endpoint, relative to the server URL.
+    fn endpoint(&self) -> &str;
+
+    fn build_payload(
+        &self,
+        request: &ProviderRequest<'_>,
+        options: &LLMHTTPCallOptions,
+        stream: bool
+    ) -> Result<Value, LLMError>;
+
+    fn parse_response(&self, body: Value) -> Result<LLMResponse, LLMError>;
+
+    /// Decodes a streamed response body into events, ending with `LLMEvent::Done`.
+    fn decode_stream(&self, stream: AccumulatedStream) -> EventStream;
+
+    /// Encodes events as a streamed response body of this API, the inverse of
+    /// `decode_stream`. `LLM::response_stream` builds its bytes this way, so they read the
+    /// same whether the events came from the server, the response cache or a middleware.
+    /// Errors become the API's in-stream error messages. Defaults to llama.cpp's format.
+    fn encode_stream(&self, events: EventStream) -> AccumulatedStream {
+        llamacpp_body(events)
+    }
+
+    /// Path of the tokenizer endpoint, if the server has one. `LLM::count_tokens` falls back
+    /// to an estimate without it.
+    fn tokenize_endpoint(&self) -> Option<&str> {
+        None
+    }
+
+    /// Path of an endpoint reporting the server's `total_slots`, used by `LLM::batch` to
+    /// size its concurrency.
+    fn props_endpoint(&self) -> Option<&str> {
+        None
+    }
+
+    /// Path of llama-server style slot actions (`/slots/{id}?action=save`), needed by
+    /// `LLM::session`.
+    fn slots_endpoint(&self) -> Option<&str> {
+        None
+    }
+
+    /// Adds authentication or other headers to every request.
+    fn authorize(&self, request: RequestBuilder) -> RequestBuilder {
+        request
+    }
+}
+
+/// Messages for providers that accept a conversation natively.
+fn request_messages(request: &ProviderRequest<'_>) -> Vec<ChatMessage> {
+    match request {
+        ProviderRequest::Prompt { system_prompt, user_prompt, images } => {
+            let mut messages = Vec::new();
+            if !system_prompt.is_empty() {
+                messages.push(ChatMessage::system(*system_prompt));
+            }
+            let mut user = ChatMessage::user(*user_prompt);
+            user.images = images.to_vec();
+            messages.push(user);
+            messages
+        }
+        ProviderRequest::Chat { messages, .. } => messages.to_vec(),
+    }
+}
+
+fn request_tools<'a>(request: &ProviderRequest<'a>) -> &'a [Value] {
+    match request {
+        ProviderRequest::Prompt { .. } => &[],
+        ProviderRequest::Chat { tools, .. } => tools,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::llm::response::{ LLMEvent, StopReason, Timings };
+    use futures::StreamExt;
+
+    #[tokio::test]
+    async fn test_encoded_streams_decode_to_the_same_events() {
+        let timings = Timings { predicted_n: 2.0, predicted_ms: 4.0, ..Default::default() };
+        let events = vec![
+            LLMEvent::token("Hi"),
+            LLMEvent::token(" there"),
+            LLMEvent::Timings(timings),
+            LLMEvent::Done { stop_reason: StopReason::Length }
+        ];
+        let providers: Vec<Box<dyn LLMProvider>> = vec![
+            Box::new(LlamaCppProvider::new()),
+            Box::new(OpenAIProvider::new("qwen")),
+            Box::new(OllamaProvider::new("qwen"))
+        ];
+
+        for provider in providers {
+            let stream = futures::stream::iter(events.clone().into_iter().map(Ok));
+            let body = provider.encode_stream(Box::pin(stream));
+            let decoded: Vec<LLMEvent> = provider
+                .decode_stream(body)
+                .map(|event| event.unwrap())
+                .collect().await;
+
+            assert_eq!(decoded.len(), 4);
+            assert_eq!(decoded[..2], events[..2]);
+            assert!(matches!(&decoded[2], LLMEvent::Timings(t) if t.predicted_ms == 4.0));
+            assert_eq!(decoded[3], events[3]);
+        }
+    }
+}
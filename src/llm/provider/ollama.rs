@@ -0,0 +1,244 @@
+use serde_json::{ json, Map, Value };
+
+use super::{ request_messages, request_tools, LLMProvider, ProviderRequest };
+use crate::llm::error::LLMError;
+use crate::llm::options::LLMHTTPCallOptions;
+use crate::llm::response::{ LLMEvent, LLMResponse, StopReason, Timings, Usage };
+use crate::llm::stream_processing::{ decode_events, encode_events, JsonLinesDecoder };
+use crate::llm::types::{ AccumulatedStream, EventStream };
+
+/// Ollama's `/api/chat`. The server applies the model's chat template.
+#[derive(Debug, Clone)]
+pub struct OllamaProvider {
+    model: String,
+}
+
+impl OllamaProvider {
+    pub fn new(model: &str) -> Self {
+        Self { model: model.to_string() }
+    }
+
+    /// Ollama takes a message's images as a list of base64 strings.
+    fn messages(request: &ProviderRequest<'_>) -> Result<Vec<Value>, LLMError> {
+        request_messages(request)
+            .iter()
+            .map(|message| {
+                let mut value = json!({ "role": message.role, "content": message.content });
+                if let Some(name) = &message.name {
+                    value["name"] = json!(name);
+                }
+                if !message.images.is_empty() {
+                    let images = message.images
+                        .iter()
+                        .map(|image| image.to_base64())
+                        .collect::<Result<Vec<String>, LLMError>>()?;
+                    value["images"] = json!(images);
+                }
+                Ok(value)
+            })
+            .collect()
+    }
+
+    fn stop_reason(body: &Value) -> StopReason {
+        match body["done_reason"].as_str() {
+            Some("stop") => StopReason::Eos,
+            Some("length") => StopReason::Length,
+            _ => StopReason::Unknown,
+        }
+    }
+
+    /// Ollama reports token counts and durations in nanoseconds.
+    fn timings(body: &Value) -> Option<Timings> {
+        let eval_count = body["eval_count"].as_f64()?;
+        let eval_ms = body["eval_duration"].as_f64().unwrap_or(0.0) / 1_000_000.0;
+        let prompt_count = body["prompt_eval_count"].as_f64().unwrap_or(0.0);
+        let prompt_ms = body["prompt_eval_duration"].as_f64().unwrap_or(0.0) / 1_000_000.0;
+        let per_second = |n: f64, ms: f64| if ms > 0.0 { (n * 1000.0) / ms } else { 0.0 };
+        let per_token = |n: f64, ms: f64| if n > 0.0 { ms / n } else { 0.0 };
+
+        Some(Timings {
+            prompt_n: prompt_count,
+            prompt_ms,
+            prompt_per_token_ms: per_token(prompt_count, prompt_ms),
+            prompt_per_second: per_second(prompt_count, prompt_ms),
+            predicted_n: eval_count,
+            predicted_ms: eval_ms,
+            predicted_per_token_ms: per_token(eval_count, eval_ms),
+            predicted_per_second: per_second(eval_count, eval_ms),
+        })
+    }
+
+    fn parse_event(line: &str) -> Vec<LLMEvent> {
+        let json: Value = match serde_json::from_str(line) {
+            Ok(json) => json,
+            Err(e) => {
+                return vec![LLMEvent::Error(format!("Malformed event {:?}: {}", line, e))];
+            }
+        };
+        if let Some(error) = json["error"].as_str() {
+            return vec![LLMEvent::Error(error.to_string())];
+        }
+
+        let mut events = Vec::new();
+        if let Some(content) = json["message"]["content"].as_str() {
+            if !content.is_empty() {
+                events.push(LLMEvent::token(content));
+            }
+        }
+        if json["done"].as_bool().unwrap_or(false) {
+            if let Some(timings) = Self::timings(&json) {
+                events.push(LLMEvent::Timings(timings));
+            }
+            events.push(LLMEvent::Done { stop_reason: Self::stop_reason(&json) });
+        }
+        events
+    }
+
+    /// The line Ollama sends for `event`, which `parse_event` reads back.
+    fn encode_event(event: Result<LLMEvent, LLMError>, timings: Option<Timings>) -> String {
+        let message = |content: String, done: bool| {
+            json!({ "message": { "role": "assistant", "content": content }, "done": done })
+        };
+        let line = match event {
+            Ok(LLMEvent::Token { content, .. }) => message(content, false),
+            Ok(LLMEvent::Done { stop_reason }) => {
+                let mut line = message(String::new(), true);
+                match stop_reason {
+                    StopReason::Length => {
+                        line["done_reason"] = json!("length");
+                    }
+                    StopReason::Unknown => {}
+                    _ => {
+                        line["done_reason"] = json!("stop");
+                    }
+                }
+                if let Some(timings) = timings {
+                    line["prompt_eval_count"] = json!(timings.prompt_n);
+                    line["prompt_eval_duration"] = json!(timings.prompt_ms * 1_000_000.0);
+                    line["eval_count"] = json!(timings.predicted_n);
+                    line["eval_duration"] = json!(timings.predicted_ms * 1_000_000.0);
+                }
+                line
+            }
+            Ok(LLMEvent::Error(message)) => json!({ "error": message }),
+            Ok(LLMEvent::Timings(_)) => unreachable!("timings are passed with Done"),
+            Err(e) => json!({ "error": e.to_string() }),
+        };
+        format!("{}\n", line)
+    }
+}
+
+impl LLMProvider for OllamaProvider {
+    fn endpoint(&self) -> &str {
+        "/api/chat"
+    }
+
+    fn build_payload(
+        &self,
+        request: &ProviderRequest<'_>,
+        options: &LLMHTTPCallOptions,
+        stream: bool
+    ) -> Result<Value, LLMError> {
+        if options.grammar.is_some() {
+            return Err(
+                LLMError::InvalidOptions(
+                    "Ollama does not support GBNF grammars, use a JSON Schema".to_string()
+                )
+            );
+        }
+        // Refused rather than dropped, so callers don't rely on options that have no effect
+        if options.logit_bias.is_some() {
+            return Err(LLMError::InvalidOptions("Ollama does not support logit_bias".to_string()));
+        }
+        if options.n_probs.is_some() {
+            return Err(
+                LLMError::InvalidOptions("Ollama does not return token probabilities".to_string())
+            );
+        }
+
+        let mut model_options = Map::new();
+        if let Some(max_tokens) = options.max_tokens {
+            model_options.insert("num_predict".to_string(), json!(max_tokens));
+        }
+        if let Some(temperature) = options.temperature {
+            model_options.insert("temperature".to_string(), json!(temperature));
+        }
+        if let Some(top_k) = options.top_k {
+            model_options.insert("top_k".to_string(), json!(top_k));
+        }
+        if let Some(top_p) = options.top_p {
+            model_options.insert("top_p".to_string(), json!(top_p));
+        }
+        if let Some(seed) = options.seed {
+            model_options.insert("seed".to_string(), json!(seed));
+        }
+        if let Some(min_p) = options.min_p {
+            model_options.insert("min_p".to_string(), json!(min_p));
+        }
+        if let Some(typical_p) = options.typical_p {
+            model_options.insert("typical_p".to_string(), json!(typical_p));
+        }
+        if let Some(mirostat) = options.mirostat {
+            model_options.insert("mirostat".to_string(), json!(mirostat.mode()));
+            model_options.insert("mirostat_tau".to_string(), json!(mirostat.tau()));
+            model_options.insert("mirostat_eta".to_string(), json!(mirostat.eta()));
+        }
+        if let Some(repetition_penalty) = options.repetition_penalty {
+            model_options.insert("repeat_penalty".to_string(), json!(repetition_penalty));
+        }
+        if let Some(presence_penalty) = options.presence_penalty {
+            model_options.insert("presence_penalty".to_string(), json!(presence_penalty));
+        }
+        if let Some(frequency_penalty) = options.frequency_penalty {
+            model_options.insert("frequency_penalty".to_string(), json!(frequency_penalty));
+        }
+        if let Some(stop_words) = &options.stop_words {
+            model_options.insert("stop".to_string(), json!(stop_words));
+        }
+
+        let mut payload = json!({
+            "model": self.model,
+            "messages": Self::messages(request)?,
+            "stream": stream,
+            "options": model_options,
+        });
+        let tools = request_tools(request);
+        if !tools.is_empty() {
+            payload["tools"] = json!(tools);
+        }
+        if let Some(json_schema) = &options.json_schema {
+            payload["format"] = json_schema.clone();
+        }
+
+        Ok(payload)
+    }
+
+    fn parse_response(&self, body: Value) -> Result<LLMResponse, LLMError> {
+        let content = body["message"]["content"]
+            .as_str()
+            .ok_or_else(|| LLMError::InvalidResponse("`message.content` is missing".to_string()))?
+            .to_string();
+
+        Ok(LLMResponse {
+            content,
+            stop_reason: Self::stop_reason(&body),
+            usage: Usage {
+                prompt_tokens: body["prompt_eval_count"].as_u64().unwrap_or(0) as usize,
+                completion_tokens: body["eval_count"].as_u64().unwrap_or(0) as usize,
+                cached_tokens: 0,
+            },
+            timings: Self::timings(&body),
+            model: body["model"].as_str().map(|m| m.to_string()),
+            logprobs: Vec::new(),
+            raw: body,
+        })
+    }
+
+    fn decode_stream(&self, stream: AccumulatedStream) -> EventStream {
+        decode_events(stream, JsonLinesDecoder::new(), Self::parse_event)
+    }
+
+    fn encode_stream(&self, events: EventStream) -> AccumulatedStream {
+        encode_events(events, Self::encode_event)
+    }
+}
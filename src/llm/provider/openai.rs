@@ -0,0 +1,265 @@
+use reqwest::RequestBuilder;
+use serde_json::{ json, Map, Value };
+
+use super::{ request_messages, request_tools, LLMProvider, ProviderRequest };
+use crate::llm::chat::{ ChatMessage, ChatRole };
+use crate::llm::error::LLMError;
+use crate::llm::logprobs::TokenLogprob;
+use crate::llm::options::LLMHTTPCallOptions;
+use crate::llm::response::{ LLMEvent, LLMResponse, StopReason, Timings, Usage };
+use crate::llm::stream_processing::{ decode_events, encode_events, SseDecoder };
+use crate::llm::types::{ AccumulatedStream, EventStream };
+
+/// OpenAI-compatible `/v1/chat/completions`, as served by vLLM, LM Studio, llama-server
+/// and OpenAI itself. The server applies the model's chat template.
+#[derive(Debug, Clone)]
+pub struct OpenAIProvider {
+    model: String,
+    api_key: Option<String>,
+}
+
+impl OpenAIProvider {
+    pub fn new(model: &str) -> Self {
+        Self { model: model.to_string(), api_key: None }
+    }
+
+    /// Sent as a bearer token.
+    pub fn with_api_key(mut self, api_key: &str) -> Self {
+        self.api_key = Some(api_key.to_string());
+        self
+    }
+
+    /// A message in the Chat Completions format; images turn the content into text and
+    /// `image_url` parts.
+    fn message(message: &ChatMessage) -> Result<Value, LLMError> {
+        if message.role == ChatRole::Tool && message.tool_call_id.is_none() {
+            // A tool message must answer a call by id, so a result without one, e.g. from a
+            // call written out as text, is passed back as an observation instead
+            let content = match &message.name {
+                Some(name) => format!("Observation from {}: {}", name, message.content),
+                None => format!("Observation: {}", message.content),
+            };
+            return Self::message(&ChatMessage::user(content));
+        }
+
+        let role = match message.role {
+            ChatRole::System => "system",
+            ChatRole::User => "user",
+            ChatRole::Assistant => "assistant",
+            ChatRole::Tool => "tool",
+        };
+        let content = if message.images.is_empty() {
+            json!(message.content)
+        } else {
+            let mut parts = vec![json!({ "type": "text", "text": message.content })];
+            for image in &message.images {
+                let url = image.to_data_url()?;
+                parts.push(json!({ "type": "image_url", "image_url": { "url": url } }));
+            }
+            Value::Array(parts)
+        };
+        let mut value = json!({ "role": role, "content": content });
+        if let Some(name) = &message.name {
+            value["name"] = json!(name);
+        }
+        if let Some(tool_call_id) = &message.tool_call_id {
+            value["tool_call_id"] = json!(tool_call_id);
+        }
+        Ok(value)
+    }
+
+    fn stop_reason(finish_reason: Option<&str>) -> StopReason {
+        match finish_reason {
+            Some("stop") | Some("tool_calls") => StopReason::Eos,
+            Some("length") => StopReason::Length,
+            _ => StopReason::Unknown,
+        }
+    }
+
+    fn parse_event(data: &str) -> Vec<LLMEvent> {
+        if data == "[DONE]" {
+            return Vec::new();
+        }
+        let json: Value = match serde_json::from_str(data) {
+            Ok(json) => json,
+            Err(e) => {
+                return vec![LLMEvent::Error(format!("Malformed event {:?}: {}", data, e))];
+            }
+        };
+        if let Some(error) = json.get("error") {
+            let message = error["message"].as_str().map(|m| m.to_string());
+            return vec![LLMEvent::Error(message.unwrap_or_else(|| error.to_string()))];
+        }
+
+        let choice = &json["choices"][0];
+        let mut events = Vec::new();
+        if let Some(content) = choice["delta"]["content"].as_str() {
+            if !content.is_empty() {
+                events.push(LLMEvent::Token {
+                    content: content.to_string(),
+                    logprobs: TokenLogprob::from_openai(choice),
+                });
+            }
+        }
+        if let Some(finish_reason) = choice["finish_reason"].as_str() {
+            // llama-server adds its own timings to the last chunk
+            if let Some(timings) = json.get("timings") {
+                if let Ok(timings) = serde_json::from_value(timings.clone()) {
+                    events.push(LLMEvent::Timings(timings));
+                }
+            }
+            events.push(LLMEvent::Done { stop_reason: Self::stop_reason(Some(finish_reason)) });
+        }
+        events
+    }
+
+    /// The chunks llama-server sends for `event`, which `parse_event` reads back.
+    fn encode_event(event: Result<LLMEvent, LLMError>, timings: Option<Timings>) -> String {
+        let chunk = match event {
+            Ok(LLMEvent::Token { content, logprobs }) => {
+                let mut choice = json!({ "index": 0, "delta": { "content": content } });
+                if !logprobs.is_empty() {
+                    choice["logprobs"] = json!({ "content": logprobs });
+                }
+                json!({ "choices": [choice] })
+            }
+            Ok(LLMEvent::Done { stop_reason }) => {
+                let finish_reason = match stop_reason {
+                    StopReason::Length => "length",
+                    _ => "stop",
+                };
+                let mut chunk = json!({
+                    "choices": [{ "index": 0, "delta": {}, "finish_reason": finish_reason }],
+                });
+                if let Some(timings) = timings {
+                    chunk["timings"] = json!(timings);
+                }
+                return format!("data: {}\n\ndata: [DONE]\n\n", chunk);
+            }
+            Ok(LLMEvent::Error(message)) => json!({ "error": { "message": message } }),
+            Ok(LLMEvent::Timings(_)) => unreachable!("timings are passed with Done"),
+            Err(e) => json!({ "error": { "message": e.to_string() } }),
+        };
+        format!("data: {}\n\n", chunk)
+    }
+}
+
+impl LLMProvider for OpenAIProvider {
+    fn endpoint(&self) -> &str {
+        "/v1/chat/completions"
+    }
+
+    fn build_payload(
+        &self,
+        request: &ProviderRequest<'_>,
+        options: &LLMHTTPCallOptions,
+        stream: bool
+    ) -> Result<Value, LLMError> {
+        let messages = request_messages(request)
+            .iter()
+            .map(Self::message)
+            .collect::<Result<Vec<Value>, LLMError>>()?;
+
+        let mut payload = Map::new();
+        payload.insert("model".to_string(), json!(self.model));
+        payload.insert("messages".to_string(), Value::Array(messages));
+        payload.insert("stream".to_string(), json!(stream));
+
+        let tools = request_tools(request);
+        if !tools.is_empty() {
+            payload.insert("tools".to_string(), json!(tools));
+        }
+        if let Some(max_tokens) = options.max_tokens {
+            payload.insert("max_tokens".to_string(), json!(max_tokens));
+        }
+        if let Some(temperature) = options.temperature {
+            payload.insert("temperature".to_string(), json!(temperature));
+        }
+        if let Some(top_p) = options.top_p {
+            payload.insert("top_p".to_string(), json!(top_p));
+        }
+        if let Some(seed) = options.seed {
+            payload.insert("seed".to_string(), json!(seed));
+        }
+        if let Some(stop_words) = &options.stop_words {
+            payload.insert("stop".to_string(), json!(stop_words));
+        }
+        if let Some(n_probs) = options.n_probs {
+            payload.insert("logprobs".to_string(), json!(true));
+            payload.insert("top_logprobs".to_string(), json!(n_probs));
+        }
+        if let Some(presence_penalty) = options.presence_penalty {
+            payload.insert("presence_penalty".to_string(), json!(presence_penalty));
+        }
+        if let Some(frequency_penalty) = options.frequency_penalty {
+            payload.insert("frequency_penalty".to_string(), json!(frequency_penalty));
+        }
+        if let Some(logit_bias) = &options.logit_bias {
+            // OpenAI clamps biases to [-100, 100], where -100 bans the token
+            let logit_bias: Map<String, Value> = logit_bias
+                .iter()
+                .map(|(token, bias)| (token.to_string(), json!(bias.clamp(-100.0, 100.0))))
+                .collect();
+            payload.insert("logit_bias".to_string(), Value::Object(logit_bias));
+        }
+        if let Some(json_schema) = &options.json_schema {
+            let response_format = json!({
+                "type": "json_schema",
+                "json_schema": { "name": "response", "schema": json_schema, "strict": true }
+            });
+            payload.insert("response_format".to_string(), response_format);
+        }
+        // Not part of the OpenAI API, but understood by llama-server and vLLM
+        if let Some(grammar) = &options.grammar {
+            payload.insert("grammar".to_string(), json!(grammar));
+        }
+
+        Ok(Value::Object(payload))
+    }
+
+    fn parse_response(&self, body: Value) -> Result<LLMResponse, LLMError> {
+        let choice = &body["choices"][0];
+        let content = choice["message"]["content"]
+            .as_str()
+            // A reply consisting only of tool calls has no content
+            .or_else(|| choice["message"]["tool_calls"].as_array().map(|_| ""))
+            .ok_or_else(|| {
+                LLMError::InvalidResponse("`choices[0].message.content` is missing".to_string())
+            })?
+            .to_string();
+
+        let count = |name: &str| body["usage"][name].as_u64().unwrap_or(0) as usize;
+        Ok(LLMResponse {
+            content,
+            stop_reason: Self::stop_reason(choice["finish_reason"].as_str()),
+            usage: Usage {
+                prompt_tokens: count("prompt_tokens"),
+                completion_tokens: count("completion_tokens"),
+                cached_tokens: body["usage"]["prompt_tokens_details"]["cached_tokens"]
+                    .as_u64()
+                    .unwrap_or(0) as usize,
+            },
+            timings: body
+                .get("timings")
+                .and_then(|t| serde_json::from_value(t.clone()).ok()),
+            model: body["model"].as_str().map(|m| m.to_string()),
+            logprobs: TokenLogprob::from_openai(choice),
+            raw: body,
+        })
+    }
+
+    fn decode_stream(&self, stream: AccumulatedStream) -> EventStream {
+        decode_events(stream, SseDecoder::new(), Self::parse_event)
+    }
+
+    fn encode_stream(&self, events: EventStream) -> AccumulatedStream {
+        encode_events(events, Self::encode_event)
+    }
+
+    fn authorize(&self, request: RequestBuilder) -> RequestBuilder {
+        match &self.api_key {
+            Some(api_key) => request.bearer_auth(api_key),
+            None => request,
+        }
+    }
+}
@@ -0,0 +1,261 @@
+use std::collections::HashMap;
+use std::time::{ Duration, Instant };
+
+use log::warn;
+use parking_lot::Mutex;
+
+use super::error::LLMError;
+
+/// Timeouts applied to every request of an `LLM`. `None` waits forever.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timeouts {
+    /// Establishing the TCP connection
+    pub connect: Option<Duration>,
+    /// A whole non-streaming generation, or the response headers of a streaming one
+    pub request: Option<Duration>,
+    /// The first chunk of a streamed response; a server that stays silent is retried
+    pub first_token: Option<Duration>,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Self {
+            connect: Some(Duration::from_secs(10)),
+            request: None,
+            first_token: None,
+        }
+    }
+}
+
+/// Exponential backoff for retryable failures: refused connections, `5xx` answers (e.g. 503
+/// while llama-server is still loading its model) and timeouts.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RetryPolicy {
+    /// Retries after the first attempt
+    pub max_retries: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    pub multiplier: f64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            initial_backoff: Duration::from_millis(250),
+            max_backoff: Duration::from_secs(4),
+            multiplier: 2.0,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Fail on the first error.
+    pub fn none() -> Self {
+        Self { max_retries: 0, ..Self::default() }
+    }
+
+    pub fn with_max_retries(mut self, max_retries: u32) -> Self {
+        self.max_retries = max_retries;
+        self
+    }
+
+    pub fn with_initial_backoff(mut self, initial_backoff: Duration) -> Self {
+        self.initial_backoff = initial_backoff;
+        self
+    }
+
+    pub fn with_max_backoff(mut self, max_backoff: Duration) -> Self {
+        self.max_backoff = max_backoff;
+        self
+    }
+
+    /// How long to wait before retry number `retry` (starting at 0).
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let factor = self.multiplier.powi(retry as i32);
+        self.initial_backoff.mul_f64(factor).min(self.max_backoff)
+    }
+
+    pub fn is_retryable(error: &LLMError) -> bool {
+        matches!(error, LLMError::ServerUnavailable(_) | LLMError::Timeout(_))
+    }
+}
+
+/// When a `CircuitBreaker` opens and for how long.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CircuitBreakerConfig {
+    /// Consecutive retryable failures that open the circuit
+    pub failure_threshold: u32,
+    /// How long an open circuit rejects requests before letting a single probe through
+    pub reset_timeout: Duration,
+}
+
+impl Default for CircuitBreakerConfig {
+    fn default() -> Self {
+        Self {
+            failure_threshold: 5,
+            reset_timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CircuitState {
+    /// Requests go through
+    Closed,
+    /// Requests are rejected without contacting the server
+    Open,
+    /// The reset timeout expired and a probe request is deciding whether to close again
+    HalfOpen,
+}
+
+#[derive(Debug, Default)]
+struct Circuit {
+    consecutive_failures: u32,
+    open_until: Option<Instant>,
+    probing: bool,
+}
+
+/// Per-endpoint circuit breaker. After `failure_threshold` consecutive failures an endpoint
+/// is given up on for `reset_timeout`, so callers fail fast instead of piling requests onto
+/// a server that is down.
+#[derive(Debug, Default)]
+pub struct CircuitBreaker {
+    config: CircuitBreakerConfig,
+    circuits: Mutex<HashMap<String, Circuit>>,
+}
+
+impl CircuitBreaker {
+    pub fn new(config: CircuitBreakerConfig) -> Self {
+        Self { config, circuits: Mutex::new(HashMap::new()) }
+    }
+
+    pub fn state(&self, url: &str) -> CircuitState {
+        match self.circuits.lock().get(url) {
+            Some(circuit) if circuit.probing => CircuitState::HalfOpen,
+            Some(Circuit { open_until: Some(until), .. }) if Instant::now() < *until => {
+                CircuitState::Open
+            }
+            Some(Circuit { open_until: Some(_), .. }) => CircuitState::HalfOpen,
+            _ => CircuitState::Closed,
+        }
+    }
+
+    /// Whether a request to `url` may be sent. Once the reset timeout has expired, only one
+    /// probe is let through until its outcome is recorded.
+    pub fn check(&self, url: &str) -> Result<(), LLMError> {
+        self.enter(url).map(|_| ())
+    }
+
+    /// Like `check`, also telling whether the request is the probe. The probe is released
+    /// when the returned guard is dropped, so a caller that gives up on the request (e.g. a
+    /// dropped stream) does not leave the circuit half-open for good.
+    pub(crate) fn admit<'a>(&'a self, url: &'a str) -> Result<Option<ProbeGuard<'a>>, LLMError> {
+        let probing = self.enter(url)?;
+        Ok(probing.then_some(ProbeGuard { breaker: self, url }))
+    }
+
+    /// Returns whether the admitted request is the probe.
+    fn enter(&self, url: &str) -> Result<bool, LLMError> {
+        let mut circuits = self.circuits.lock();
+        let circuit = circuits.entry(url.to_string()).or_default();
+        match circuit.open_until {
+            None => Ok(false),
+            Some(until) if Instant::now() < until || circuit.probing => {
+                Err(LLMError::CircuitOpen(url.to_string()))
+            }
+            Some(_) => {
+                circuit.probing = true;
+                Ok(true)
+            }
+        }
+    }
+
+    pub fn record_success(&self, url: &str) {
+        self.circuits.lock().remove(url);
+    }
+
+    pub fn record_failure(&self, url: &str) {
+        let mut circuits = self.circuits.lock();
+        let circuit = circuits.entry(url.to_string()).or_default();
+        circuit.consecutive_failures += 1;
+
+        if circuit.probing || circuit.consecutive_failures >= self.config.failure_threshold {
+            warn!(
+                "Opening circuit for {} after {} consecutive failures",
+                url,
+                circuit.consecutive_failures
+            );
+            circuit.open_until = Some(Instant::now() + self.config.reset_timeout);
+            circuit.probing = false;
+        }
+    }
+}
+
+/// The probe let through by a half-open circuit.
+pub(crate) struct ProbeGuard<'a> {
+    breaker: &'a CircuitBreaker,
+    url: &'a str,
+}
+
+impl Drop for ProbeGuard<'_> {
+    fn drop(&mut self) {
+        // A probe with a recorded outcome has already been cleared
+        if let Some(circuit) = self.breaker.circuits.lock().get_mut(self.url) {
+            circuit.probing = false;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff_grows_and_is_capped() {
+        let policy = RetryPolicy::default().with_max_backoff(Duration::from_millis(600));
+        assert_eq!(policy.backoff(0), Duration::from_millis(250));
+        assert_eq!(policy.backoff(1), Duration::from_millis(500));
+        assert_eq!(policy.backoff(2), Duration::from_millis(600));
+    }
+
+    #[test]
+    fn test_circuit_opens_and_probes() {
+        let breaker = CircuitBreaker::new(CircuitBreakerConfig {
+            failure_threshold: 2,
+            reset_timeout: Duration::ZERO,
+        });
+        let url = "http://localhost:8000";
+
+        breaker.record_failure(url);
+        assert_eq!(breaker.state(url), CircuitState::Closed);
+        breaker.record_failure(url);
+
+        // The reset timeout has expired: a single probe goes through
+        assert!(breaker.check(url).is_ok());
+        assert_eq!(breaker.state(url), CircuitState::HalfOpen);
+        assert!(breaker.check(url).is_err());
+
+        breaker.record_success(url);
+        assert_eq!(breaker.state(url), CircuitState::Closed);
+        assert!(breaker.check(url).is_ok());
+    }
+
+    #[test]
+    fn test_dropped_probe_is_released() {
+        let breaker = CircuitBreaker::new(CircuitBreakerConfig {
+            failure_threshold: 1,
+            reset_timeout: Duration::ZERO,
+        });
+        let url = "http://localhost:8000";
+
+        breaker.record_failure(url);
+        let probe = breaker.admit(url).unwrap();
+        assert!(probe.is_some());
+        assert!(breaker.check(url).is_err());
+
+        // The probe was abandoned without an outcome: the next request may probe instead
+        drop(probe);
+        assert!(breaker.check(url).is_ok());
+    }
+}
@@ -0,0 +1,261 @@
+use serde::{ Deserialize, Serialize };
+use serde_json::Value;
+
+use super::error::LLMError;
+use super::logprobs::TokenLogprob;
+
+/// Why the model stopped generating.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum StopReason {
+    /// The model produced its end-of-sequence token
+    Eos,
+    /// One of the stop words was generated
+    StopWord(String),
+    /// `max_tokens` (llama.cpp's `n_predict`) was reached
+    Length,
+    Unknown,
+}
+
+impl StopReason {
+    /// Reads llama.cpp's `stop_type`, falling back to the `stopped_*` flags of older servers.
+    pub fn from_llamacpp(json: &Value) -> Self {
+        let stopping_word = || {
+            json.get("stopping_word")
+                .and_then(|w| w.as_str())
+                .unwrap_or_default()
+                .to_string()
+        };
+        let flag = |name: &str| {
+            json.get(name)
+                .and_then(|f| f.as_bool())
+                .unwrap_or(false)
+        };
+
+        match json.get("stop_type").and_then(|t| t.as_str()) {
+            Some("eos") => StopReason::Eos,
+            Some("word") => StopReason::StopWord(stopping_word()),
+            Some("limit") => StopReason::Length,
+            _ if flag("stopped_eos") => StopReason::Eos,
+            _ if flag("stopped_word") => StopReason::StopWord(stopping_word()),
+            _ if flag("stopped_limit") => StopReason::Length,
+            _ => StopReason::Unknown,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Usage {
+    pub prompt_tokens: usize,
+    pub completion_tokens: usize,
+    /// Prompt tokens reused from the server's KV cache
+    pub cached_tokens: usize,
+}
+
+impl Usage {
+    pub fn from_llamacpp(json: &Value) -> Self {
+        let count = |name: &str| {
+            json.get(name)
+                .and_then(|n| n.as_u64())
+                .unwrap_or(0) as usize
+        };
+        Self {
+            prompt_tokens: count("tokens_evaluated"),
+            completion_tokens: count("tokens_predicted"),
+            cached_tokens: count("tokens_cached"),
+        }
+    }
+
+    pub fn total_tokens(&self) -> usize {
+        self.prompt_tokens + self.completion_tokens
+    }
+}
+
+/// Generation timings as reported by llama.cpp.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Timings {
+    pub prompt_n: f64,
+    pub prompt_ms: f64,
+    pub prompt_per_token_ms: f64,
+    pub prompt_per_second: f64,
+    pub predicted_n: f64,
+    pub predicted_ms: f64,
+    pub predicted_per_token_ms: f64,
+    pub predicted_per_second: f64,
+}
+
+impl Timings {
+    /// The server's own rate when it reports one, otherwise computed from the counts; 0.0 when
+    /// no generation time was recorded.
+    pub fn tokens_per_second(&self) -> f64 {
+        if self.predicted_per_second > 0.0 {
+            self.predicted_per_second
+        } else if self.predicted_ms > 0.0 {
+            (self.predicted_n * 1000.0) / self.predicted_ms
+        } else {
+            0.0
+        }
+    }
+}
+
+/// A completed (non-streaming) generation.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LLMResponse {
+    pub content: String,
+    pub stop_reason: StopReason,
+    pub usage: Usage,
+    pub timings: Option<Timings>,
+    pub model: Option<String>,
+    /// Per-token log-probabilities, when requested with `n_probs`
+    #[serde(default)]
+    pub logprobs: Vec<TokenLogprob>,
+    /// The server's full response, for fields not modelled here
+    pub raw: Value,
+}
+
+impl LLMResponse {
+    pub fn from_llamacpp(raw: Value) -> Result<Self, LLMError> {
+        let content = raw
+            .get("content")
+            .and_then(|c| c.as_str())
+            .ok_or_else(|| {
+                LLMError::InvalidResponse("`content` is missing or not a string".to_string())
+            })?
+            .to_string();
+
+        Ok(Self {
+            content,
+            stop_reason: StopReason::from_llamacpp(&raw),
+            usage: Usage::from_llamacpp(&raw),
+            timings: raw
+                .get("timings")
+                .and_then(|t| serde_json::from_value(t.clone()).ok()),
+            model: raw
+                .get("model")
+                .and_then(|m| m.as_str())
+                .map(|m| m.to_string()),
+            logprobs: TokenLogprob::from_llamacpp(&raw),
+            raw,
+        })
+    }
+}
+
+/// One event of a streamed generation.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub enum LLMEvent {
+    Token {
+        content: String,
+        /// Log-probabilities of the tokens making up `content`, when requested with `n_probs`
+        #[serde(skip_serializing_if = "Vec::is_empty")]
+        logprobs: Vec<TokenLogprob>,
+    },
+    Timings(Timings),
+    /// The last event of a generation
+    Done {
+        stop_reason: StopReason,
+    },
+    /// An error reported by the server in the middle of the stream
+    Error(String),
+}
+
+impl LLMEvent {
+    /// A token event without log-probabilities.
+    pub fn token(content: impl Into<String>) -> Self {
+        LLMEvent::Token { content: content.into(), logprobs: Vec::new() }
+    }
+
+    /// Parses the `data` of one llama.cpp SSE event. The final event carries the last token
+    /// (if any), the timings and the stop reason, so it becomes several `LLMEvent`s.
+    pub fn from_llamacpp(data: &str) -> Vec<LLMEvent> {
+        let json: Value = match serde_json::from_str(data) {
+            Ok(json) => json,
+            Err(e) => {
+                return vec![LLMEvent::Error(format!("Malformed event {:?}: {}", data, e))];
+            }
+        };
+
+        if let Some(error) = json.get("error") {
+            let message = error
+                .get("message")
+                .and_then(|m| m.as_str())
+                .map(|m| m.to_string())
+                .unwrap_or_else(|| error.to_string());
+            return vec![LLMEvent::Error(message)];
+        }
+
+        let mut events = Vec::new();
+        if let Some(content) = json.get("content").and_then(|c| c.as_str()) {
+            if !content.is_empty() {
+                events.push(LLMEvent::Token {
+                    content: content.to_string(),
+                    logprobs: TokenLogprob::from_llamacpp(&json),
+                });
+            }
+        }
+        let stop = json
+            .get("stop")
+            .and_then(|s| s.as_bool())
+            .unwrap_or(false);
+        if stop {
+            if let Some(timings) = json.get("timings") {
+                if let Ok(timings) = serde_json::from_value(timings.clone()) {
+                    events.push(LLMEvent::Timings(timings));
+                }
+            }
+            events.push(LLMEvent::Done { stop_reason: StopReason::from_llamacpp(&json) });
+        }
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_parse_llamacpp_response() {
+        let response = LLMResponse::from_llamacpp(
+            json!({
+                "content": "Paris",
+                "model": "qwen-7b",
+                "stop": true,
+                "stop_type": "word",
+                "stopping_word": "<|im_end|>",
+                "tokens_predicted": 3,
+                "tokens_evaluated": 12,
+                "tokens_cached": 4,
+                "timings": { "predicted_n": 3, "predicted_ms": 60.0 },
+                "id_slot": 0
+            })
+        ).unwrap();
+
+        assert_eq!(response.content, "Paris");
+        assert_eq!(response.stop_reason, StopReason::StopWord("<|im_end|>".to_string()));
+        assert_eq!(response.usage.total_tokens(), 15);
+        assert_eq!(response.usage.cached_tokens, 4);
+        assert_eq!(response.timings.unwrap().tokens_per_second(), 50.0);
+        assert_eq!(response.model.as_deref(), Some("qwen-7b"));
+        assert_eq!(response.raw["id_slot"], 0);
+    }
+
+    #[test]
+    fn test_tokens_per_second() {
+        let reported = Timings { predicted_per_second: 42.0, ..Default::default() };
+        assert_eq!(reported.tokens_per_second(), 42.0);
+
+        let instant = Timings { predicted_n: 3.0, predicted_ms: 0.0, ..Default::default() };
+        assert_eq!(instant.tokens_per_second(), 0.0);
+        assert_eq!(Timings::default().tokens_per_second(), 0.0);
+    }
+
+    #[test]
+    fn test_legacy_stop_flags() {
+        let response = LLMResponse::from_llamacpp(
+            json!({ "content": "", "stopped_limit": true })
+        ).unwrap();
+        assert_eq!(response.stop_reason, StopReason::Length);
+
+        assert!(LLMResponse::from_llamacpp(json!({ "error": "oops" })).is_err());
+    }
+}
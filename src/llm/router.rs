@@ -0,0 +1,362 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::time::Duration;
+
+use log::{ info, warn };
+
+use super::chat::ChatMessage;
+use super::context::{ TokenCounter, PART_OVERHEAD_TOKENS };
+use super::error::LLMError;
+use super::llm_builder::LLM;
+use super::provider::ProviderRequest;
+use super::response::LLMResponse;
+use super::types::{ AccumulatedStream, EventStream };
+use crate::error::BoxError;
+
+type Attempt<'a, T> = Pin<Box<dyn Future<Output = Result<T, BoxError>> + Send + 'a>>;
+
+/// A model an `LLMRouter` can send requests to.
+#[derive(Clone)]
+pub struct RouteTarget {
+    pub name: String,
+    pub llm: LLM,
+    /// What the model is suited for, e.g. `vision` or `code`
+    pub capabilities: Vec<String>,
+    /// Relative price of a request, lower is cheaper
+    pub cost_tier: u32,
+}
+
+impl RouteTarget {
+    pub fn new(name: &str, llm: LLM) -> Self {
+        Self { name: name.to_string(), llm, capabilities: Vec::new(), cost_tier: 0 }
+    }
+
+    pub fn with_capabilities(mut self, capabilities: &[&str]) -> Self {
+        self.capabilities = capabilities
+            .iter()
+            .map(|capability| capability.to_string())
+            .collect();
+        self
+    }
+
+    pub fn with_cost_tier(mut self, cost_tier: u32) -> Self {
+        self.cost_tier = cost_tier;
+        self
+    }
+
+    fn has_capability(&self, capability: &str) -> bool {
+        self.capabilities.iter().any(|c| c.eq_ignore_ascii_case(capability))
+    }
+}
+
+/// Narrows the models a request may go to.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RouteHints {
+    /// Only models with this capability are tried
+    pub capability: Option<String>,
+    /// Only models at or below this cost tier are tried
+    pub max_cost_tier: Option<u32>,
+}
+
+impl RouteHints {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_capability(mut self, capability: &str) -> Self {
+        self.capability = Some(capability.to_string());
+        self
+    }
+
+    pub fn with_max_cost_tier(mut self, max_cost_tier: u32) -> Self {
+        self.max_cost_tier = Some(max_cost_tier);
+        self
+    }
+}
+
+/// A model that was passed over, and why.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RouteFailure {
+    pub model: String,
+    pub reason: String,
+}
+
+/// The answer to a routed request, and the model that gave it.
+pub struct Routed<T> {
+    pub model: String,
+    pub output: T,
+    /// Models tried or skipped before `model`, in order
+    pub failures: Vec<RouteFailure>,
+}
+
+/// Sends each request to the first of several `LLM`s able to answer it.
+///
+/// Models are tried in the order they were added, so the cheapest or fastest one goes first.
+/// A model is skipped when it lacks the capability the hints ask for, costs more than they
+/// allow, or when the prompt does not fit its context window (for `LLM`s whose options set
+/// `context_size`). A model that fails or times out hands the request to the next one. Each
+/// answer names the model that produced it.
+///
+/// Streams fall back only while opening; once events flow, errors reach the caller.
+///
+/// The router's hints apply to every request unless one of the `_with_hints` methods passes
+/// others. Wherever an `LLM` is expected, e.g. by `AgentBuilder::with_llm` or in a chain,
+/// use `LLM::builder().with_router(router)`.
+///
+/// # Usage
+/// ```rust,ignore
+/// let small = manager.get_or_create_llm("smolTalk", None, true).await?;
+/// let large = manager.get_or_create_llm("qwen-7b", None, true).await?;
+/// let router = LLMRouter::new()
+///     .with_model(RouteTarget::new("smolTalk", small))
+///     .with_model(RouteTarget::new("qwen-7b", large).with_cost_tier(1))
+///     .with_attempt_timeout(Duration::from_secs(30));
+///
+/// let answer = router.response(&document, "Summarize the document").await?;
+/// println!("{} answered: {}", answer.model, answer.output.content);
+///
+/// let vision = RouteHints::new().with_capability("vision");
+/// let answer = router.chat_with_hints(&messages, &vision).await?;
+/// ```
+#[derive(Clone, Default)]
+pub struct LLMRouter {
+    targets: Vec<RouteTarget>,
+    hints: RouteHints,
+    attempt_timeout: Option<Duration>,
+}
+
+impl LLMRouter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_model(mut self, target: RouteTarget) -> Self {
+        self.targets.push(target);
+        self
+    }
+
+    /// Hints applied to requests that do not pass their own.
+    pub fn with_hints(mut self, hints: RouteHints) -> Self {
+        self.hints = hints;
+        self
+    }
+
+    pub fn hints(&self) -> &RouteHints {
+        &self.hints
+    }
+
+    /// How long one model may take before the next one is tried. For streams this covers
+    /// opening the stream only.
+    pub fn with_attempt_timeout(mut self, timeout: Duration) -> Self {
+        self.attempt_timeout = Some(timeout);
+        self
+    }
+
+    /// Names of the models, in the order they are tried.
+    pub fn models(&self) -> Vec<&str> {
+        self.targets
+            .iter()
+            .map(|target| target.name.as_str())
+            .collect()
+    }
+
+    pub async fn response(
+        &self,
+        prompt_with_context: &str,
+        system_prompt: &str
+    ) -> Result<Routed<LLMResponse>, BoxError> {
+        self.response_with_hints(prompt_with_context, system_prompt, &self.hints).await
+    }
+
+    /// Like `response`, routed by `hints` instead of the router's own.
+    pub async fn response_with_hints(
+        &self,
+        prompt_with_context: &str,
+        system_prompt: &str,
+        hints: &RouteHints
+    ) -> Result<Routed<LLMResponse>, BoxError> {
+        self.complete(prompt(prompt_with_context, system_prompt), hints).await
+    }
+
+    pub async fn chat(&self, messages: &[ChatMessage]) -> Result<Routed<LLMResponse>, BoxError> {
+        self.chat_with_hints(messages, &self.hints).await
+    }
+
+    /// Like `chat`, routed by `hints` instead of the router's own.
+    pub async fn chat_with_hints(
+        &self,
+        messages: &[ChatMessage],
+        hints: &RouteHints
+    ) -> Result<Routed<LLMResponse>, BoxError> {
+        self.complete(ProviderRequest::Chat { messages, tools: &[] }, hints).await
+    }
+
+    pub async fn chat_with_tools(
+        &self,
+        messages: &[ChatMessage],
+        tools: &[serde_json::Value]
+    ) -> Result<Routed<LLMResponse>, BoxError> {
+        self.complete(ProviderRequest::Chat { messages, tools }, &self.hints).await
+    }
+
+    pub async fn stream(
+        &self,
+        prompt_with_context: &str,
+        system_prompt: &str
+    ) -> Result<Routed<EventStream>, BoxError> {
+        self.stream_with_hints(prompt_with_context, system_prompt, &self.hints).await
+    }
+
+    /// Like `stream`, routed by `hints` instead of the router's own.
+    pub async fn stream_with_hints(
+        &self,
+        prompt_with_context: &str,
+        system_prompt: &str,
+        hints: &RouteHints
+    ) -> Result<Routed<EventStream>, BoxError> {
+        self.stream_events(prompt(prompt_with_context, system_prompt), hints).await
+    }
+
+    pub async fn stream_chat(
+        &self,
+        messages: &[ChatMessage]
+    ) -> Result<Routed<EventStream>, BoxError> {
+        self.stream_chat_with_hints(messages, &self.hints).await
+    }
+
+    /// Like `stream_chat`, routed by `hints` instead of the router's own.
+    pub async fn stream_chat_with_hints(
+        &self,
+        messages: &[ChatMessage],
+        hints: &RouteHints
+    ) -> Result<Routed<EventStream>, BoxError> {
+        self.stream_events(ProviderRequest::Chat { messages, tools: &[] }, hints).await
+    }
+
+    // Boxed rather than `async fn`, as `LLM` calls back into these when it wraps a router and
+    // the compiler cannot tell such recursive futures are `Send`.
+    pub(crate) fn complete<'a>(
+        &'a self,
+        request: ProviderRequest<'a>,
+        hints: &'a RouteHints
+    ) -> Attempt<'a, Routed<LLMResponse>> {
+        Box::pin(self.route(request, hints, move |llm| Box::pin(llm.complete(request))))
+    }
+
+    pub(crate) fn stream_events<'a>(
+        &'a self,
+        request: ProviderRequest<'a>,
+        hints: &'a RouteHints
+    ) -> Attempt<'a, Routed<EventStream>> {
+        Box::pin(self.route(request, hints, move |llm| Box::pin(llm.stream_events(request))))
+    }
+
+    pub(crate) fn stream_bytes<'a>(
+        &'a self,
+        request: ProviderRequest<'a>,
+        hints: &'a RouteHints
+    ) -> Attempt<'a, Routed<AccumulatedStream>> {
+        Box::pin(self.route(request, hints, move |llm| Box::pin(llm.stream_bytes(request))))
+    }
+
+    /// Tries the models allowed by `hints` in order, skipping those whose context window is
+    /// too small for the request, until `call` succeeds.
+    async fn route<'a, T>(
+        &'a self,
+        request: ProviderRequest<'a>,
+        hints: &RouteHints,
+        call: impl Fn(&'a LLM) -> Attempt<'a, T>
+    ) -> Result<Routed<T>, BoxError> {
+        let texts = texts(&request);
+        let mut failures = Vec::new();
+
+        for target in self.targets.iter().filter(|target| allows(hints, target)) {
+            if let Some(budget) = target.llm.context_budget() {
+                let needed = count_tokens(&target.llm, &texts).await;
+                if needed > budget.available() {
+                    failures.push(RouteFailure {
+                        model: target.name.clone(),
+                        reason: format!(
+                            "Prompt needs {} tokens but only {} fit",
+                            needed,
+                            budget.available()
+                        ),
+                    });
+                    continue;
+                }
+            }
+
+            let attempt = call(&target.llm);
+            let result = match self.attempt_timeout {
+                Some(limit) =>
+                    tokio::time::timeout(limit, attempt).await.unwrap_or_else(|_| {
+                        Err(Box::new(LLMError::Timeout(format!("No answer within {:?}", limit))))
+                    }),
+                None => attempt.await,
+            };
+
+            match result {
+                Ok(output) => {
+                    if !failures.is_empty() {
+                        info!("{} answered after {} other models", target.name, failures.len());
+                    }
+                    return Ok(Routed { model: target.name.clone(), output, failures });
+                }
+                Err(e) => {
+                    warn!("{} failed, trying the next model: {}", target.name, e);
+                    failures.push(RouteFailure {
+                        model: target.name.clone(),
+                        reason: e.to_string(),
+                    });
+                }
+            }
+        }
+
+        let message = if failures.is_empty() {
+            format!("No model matches {:?}", hints)
+        } else {
+            failures
+                .iter()
+                .map(|failure| format!("{}: {}", failure.model, failure.reason))
+                .collect::<Vec<_>>()
+                .join("; ")
+        };
+        Err(Box::new(LLMError::RoutingFailed(message)))
+    }
+}
+
+fn prompt<'a>(prompt_with_context: &'a str, system_prompt: &'a str) -> ProviderRequest<'a> {
+    ProviderRequest::Prompt { system_prompt, user_prompt: prompt_with_context, images: &[] }
+}
+
+fn allows(hints: &RouteHints, target: &RouteTarget) -> bool {
+    let capable = hints.capability
+        .as_deref()
+        .is_none_or(|capability| target.has_capability(capability));
+    let affordable = hints.max_cost_tier.is_none_or(|max| target.cost_tier <= max);
+    capable && affordable
+}
+
+/// The prompt text of `request`.
+fn texts<'a>(request: &ProviderRequest<'a>) -> Vec<&'a str> {
+    match *request {
+        ProviderRequest::Prompt { system_prompt, user_prompt, .. } => {
+            vec![system_prompt, user_prompt]
+        }
+        ProviderRequest::Chat { messages, .. } => {
+            messages
+                .iter()
+                .map(|message| message.content.as_str())
+                .collect()
+        }
+    }
+}
+
+/// Prompt size as `ContextBudget` counts it.
+async fn count_tokens(counter: &dyn TokenCounter, texts: &[&str]) -> usize {
+    let mut total = 0;
+    for text in texts {
+        total += counter.count_tokens(text).await + PART_OVERHEAD_TOKENS;
+    }
+    total
+}
@@ -0,0 +1,137 @@
+use serde::{ Deserialize, Serialize };
+
+use super::chat::ChatMessage;
+use super::error::LLMError;
+use super::llm_builder::LLM;
+use super::response::LLMResponse;
+use crate::error::BoxError;
+
+/// A slot's KV cache as saved to or restored from a file in the server's `--slot-save-path`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SlotFile {
+    pub filename: String,
+    pub n_tokens: usize,
+}
+
+/// A conversation pinned to one llama-server slot, created with `LLM::session`.
+///
+/// Every request runs in the same slot with `cache_prompt`, so the server only processes
+/// what was added since the previous request instead of the whole history. A long system
+/// prompt or document can be `prefill`ed once, saved with `save`, and restored into a slot
+/// later to answer many questions about it.
+///
+/// # Usage
+/// ```rust,ignore
+/// let mut session = llm.session(0)?;
+/// session.prefill(&format!("Answer questions about this report:\n{}", report)).await?;
+/// session.save("report.bin").await?;
+///
+/// let answer = session.ask("What was the revenue in 2023?").await?;
+/// ```
+pub struct LLMSession {
+    llm: LLM,
+    id_slot: usize,
+    /// Prefilled messages every question is asked after
+    context: Vec<ChatMessage>,
+    /// Turns added by `chat`
+    history: Vec<ChatMessage>,
+}
+
+impl LLMSession {
+    pub(crate) fn new(llm: LLM, id_slot: usize) -> Self {
+        Self { llm, id_slot, context: Vec::new(), history: Vec::new() }
+    }
+
+    pub fn id_slot(&self) -> usize {
+        self.id_slot
+    }
+
+    /// The `LLM` pinned to the session's server and slot.
+    pub fn llm(&self) -> &LLM {
+        &self.llm
+    }
+
+    /// The prefilled context followed by the conversation so far.
+    pub fn messages(&self) -> Vec<ChatMessage> {
+        self.context.iter().chain(&self.history).cloned().collect()
+    }
+
+    /// Sets the context without sending it, e.g. after `restore`: questions then reuse the
+    /// restored cache as long as the context renders to the same prompt.
+    pub fn with_context(mut self, context: Vec<ChatMessage>) -> Self {
+        self.context = context;
+        self
+    }
+
+    /// Processes `system_prompt` into the slot's cache, generating a single token, and makes
+    /// it the context of every following question. The conversation is cleared.
+    pub async fn prefill(&mut self, system_prompt: &str) -> Result<LLMResponse, BoxError> {
+        self.context = vec![ChatMessage::system(system_prompt)];
+        self.history.clear();
+
+        let mut llm = self.llm.clone();
+        llm.set_options(llm.options().clone().with_max_tokens(1));
+        llm.chat(&self.context).await
+    }
+
+    /// Asks a one-off question after the context; the conversation is left unchanged.
+    pub async fn ask(&self, question: &str) -> Result<LLMResponse, BoxError> {
+        let mut messages = self.context.clone();
+        messages.push(ChatMessage::user(question));
+        self.llm.chat(&messages).await
+    }
+
+    /// Adds a turn to the conversation.
+    pub async fn chat(&mut self, message: &str) -> Result<LLMResponse, BoxError> {
+        let mut messages = self.messages();
+        messages.push(ChatMessage::user(message));
+        let response = self.llm.chat(&messages).await?;
+
+        self.history.push(ChatMessage::user(message));
+        self.history.push(ChatMessage::assistant(response.content.clone()));
+        Ok(response)
+    }
+
+    /// Forgets the conversation, keeping the context.
+    pub fn reset(&mut self) {
+        self.history.clear();
+    }
+
+    /// Saves the slot's cache to `filename` in the server's `--slot-save-path`.
+    pub async fn save(&self, filename: &str) -> Result<SlotFile, LLMError> {
+        let body = self.llm.slot_action(self.id_slot, "save", Some(filename)).await?;
+        Ok(SlotFile {
+            filename: filename.to_string(),
+            n_tokens: body["n_saved"].as_u64().unwrap_or_default() as usize,
+        })
+    }
+
+    /// Loads a cache saved with `save` into the slot.
+    pub async fn restore(&self, filename: &str) -> Result<SlotFile, LLMError> {
+        let body = self.llm.slot_action(self.id_slot, "restore", Some(filename)).await?;
+        Ok(SlotFile {
+            filename: filename.to_string(),
+            n_tokens: body["n_restored"].as_u64().unwrap_or_default() as usize,
+        })
+    }
+
+    /// Empties the slot's cache, returning the number of tokens dropped.
+    pub async fn erase(&self) -> Result<usize, LLMError> {
+        let body = self.llm.slot_action(self.id_slot, "erase", None).await?;
+        Ok(body["n_erased"].as_u64().unwrap_or_default() as usize)
+    }
+}
+
+/// llama-server only accepts plain file names inside its `--slot-save-path`.
+pub(crate) fn validate_slot_filename(filename: &str) -> Result<(), LLMError> {
+    let valid =
+        !filename.is_empty() &&
+        filename != "." &&
+        filename != ".." &&
+        !filename.contains(['/', '\\', ':', '\0']);
+    if valid {
+        Ok(())
+    } else {
+        Err(LLMError::InvalidOptions(format!("Invalid slot file name: {:?}", filename)))
+    }
+}
@@ -1,94 +1,434 @@
+use std::collections::VecDeque;
 use std::pin::Pin;
 
-use log::info;
+use log::{ info, warn };
 use bytes::Bytes;
 
-// use serde_json::json;
-use serde::Deserialize;
-
 use futures::{ Stream, StreamExt }; // Ensure StreamExt is imported
-use serde_json::Value;
+use serde_json::json;
+
+use super::error::LLMError;
+use super::response::{ LLMEvent, StopReason, Timings };
+use super::types::{ CancellationToken, EventStream };
 
 type StreamResult = Result<Bytes, reqwest::Error>;
 type BoxedStream = Pin<Box<dyn Stream<Item = StreamResult> + Send>>;
 
-#[allow(dead_code)]
-#[derive(Debug, Deserialize)]
-struct LLMGenerattionTimings {
-    predicted_ms: f64,
-    predicted_n: f64,
-    predicted_per_second: f64,
-    predicted_per_token_ms: f64,
-    prompt_ms: f64,
-    prompt_n: f64,
-    prompt_per_second: f64,
-    prompt_per_token_ms: f64,
+/// Incremental decoder for `text/event-stream` bodies.
+///
+/// HTTP chunks can end anywhere: in the middle of a `data:` line or even inside a multi-byte
+/// UTF-8 character. Bytes are buffered until a line is complete, and an event's data is only
+/// returned once the blank line ending the event has arrived.
+#[derive(Debug, Default)]
+pub struct SseDecoder {
+    buffer: Vec<u8>,
+    data: Vec<String>,
+}
+
+impl SseDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Feeds the next chunk, returning the data of every event it completes.
+    pub fn push(&mut self, chunk: &[u8]) -> Vec<String> {
+        self.buffer.extend_from_slice(chunk);
+
+        let mut events = Vec::new();
+        // `\n` never occurs inside a multi-byte character, so complete lines are valid UTF-8
+        while let Some(newline) = self.buffer.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = self.buffer.drain(..=newline).collect();
+            self.process_line(&line[..newline], &mut events);
+        }
+        events
+    }
+
+    /// Flushes a last event that was not terminated by a blank line.
+    pub fn finish(&mut self) -> Vec<String> {
+        let mut events = Vec::new();
+        if !self.buffer.is_empty() {
+            let line = std::mem::take(&mut self.buffer);
+            self.process_line(&line, &mut events);
+        }
+        self.dispatch(&mut events);
+        events
+    }
+
+    fn process_line(&mut self, line: &[u8], events: &mut Vec<String>) {
+        let line = String::from_utf8_lossy(line);
+        let line = line.strip_suffix('\r').unwrap_or(&line);
+
+        if line.is_empty() {
+            self.dispatch(events);
+            return;
+        }
+        // Comments, e.g. keep-alives
+        if line.starts_with(':') {
+            return;
+        }
+
+        let (field, value) = match line.split_once(':') {
+            Some((field, value)) => (field, value.strip_prefix(' ').unwrap_or(value)),
+            None => (line, ""),
+        };
+        if field == "data" {
+            self.data.push(value.to_string());
+        }
+    }
+
+    fn dispatch(&mut self, events: &mut Vec<String>) {
+        if !self.data.is_empty() {
+            events.push(self.data.join("\n"));
+            self.data.clear();
+        }
+    }
+}
+
+/// Splits a streamed response body into messages.
+pub trait FrameDecoder: Send + 'static {
+    /// Feeds the next chunk, returning every message it completes.
+    fn push(&mut self, chunk: &[u8]) -> Vec<String>;
+    /// Flushes what is left once the body has ended.
+    fn finish(&mut self) -> Vec<String>;
 }
 
-pub fn llamacpp_process_stream<'a>(stream: BoxedStream) -> BoxedStream {
+impl FrameDecoder for SseDecoder {
+    fn push(&mut self, chunk: &[u8]) -> Vec<String> {
+        SseDecoder::push(self, chunk)
+    }
+
+    fn finish(&mut self) -> Vec<String> {
+        SseDecoder::finish(self)
+    }
+}
+
+/// Decoder for newline-delimited JSON bodies, as streamed by Ollama.
+#[derive(Debug, Default)]
+pub struct JsonLinesDecoder {
+    buffer: Vec<u8>,
+}
+
+impl JsonLinesDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn line(bytes: &[u8]) -> Option<String> {
+        let line = String::from_utf8_lossy(bytes).trim().to_string();
+        (!line.is_empty()).then_some(line)
+    }
+}
+
+impl FrameDecoder for JsonLinesDecoder {
+    fn push(&mut self, chunk: &[u8]) -> Vec<String> {
+        self.buffer.extend_from_slice(chunk);
+
+        let mut lines = Vec::new();
+        while let Some(newline) = self.buffer.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = self.buffer.drain(..=newline).collect();
+            lines.extend(Self::line(&line));
+        }
+        lines
+    }
+
+    fn finish(&mut self) -> Vec<String> {
+        Self::line(&std::mem::take(&mut self.buffer)).into_iter().collect()
+    }
+}
+
+struct DecodeState<D, P, T, F, E> {
+    stream: BoxedStream,
+    decoder: D,
+    parse: P,
+    pending: VecDeque<T>,
+    finished: bool,
+    on_event: F,
+    on_error: E,
+}
+
+/// Splits the body with `decoder`, parses each message into `LLMEvent`s with `parse` and
+/// maps them with `on_event`; events mapped to `None` are skipped.
+fn decode_stream<D, P, T, F, E>(
+    stream: BoxedStream,
+    decoder: D,
+    parse: P,
+    on_event: F,
+    on_error: E
+) -> Pin<Box<dyn Stream<Item = T> + Send>>
+    where
+        D: FrameDecoder,
+        P: Fn(&str) -> Vec<LLMEvent> + Send + 'static,
+        T: Send + 'static,
+        F: Fn(LLMEvent) -> Option<T> + Send + 'static,
+        E: Fn(reqwest::Error) -> T + Send + 'static
+{
+    let state = DecodeState {
+        stream,
+        decoder,
+        parse,
+        pending: VecDeque::new(),
+        finished: false,
+        on_event,
+        on_error,
+    };
+
     Box::pin(
-        futures::stream::unfold((stream, String::new()), |(mut stream, acc)| async move {
-            if let Some(chunk_result) = stream.next().await {
-                match chunk_result {
-                    Ok(chunk) => {
-                        if let Ok(chunk_str) = std::str::from_utf8(&chunk) {
-                            let content_to_stream = process_chunk(chunk_str).await;
-
-                            if !content_to_stream.is_empty() {
-                                return Some((Ok(Bytes::from(content_to_stream)), (stream, acc)));
-                            }
-                        } else {
-                            eprintln!("Failed to parse chunk as UTF-8");
-                        }
+        futures::stream::unfold(state, |mut state| async move {
+            loop {
+                if let Some(item) = state.pending.pop_front() {
+                    return Some((item, state));
+                }
+                if state.finished {
+                    return None;
+                }
+
+                let messages = match state.stream.next().await {
+                    Some(Ok(chunk)) => state.decoder.push(&chunk),
+                    Some(Err(e)) => {
+                        warn!("Error receiving chunk: {}", e);
+                        return Some(((state.on_error)(e), state));
                     }
-                    Err(e) => {
-                        eprintln!("Error receiving chunk: {}", e);
-                        return Some((Err(e), (stream, acc)));
+                    None => {
+                        state.finished = true;
+                        state.decoder.finish()
                     }
+                };
+
+                for message in messages {
+                    let items: Vec<T> = (state.parse)(&message)
+                        .into_iter()
+                        .filter_map(&state.on_event)
+                        .collect();
+                    state.pending.extend(items);
+                }
+            }
+        })
+    )
+}
+
+/// Typed events of a streamed response, split by `decoder` and parsed by `parse`.
+pub fn decode_events<D, P>(stream: BoxedStream, decoder: D, parse: P) -> EventStream
+    where D: FrameDecoder, P: Fn(&str) -> Vec<LLMEvent> + Send + 'static
+{
+    decode_stream(
+        stream,
+        decoder,
+        parse,
+        |event| Some(Ok(event)),
+        |e| Err(LLMError::RequestFailed(e.to_string()))
+    )
+}
+
+/// Typed events of a llama.cpp `/completion` stream.
+pub fn llamacpp_events(stream: BoxedStream) -> EventStream {
+    decode_events(stream, SseDecoder::new(), LLMEvent::from_llamacpp)
+}
+
+/// Writes `events` back as a streamed body, `encode` giving the text of each event. Timings
+/// are held back and passed with the `Done` event that follows them, as servers report both
+/// in their last message.
+pub fn encode_events<F>(events: EventStream, encode: F) -> BoxedStream
+    where F: Fn(Result<LLMEvent, LLMError>, Option<Timings>) -> String + Send + 'static
+{
+    let mut timings = None;
+    Box::pin(
+        events.filter_map(move |event| {
+            let frame = match event {
+                Ok(LLMEvent::Timings(t)) => {
+                    timings = Some(t);
+                    None
+                }
+                Ok(done @ LLMEvent::Done { .. }) => Some(encode(Ok(done), timings.take())),
+                event => Some(encode(event, None)),
+            };
+            futures::future::ready(frame.map(|frame| Ok(Bytes::from(frame))))
+        })
+    )
+}
+
+/// A llama.cpp `/completion` stream carrying `events`, the inverse of `llamacpp_events`.
+pub fn llamacpp_body(events: EventStream) -> BoxedStream {
+    encode_events(events, |event, timings| {
+        let frame = match event {
+            Ok(LLMEvent::Token { content, logprobs }) => {
+                let mut frame = json!({ "content": content, "stop": false });
+                if !logprobs.is_empty() {
+                    frame["completion_probabilities"] = json!(logprobs);
                 }
-            } else {
-                return None;
+                frame
             }
+            Ok(LLMEvent::Done { stop_reason }) => {
+                let (stop_type, stopping_word) = match stop_reason {
+                    StopReason::Eos => ("eos", String::new()),
+                    StopReason::StopWord(word) => ("word", word),
+                    StopReason::Length => ("limit", String::new()),
+                    StopReason::Unknown => ("none", String::new()),
+                };
+                let mut frame = json!({
+                    "content": "",
+                    "stop": true,
+                    "stop_type": stop_type,
+                    "stopping_word": stopping_word,
+                });
+                if let Some(timings) = timings {
+                    frame["timings"] = json!(timings);
+                }
+                frame
+            }
+            Ok(LLMEvent::Error(message)) => json!({ "error": { "message": message } }),
+            Ok(LLMEvent::Timings(_)) => unreachable!("timings are passed with Done"),
+            Err(e) => json!({ "error": { "message": e.to_string() } }),
+        };
+        format!("data: {}\n\n", frame)
+    })
+}
 
-            Some((Ok(Bytes::new()), (stream, acc)))
+/// Reduces a llama.cpp `/completion` stream to the generated text.
+pub fn llamacpp_process_stream(stream: BoxedStream) -> BoxedStream {
+    decode_stream(
+        stream,
+        SseDecoder::new(),
+        LLMEvent::from_llamacpp,
+        |event| {
+            match event {
+                LLMEvent::Token { content, .. } => Some(Ok(Bytes::from(content))),
+                LLMEvent::Timings(timings) => {
+                    info!("Tokens generated per second: {:.2}", timings.tokens_per_second());
+                    None
+                }
+                LLMEvent::Error(message) => {
+                    warn!("Server reported an error while streaming: {}", message);
+                    None
+                }
+                LLMEvent::Done { .. } => None,
+            }
+        },
+        Err
+    )
+}
+
+/// Yields items of `stream` until `token` is cancelled, then `on_cancel` if given. The inner
+/// stream, and with it the connection, is dropped right away so the server stops generating.
+fn until_cancelled<T: Send + 'static>(
+    stream: Pin<Box<dyn Stream<Item = T> + Send>>,
+    token: CancellationToken,
+    on_cancel: Option<T>
+) -> Pin<Box<dyn Stream<Item = T> + Send>> {
+    let state = (Some(stream), token, on_cancel);
+    Box::pin(
+        futures::stream::unfold(state, |(stream, token, on_cancel)| async move {
+            let mut stream = stream?;
+            tokio::select! {
+                biased;
+                _ = token.cancelled() => on_cancel.map(|item| (item, (None, token, None))),
+                item = stream.next() => item.map(|item| (item, (Some(stream), token, on_cancel))),
+            }
         })
     )
 }
 
+/// Ends an event stream with `LLMError::Cancelled` once `token` is cancelled.
+pub fn cancellable_events(stream: EventStream, token: CancellationToken) -> EventStream {
+    until_cancelled(stream, token, Some(Err(LLMError::Cancelled)))
+}
+
+/// Ends a byte stream once `token` is cancelled.
+pub fn cancellable_bytes(stream: BoxedStream, token: CancellationToken) -> BoxedStream {
+    until_cancelled(stream, token, None)
+}
+
 pub fn qwen_process_stream(stream: BoxedStream) -> BoxedStream {
     // For now, using the same implementation as llamacpp
     llamacpp_process_stream(stream)
 }
 
-async fn process_chunk(chunk_str: &str) -> String {
-    let mut content_to_stream = String::new();
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::llm::response::StopReason;
+    use proptest::prelude::*;
+    use serde_json::json;
 
-    for line in chunk_str.lines() {
-        if line.starts_with("data: ") {
-            if let Ok(json_data) = serde_json::from_str::<Value>(&line[6..]) {
-                if let Some(content) = json_data.get("content").and_then(|c| c.as_str()) {
-                    content_to_stream.push_str(content); // Stream content
-                }
-                if let Some(timings) = json_data.get("timings") {
-                    if
-                        let Ok(timing_struct) = serde_json::from_value::<LLMGenerattionTimings>(
-                            timings.clone()
-                        )
-                    {
-                        let tokens_per_second = calculate_tokens_per_second(
-                            timing_struct.predicted_n,
-                            timing_struct.predicted_ms
-                        );
-                        info!("Tokens generated per second: {:.2}", tokens_per_second);
-                    }
-                }
-            }
+    fn sse_body(tokens: &[String]) -> Vec<u8> {
+        let mut body = String::new();
+        for token in tokens {
+            body.push_str(&format!("data: {}\n\n", json!({ "content": token, "stop": false })));
         }
+        let last = json!({
+            "content": "",
+            "stop": true,
+            "stop_type": "limit",
+            "timings": { "predicted_n": 2 }
+        });
+        body.push_str(&format!("data: {}\n\n", last));
+        body.into_bytes()
     }
-    content_to_stream
-}
 
-fn calculate_tokens_per_second(predicted_n: f64, predicted_ms: f64) -> f64 {
-    let predicted_seconds = predicted_ms / 1000.0;
-    predicted_n / predicted_seconds
+    fn decode(chunks: Vec<Vec<u8>>) -> Vec<LLMEvent> {
+        let stream: BoxedStream = Box::pin(
+            futures::stream::iter(chunks.into_iter().map(|chunk| Ok(Bytes::from(chunk))))
+        );
+        futures::executor::block_on(
+            llamacpp_events(stream)
+                .map(|event| event.unwrap())
+                .collect()
+        )
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_stream_ends_with_error() {
+        let token = CancellationToken::new();
+        let pending: EventStream = Box::pin(futures::stream::pending());
+        let mut events = cancellable_events(pending, token.clone());
+
+        token.cancel();
+        assert!(matches!(events.next().await, Some(Err(LLMError::Cancelled))));
+        assert!(events.next().await.is_none());
+    }
+
+    #[test]
+    fn test_decoder_handles_crlf_comments_and_multiline_data() {
+        let mut decoder = SseDecoder::new();
+        let events = decoder.push(b": keep-alive\r\ndata: first\r\ndata: second\r\n\r\ndata: x");
+        assert_eq!(events, vec!["first\nsecond".to_string()]);
+        assert_eq!(decoder.finish(), vec!["x".to_string()]);
+    }
+
+    #[test]
+    fn test_events_end_with_timings_and_done() {
+        let tokens = vec!["Hi".to_string(), " there".to_string()];
+        let events = decode(vec![sse_body(&tokens)]);
+
+        assert_eq!(events[0], LLMEvent::token("Hi"));
+        assert!(matches!(events[2], LLMEvent::Timings(_)));
+        assert_eq!(events[3], LLMEvent::Done { stop_reason: StopReason::Length });
+    }
+
+    proptest! {
+        #[test]
+        fn test_arbitrary_chunk_boundaries(
+            tokens in prop::collection::vec("[a-z \u{e9}\u{65e5}\u{672c}\u{1f980}\"\\\\]{1,6}", 1..20),
+            cuts in prop::collection::vec(any::<prop::sample::Index>(), 0..12)
+        ) {
+            let body = sse_body(&tokens);
+            let mut cuts: Vec<usize> = cuts.iter().map(|cut| cut.index(body.len())).collect();
+            cuts.push(body.len());
+            cuts.sort_unstable();
+
+            let mut chunks = Vec::new();
+            let mut start = 0;
+            for cut in cuts {
+                chunks.push(body[start..cut].to_vec());
+                start = cut;
+            }
+
+            let events = decode(chunks);
+            let expected: Vec<LLMEvent> = tokens
+                .iter()
+                .map(|token| LLMEvent::token(token.clone()))
+                .collect();
+            prop_assert_eq!(&events[..tokens.len()], &expected[..]);
+            prop_assert_eq!(events.len(), tokens.len() + 2);
+        }
+    }
 }
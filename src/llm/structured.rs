@@ -0,0 +1,77 @@
+use schemars::JsonSchema;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+/// How many times `LLM::generate_structured` asks again after an answer fails to parse.
+pub const STRUCTURED_OUTPUT_RETRIES: usize = 2;
+
+/// JSON Schema of `T`, as passed to llama.cpp's `json_schema`.
+pub fn schema_for<T: JsonSchema>() -> Value {
+    schemars::schema_for!(T).to_value()
+}
+
+/// Parses a structured answer. Grammar-constrained output is bare JSON, but models behind
+/// other providers like to wrap it in a Markdown code fence.
+pub fn parse_structured<T: DeserializeOwned>(output: &str) -> Result<T, serde_json::Error> {
+    let output = output.trim();
+    let unfenced = output
+        .strip_prefix("```json")
+        .or_else(|| output.strip_prefix("```"))
+        .and_then(|rest| rest.trim_end().strip_suffix("```"))
+        .unwrap_or(output);
+    serde_json::from_str(unfenced.trim())
+}
+
+/// The system prompt for a structured request: the caller's prompt and the schema to follow.
+pub fn structured_system_prompt(system_prompt: &str, schema: &Value) -> String {
+    let instructions = format!(
+        "Respond only with a JSON value that matches this JSON Schema:\n{}",
+        schema
+    );
+    if system_prompt.is_empty() {
+        instructions
+    } else {
+        format!("{}\n\n{}", system_prompt, instructions)
+    }
+}
+
+/// The user prompt of a retry: the original prompt followed by the rejected answer and why.
+pub fn feedback_prompt(prompt: &str, output: &str, error: &serde_json::Error) -> String {
+    format!(
+        "{}\n\nYour previous answer was:\n{}\n\nIt is not valid: {}. {}",
+        prompt,
+        output,
+        error,
+        "Answer again with valid JSON only."
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Debug, PartialEq, Deserialize, JsonSchema)]
+    struct City {
+        name: String,
+        population: u64,
+    }
+
+    #[test]
+    fn test_schema_lists_fields() {
+        let schema = schema_for::<City>();
+        assert_eq!(schema["type"], "object");
+        assert_eq!(schema["properties"]["population"]["type"], "integer");
+        assert_eq!(schema["required"], serde_json::json!(["name", "population"]));
+    }
+
+    #[test]
+    fn test_parse_fenced_and_bare_json() {
+        let expected = City { name: "Paris".to_string(), population: 2 };
+        let fenced = "```json\n{\"name\": \"Paris\", \"population\": 2}\n```";
+        assert_eq!(parse_structured::<City>(fenced).unwrap(), expected);
+        let bare = r#" {"name":"Paris","population":2} "#;
+        assert_eq!(parse_structured::<City>(bare).unwrap(), expected);
+        assert!(parse_structured::<City>(r#"{"name":"Paris"}"#).is_err());
+    }
+}
@@ -1,6 +1,14 @@
 use std::pin::Pin;
+use std::sync::Arc;
 use futures::Stream;
 use bytes::Bytes;
 use reqwest::Error as ReqwestError;
 
+pub use tokio_util::sync::CancellationToken;
+
+use super::error::LLMError;
+use super::response::LLMEvent;
+
 pub type AccumulatedStream = Pin<Box<dyn Stream<Item = Result<Bytes, ReqwestError>> + Send>>;
+pub type StreamProcessor = Arc<dyn (Fn(AccumulatedStream) -> AccumulatedStream) + Send + Sync>;
+pub type EventStream = Pin<Box<dyn Stream<Item = Result<LLMEvent, LLMError>> + Send>>;
@@ -1,13 +1,18 @@
+use std::sync::Arc;
+
 use async_trait::async_trait;
 use serde_json::json;
-use reqwest::Client;
+use reqwest::{ Client, StatusCode };
+use url::Url;
 
 use super::manager_trait::ModelManagerInterface;
+use super::model_kind::ModelKindRegistry;
+use super::system_memory::MemoryStatus;
 use super::types::{ ModelConfig, ModelInfo, ModelStatus };
-use super::error::ModelResult;
+use super::error::{ ModelError, ModelResult };
 use crate::llm::llm_builder::LLM;
+use crate::llm::load_balancer::LoadBalancer;
 use crate::llm::options::LLMHTTPCallOptions;
-use crate::llm::stream_processing::{ llamacpp_process_stream, qwen_process_stream };
 
 pub struct ModelManagerClient {
     base_url: String,
@@ -17,45 +22,108 @@ pub struct ModelManagerClient {
 impl ModelManagerClient {
     pub fn new(base_url: &str) -> Self {
         Self {
-            base_url: base_url.to_string(),
+            base_url: base_url.trim_end_matches('/').to_string(),
             client: Client::new(),
         }
     }
+
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    /// Turns non-success responses into `ModelError`s, keeping the server's error message.
+    async fn check(response: reqwest::Response) -> ModelResult<reqwest::Response> {
+        let status = response.status();
+        if status.is_success() {
+            return Ok(response);
+        }
+
+        let body: serde_json::Value = response.json().await.unwrap_or_default();
+        let message = body["error"].as_str().unwrap_or("unknown error").to_string();
+        match status {
+            StatusCode::NOT_FOUND => Err(ModelError::ModelNotFound(message)),
+            StatusCode::BAD_REQUEST => Err(ModelError::ConfigError(message)),
+            _ => Err(ModelError::ServerError(message)),
+        }
+    }
+
+    /// Model servers bind to the manager's own machine, so a `localhost` host in a remote
+    /// config has to be replaced with the host this client talks to.
+    fn reachable_url(&self, url: &str) -> String {
+        let remote_host = Url::parse(&self.base_url)
+            .ok()
+            .and_then(|u| u.host_str().map(|h| h.to_string()));
+
+        match (Url::parse(url), remote_host) {
+            (Ok(mut parsed), Some(remote_host)) => {
+                let is_local = matches!(
+                    parsed.host_str(),
+                    Some("localhost") | Some("127.0.0.1") | Some("0.0.0.0")
+                );
+                if is_local && parsed.set_host(Some(&remote_host)).is_ok() {
+                    return parsed.as_str().trim_end_matches('/').to_string();
+                }
+                url.to_string()
+            }
+            _ => url.to_string(),
+        }
+    }
 }
 
 #[async_trait]
 impl ModelManagerInterface for ModelManagerClient {
     async fn load_model(&self, config: ModelConfig) -> ModelResult<()> {
         let url = format!("{}/models/load", self.base_url);
-        let _ = self.client.post(&url).json(&config).send().await?.error_for_status();
+        Self::check(self.client.post(&url).json(&config).send().await?).await?;
         Ok(())
     }
 
     async fn load_model_by_name(&self, name: &str) -> ModelResult<()> {
-        // todo
+        let url = format!("{}/models/load_by_name", self.base_url);
+        Self::check(
+            self.client
+                .post(&url)
+                .json(&json!({ "name": name }))
+                .send().await?
+        ).await?;
         Ok(())
     }
 
     async fn unload_model(&self, name: &str) -> ModelResult<()> {
         let url = format!("{}/models/unload", self.base_url);
-        let _ = self.client
-            .post(&url)
-            .json(&json!({ "name": name }))
-            .send().await?
-            .error_for_status();
+        Self::check(
+            self.client
+                .post(&url)
+                .json(&json!({ "name": name }))
+                .send().await?
+        ).await?;
         Ok(())
     }
 
     async fn get_model_status(&self, name: &str) -> ModelResult<ModelStatus> {
         let url = format!("{}/models/status/{}", self.base_url, name);
-        let response = self.client.get(&url).send().await?.error_for_status()?;
+        let response = Self::check(self.client.get(&url).send().await?).await?;
 
         Ok(response.json().await?)
     }
 
     async fn list_models(&self) -> ModelResult<Vec<ModelInfo>> {
         let url = format!("{}/models/list", self.base_url);
-        let response = self.client.get(&url).send().await?.error_for_status()?;
+        let response = Self::check(self.client.get(&url).send().await?).await?;
+
+        Ok(response.json().await?)
+    }
+
+    async fn get_model_config(&self, name: &str) -> ModelResult<ModelConfig> {
+        let url = format!("{}/models/config/{}", self.base_url, name);
+        let response = Self::check(self.client.get(&url).send().await?).await?;
+
+        Ok(response.json().await?)
+    }
+
+    async fn memory_status(&self) -> ModelResult<MemoryStatus> {
+        let url = format!("{}/system/memory", self.base_url);
+        let response = Self::check(self.client.get(&url).send().await?).await?;
 
         Ok(response.json().await?)
     }
@@ -66,53 +134,38 @@ impl ModelManagerInterface for ModelManagerClient {
         options: Option<LLMHTTPCallOptions>,
         auto_load: bool
     ) -> ModelResult<LLM> {
-        // todo add this to server
         // First ensure the model is loaded
-        match self.get_model_status(model_name).await {
-            Ok(_) => (), // Model is already loaded
-            Err(_) => {
-                // If model is not found/loaded, get its config and load it
-                let url = format!("{}/models/config/{}", self.base_url, model_name);
-                let config: ModelConfig = self.client
-                    .get(&url)
-                    .send().await?
-                    .error_for_status()?
-                    .json().await?;
-
-                self.load_model(config).await?;
+        if auto_load {
+            match self.get_model_status(model_name).await {
+                Ok(ModelStatus::Running) => (), // Model is already loaded
+                _ => self.load_model_by_name(model_name).await?,
             }
         }
+
         // Get the model's server details
-        let url = format!("{}/models/server/{}", self.base_url, model_name);
-        let server_info: serde_json::Value = self.client
-            .get(&url)
-            .send().await?
-            .error_for_status()?
-            .json().await?;
+        let config = self.get_model_config(model_name).await?;
+        let replica_urls: Vec<String> = config.server_config
+            .replica_urls()?
+            .iter()
+            .map(|url| self.reachable_url(url))
+            .collect();
 
         // Create LLM with the server information
-        let mut llm_options = options.unwrap_or_default();
-        llm_options = llm_options
-            .with_server_url(
-                format!(
-                    "http://{}:{}",
-                    server_info["host"].as_str().unwrap_or("localhost"),
-                    server_info["port"].as_u64().unwrap_or(8000)
-                )
-            )
-            .with_prompt_template(
-                server_info["prompt_template"]
-                    .as_str()
-                    .unwrap_or("{system_prompt}\n{user_prompt}")
-                    .to_string()
-            );
+        let (llm_options, processor) = ModelKindRegistry::llm_settings(
+            &config,
+            options.unwrap_or_default(),
+            replica_urls[0].clone()
+        );
 
-        let processor = match server_info["model_kind"].as_str() {
-            Some("LLaMA") => llamacpp_process_stream,
-            Some("Qwen") => qwen_process_stream,
-            _ => llamacpp_process_stream, // default
-        };
+        let mut builder = LLM::builder()
+            .with_options(llm_options)
+            .with_process_response(move |stream| processor(stream));
+        if replica_urls.len() > 1 {
+            builder = builder.with_load_balancer(
+                Arc::new(LoadBalancer::new(replica_urls, config.server_config.load_balancing))
+            );
+        }
 
-        Ok(LLM::builder().with_options(llm_options).with_process_response(processor).build())
+        Ok(builder.build()?)
     }
 }
@@ -2,9 +2,12 @@ use std::{ collections::HashMap, path::PathBuf };
 
 use log::info;
 
+use crate::llm::load_balancer::LoadBalanceStrategy;
+
 use super::{
     ModelConfig,
     ModelDefaults,
+    ModelKind,
     ModelMemoryConfig,
     ModelType,
     PromptTemplate,
@@ -35,17 +38,19 @@ impl ModelRegistry {
         configs.insert("qwen-7b".to_string(), ModelConfig {
             name: "qwen-7b".to_string(),
             model_type: ModelType::Text,
-            model_kind: "Qwen".to_string(),
+            model_kind: ModelKind::Qwen,
             model_path: PathBuf::from(model_path),
+            mmproj_path: None,
             memory_config: ModelMemoryConfig {
                 min_ram_gb: 1.0,
                 recommended_ram_gb: 16.0,
                 gpu_memory_gb: Some(8.0),
             },
             prompt_template: PromptTemplate {
-                template: "<|im_start|>system\n{system}\n<|im_end|>\n<|im_start|>user\n{user}\n<|im_end|>".to_string(),
-                required_keys: vec!["system".to_string(), "user".to_string()],
+                template: "<|im_start|>system\n{system_prompt}<|im_end|>\n<|im_start|>user\n{user_prompt}<|im_end|>\n<|im_start|>assistant\n".to_string(),
+                required_keys: vec!["system_prompt".to_string(), "user_prompt".to_string()],
             },
+            chat_template: None,
             defaults: ModelDefaults {
                 temperature: 0.7,
                 top_p: 0.9,
@@ -62,6 +67,10 @@ impl ModelRegistry {
                 num_threads: Some(8),
                 use_mmap: true,
                 use_gpu: true,
+                replicas: 1,
+                load_balancing: LoadBalanceStrategy::RoundRobin,
+                parallel: None,
+                slot_save_path: None,
                 extra_args: HashMap::new(),
             },
         });
@@ -75,17 +84,19 @@ impl ModelRegistry {
         configs.insert("llama-7b".to_string(), ModelConfig {
             name: "llama-7b".to_string(),
             model_type: ModelType::Text,
-            model_kind: "LLaMa".to_string(),
+            model_kind: ModelKind::LLaMA,
             model_path: PathBuf::from(llama_path),
+            mmproj_path: None,
             memory_config: ModelMemoryConfig {
                 min_ram_gb: 3.0,
                 recommended_ram_gb: 16.0,
                 gpu_memory_gb: Some(8.0),
             },
             prompt_template: PromptTemplate {
-                template: "<|im_start|>system\n{system}\n<|im_end|>\n<|im_start|>user\n{user}\n<|im_end|>".to_string(),
-                required_keys: vec!["system".to_string(), "user".to_string()],
+                template: "<|im_start|>system\n{system_prompt}<|im_end|>\n<|im_start|>user\n{user_prompt}<|im_end|>\n<|im_start|>assistant\n".to_string(),
+                required_keys: vec!["system_prompt".to_string(), "user_prompt".to_string()],
             },
+            chat_template: None,
             defaults: ModelDefaults {
                 temperature: 0.7,
                 top_p: 0.9,
@@ -102,6 +113,10 @@ impl ModelRegistry {
                 num_threads: Some(8),
                 use_mmap: true,
                 use_gpu: true,
+                replicas: 1,
+                load_balancing: LoadBalanceStrategy::RoundRobin,
+                parallel: None,
+                slot_save_path: None,
                 extra_args: HashMap::new(),
             },
         });
@@ -115,8 +130,9 @@ impl ModelRegistry {
         configs.insert("smolTalk".to_string(), ModelConfig {
             name: "smolTalk".to_string(),
             model_type: ModelType::Text,
-            model_kind: "LLaMa".to_string(),
+            model_kind: ModelKind::LLaMA,
             model_path: PathBuf::from(smol_talk_path),
+            mmproj_path: None,
             memory_config: ModelMemoryConfig {
                 min_ram_gb: 2.0,
                 recommended_ram_gb: 16.0,
@@ -131,6 +147,7 @@ Today Date: 26 July 2024
 ".to_string(),
                 required_keys: vec!["system_prompt".to_string(), "user_prompt".to_string()],
             },
+            chat_template: None,
             defaults: ModelDefaults {
                 temperature: 0.7,
                 top_p: 0.9,
@@ -147,6 +164,10 @@ Today Date: 26 July 2024
                 num_threads: Some(8),
                 use_mmap: true,
                 use_gpu: true,
+                replicas: 1,
+                load_balancing: LoadBalanceStrategy::RoundRobin,
+                parallel: None,
+                slot_save_path: None,
                 extra_args: HashMap::new(),
             },
         });
@@ -160,17 +181,19 @@ Today Date: 26 July 2024
         configs.insert("granite".to_string(), ModelConfig {
             name: "granite".to_string(),
             model_type: ModelType::Text,
-            model_kind: "LLaMa".to_string(),
+            model_kind: ModelKind::Granite,
             model_path: PathBuf::from(granite_path),
+            mmproj_path: None,
             memory_config: ModelMemoryConfig {
                 min_ram_gb: 3.5,
                 recommended_ram_gb: 16.0,
                 gpu_memory_gb: Some(8.0),
             },
             prompt_template: PromptTemplate {
-                template: "<<|start_of_role|>system<|end_of_role|>{system_prompt}<|end_of_text|>\n<|start_of_role|>user<|end_of_role|>{user_prompt}<|end_of_text|>\n<|start_of_role|>assistant<|end_of_role|>".to_string(),
+                template: "<|start_of_role|>system<|end_of_role|>{system_prompt}<|end_of_text|>\n<|start_of_role|>user<|end_of_role|>{user_prompt}<|end_of_text|>\n<|start_of_role|>assistant<|end_of_role|>".to_string(),
                 required_keys: vec!["system_prompt".to_string(), "user_prompt".to_string()],
             },
+            chat_template: None,
             defaults: ModelDefaults {
                 temperature: 0.7,
                 top_p: 0.9,
@@ -187,6 +210,10 @@ Today Date: 26 July 2024
                 num_threads: Some(8),
                 use_mmap: true,
                 use_gpu: true,
+                replicas: 1,
+                load_balancing: LoadBalanceStrategy::RoundRobin,
+                parallel: None,
+                slot_save_path: None,
                 extra_args: HashMap::new(),
             },
         });
@@ -204,3 +231,31 @@ Today Date: 26 July 2024
         config
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_templates_are_valid() {
+        for config in ModelRegistry::new().configs.values() {
+            assert!(config.prompt_template.validate().is_ok(), "{}", config.name);
+        }
+    }
+
+    #[test]
+    fn test_missing_placeholder_is_rejected() {
+        let template = PromptTemplate {
+            template: "<|im_start|>system\n{system}<|im_end|>".to_string(),
+            required_keys: vec!["system_prompt".to_string()],
+        };
+        assert!(template.validate().is_err());
+
+        let values = HashMap::from([("system_prompt", "Be brief")]);
+        let template = PromptTemplate {
+            template: "{system_prompt}\n{user_prompt}".to_string(),
+            required_keys: vec!["system_prompt".to_string(), "user_prompt".to_string()],
+        };
+        assert!(template.render(&values).is_err());
+    }
+}
@@ -1,5 +1,7 @@
 use thiserror::Error;
 
+use crate::llm::error::LLMError;
+
 #[derive(Error, Debug)]
 pub enum ModelError {
     #[error("Model not found: {0}")] ModelNotFound(String),
@@ -20,7 +22,11 @@ pub enum ModelError {
 
     #[error("Memory error: {0}")] MemoryError(String),
 
+    #[error("Not supported by this model manager: {0}")] Unsupported(String),
+
     #[error("IO error: {0}")] IoError(#[from] std::io::Error),
+
+    #[error("LLM error: {0}")] LLMError(#[from] LLMError),
 }
 
 pub type ModelResult<T> = std::result::Result<T, ModelError>;
@@ -0,0 +1,493 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use futures::future::join_all;
+use log::{ info, warn };
+use tokio::sync::RwLock;
+
+use super::client::ModelManagerClient;
+use super::error::{ ModelError, ModelResult };
+use super::manager_trait::ModelManagerInterface;
+use super::system_memory::MemoryStatus;
+use super::types::{ ModelConfig, ModelInfo, ModelStatus };
+use crate::llm::llm_builder::LLM;
+use crate::llm::options::LLMHTTPCallOptions;
+
+/// A machine running a `ModelManagerServer`, or any other `ModelManagerInterface`.
+pub struct FederatedNode {
+    pub name: String,
+    pub manager: Arc<dyn ModelManagerInterface>,
+}
+
+/// Manages models across several nodes as if they were one `ModelManager`.
+///
+/// New models are placed on the node with the most free memory unless an affinity pins them
+/// to a specific node. Lookups go to whichever node currently hosts the model.
+///
+/// # Usage
+/// ```rust,ignore
+/// let manager = FederatedModelManager::new()
+///     .with_node("workstation-1", "http://10.0.0.11:8090")
+///     .with_node("workstation-2", "http://10.0.0.12:8090")
+///     .with_affinity("qwen-7b", "workstation-2");
+///
+/// let llm = manager.get_or_create_llm("smolTalk", None, true).await?;
+/// ```
+pub struct FederatedModelManager {
+    nodes: Vec<FederatedNode>,
+    affinities: HashMap<String, String>,
+    // model name -> node name, refreshed whenever a lookup misses
+    placements: RwLock<HashMap<String, String>>,
+}
+
+impl Default for FederatedModelManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FederatedModelManager {
+    pub fn new() -> Self {
+        Self {
+            nodes: Vec::new(),
+            affinities: HashMap::new(),
+            placements: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Adds a remote node reachable through its `ModelManagerServer` url.
+    pub fn with_node<S: Into<String>>(self, name: S, base_url: &str) -> Self {
+        self.with_node_manager(name, Arc::new(ModelManagerClient::new(base_url)))
+    }
+
+    /// Adds a node backed by any `ModelManagerInterface`, e.g. a local `ModelManager`.
+    pub fn with_node_manager<S: Into<String>>(
+        mut self,
+        name: S,
+        manager: Arc<dyn ModelManagerInterface>
+    ) -> Self {
+        self.nodes.push(FederatedNode { name: name.into(), manager });
+        self
+    }
+
+    /// Always places `model_name` on `node_name`.
+    pub fn with_affinity<S: Into<String>>(mut self, model_name: S, node_name: S) -> Self {
+        self.affinities.insert(model_name.into(), node_name.into());
+        self
+    }
+
+    pub fn nodes(&self) -> &[FederatedNode] {
+        &self.nodes
+    }
+
+    fn node(&self, name: &str) -> ModelResult<&FederatedNode> {
+        self.nodes
+            .iter()
+            .find(|node| node.name == name)
+            .ok_or_else(|| ModelError::ConfigError(format!("Unknown node: {}", name)))
+    }
+
+    /// Finds the node currently hosting `model_name`.
+    async fn locate(&self, model_name: &str) -> Option<&FederatedNode> {
+        let cached = self.placements.read().await.get(model_name).cloned();
+        if let Some(node) = cached.and_then(|name| self.node(&name).ok()) {
+            match node.manager.get_model_status(model_name).await {
+                Ok(_) => {
+                    return Some(node);
+                }
+                Err(e) => {
+                    info!("Model {} no longer on node {}: {}", model_name, node.name, e);
+                    self.placements.write().await.remove(model_name);
+                }
+            }
+        }
+
+        for node in &self.nodes {
+            match node.manager.list_models().await {
+                Ok(models) if models.iter().any(|model| model.name == model_name) => {
+                    self.record_placement(model_name, &node.name).await;
+                    return Some(node);
+                }
+                Ok(_) => {}
+                Err(e) => warn!("Node {} is unreachable: {}", node.name, e),
+            }
+        }
+
+        None
+    }
+
+    /// Chooses where a new model should run: its affinity, or the node with most free memory
+    /// among those with room for all of the model's replicas.
+    async fn place(&self, config: &ModelConfig) -> ModelResult<&FederatedNode> {
+        let model_name = &config.name;
+        if let Some(node_name) = self.affinities.get(model_name) {
+            return self.node(node_name);
+        }
+
+        let statuses = join_all(self.nodes.iter().map(|node| node.manager.memory_status())).await;
+        let reachable: Vec<(&FederatedNode, f32)> = self.nodes
+            .iter()
+            .zip(statuses)
+            .filter_map(|(node, status)| {
+                match status {
+                    Ok(status) => Some((node, status.available_gb)),
+                    Err(e) => {
+                        warn!("Skipping node {} for placement: {}", node.name, e);
+                        None
+                    }
+                }
+            })
+            .collect();
+        if reachable.is_empty() {
+            return Err(ModelError::ServerError("No reachable nodes".to_string()));
+        }
+
+        // Every replica is a full copy of the model
+        let required_gb =
+            config.memory_config.min_ram_gb * (config.server_config.replicas.max(1) as f32);
+
+        reachable
+            .into_iter()
+            .filter(|(_, available_gb)| *available_gb >= required_gb)
+            .max_by(|(_, a), (_, b)| a.total_cmp(b))
+            .map(|(node, available_gb)| {
+                info!(
+                    "Placing model {} on node {} ({:.1} GB available)",
+                    model_name,
+                    node.name,
+                    available_gb
+                );
+                node
+            })
+            .ok_or_else(|| {
+                ModelError::MemoryError(
+                    format!("No node has {:.1} GB free for model {}", required_gb, model_name)
+                )
+            })
+    }
+
+    async fn record_placement(&self, model_name: &str, node_name: &str) {
+        self.placements.write().await.insert(model_name.to_string(), node_name.to_string());
+    }
+}
+
+#[async_trait]
+impl ModelManagerInterface for FederatedModelManager {
+    async fn load_model(&self, config: ModelConfig) -> ModelResult<()> {
+        if self.locate(&config.name).await.is_some() {
+            return Ok(());
+        }
+        let name = config.name.clone();
+        let node = self.place(&config).await?;
+        node.manager.load_model(config).await?;
+        self.record_placement(&name, &node.name).await;
+        Ok(())
+    }
+
+    async fn load_model_by_name(&self, name: &str) -> ModelResult<()> {
+        if self.locate(name).await.is_some() {
+            return Ok(());
+        }
+        let config = self.get_model_config(name).await?;
+        let node = self.place(&config).await?;
+        node.manager.load_model_by_name(name).await?;
+        self.record_placement(name, &node.name).await;
+        Ok(())
+    }
+
+    async fn unload_model(&self, name: &str) -> ModelResult<()> {
+        let node = self
+            .locate(name).await
+            .ok_or_else(|| ModelError::ModelNotFound(name.to_string()))?;
+        node.manager.unload_model(name).await?;
+        self.placements.write().await.remove(name);
+        Ok(())
+    }
+
+    async fn get_model_status(&self, name: &str) -> ModelResult<ModelStatus> {
+        match self.locate(name).await {
+            Some(node) => node.manager.get_model_status(name).await,
+            None => Err(ModelError::ModelNotFound(name.to_string())),
+        }
+    }
+
+    async fn list_models(&self) -> ModelResult<Vec<ModelInfo>> {
+        let results = join_all(self.nodes.iter().map(|node| node.manager.list_models())).await;
+
+        let mut models = Vec::new();
+        for (node, result) in self.nodes.iter().zip(results) {
+            match result {
+                Ok(node_models) => {
+                    models.extend(
+                        node_models.into_iter().map(|mut info| {
+                            info.node = Some(node.name.clone());
+                            info
+                        })
+                    );
+                }
+                Err(e) => warn!("Could not list models on node {}: {}", node.name, e),
+            }
+        }
+        Ok(models)
+    }
+
+    async fn get_or_create_llm(
+        &self,
+        model_name: &str,
+        options: Option<LLMHTTPCallOptions>,
+        auto_load: bool
+    ) -> ModelResult<LLM> {
+        let node = match self.locate(model_name).await {
+            Some(node) => node,
+            None => {
+                let config = self.get_model_config(model_name).await?;
+                let node = self.place(&config).await?;
+                if auto_load {
+                    node.manager.load_model_by_name(model_name).await?;
+                    self.record_placement(model_name, &node.name).await;
+                }
+                node
+            }
+        };
+
+        node.manager.get_or_create_llm(model_name, options, false).await
+    }
+
+    async fn get_model_config(&self, name: &str) -> ModelResult<ModelConfig> {
+        if let Some(node) = self.locate(name).await {
+            return node.manager.get_model_config(name).await;
+        }
+        for node in &self.nodes {
+            if let Ok(config) = node.manager.get_model_config(name).await {
+                return Ok(config);
+            }
+        }
+        Err(ModelError::ModelNotFound(name.to_string()))
+    }
+
+    /// Combined memory of every reachable node.
+    async fn memory_status(&self) -> ModelResult<MemoryStatus> {
+        let statuses = join_all(self.nodes.iter().map(|node| node.manager.memory_status())).await;
+
+        let mut total = MemoryStatus {
+            total_gb: 0.0,
+            available_gb: 0.0,
+            used_gb: 0.0,
+            usage_percentage: 0.0,
+        };
+        for status in statuses.into_iter().flatten() {
+            total.total_gb += status.total_gb;
+            total.available_gb += status.available_gb;
+            total.used_gb += status.used_gb;
+        }
+        if total.total_gb > 0.0 {
+            total.usage_percentage = (total.used_gb / total.total_gb) * 100.0;
+        }
+        Ok(total)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{
+        ModelDefaults,
+        ModelKind,
+        ModelManagerServer,
+        ModelMemoryConfig,
+        ModelType,
+        PromptTemplate,
+        ServerConfig,
+    };
+    use crate::testing::FakeModelManager;
+    use chrono::Utc;
+    use parking_lot::Mutex;
+    use std::path::PathBuf;
+    use tokio::net::TcpListener;
+
+    /// A node that "runs" models by remembering them, serving on `port`.
+    struct StubNode {
+        available_gb: f32,
+        port: u16,
+        models: Mutex<HashMap<String, ModelConfig>>,
+    }
+
+    impl StubNode {
+        fn config(&self, name: &str) -> ModelConfig {
+            ModelConfig {
+                name: name.to_string(),
+                model_path: PathBuf::from(format!("/models/{}.gguf", name)),
+                mmproj_path: None,
+                model_type: ModelType::Text,
+                model_kind: ModelKind::LLaMA,
+                memory_config: ModelMemoryConfig {
+                    min_ram_gb: 1.0,
+                    recommended_ram_gb: 2.0,
+                    gpu_memory_gb: None,
+                },
+                prompt_template: PromptTemplate {
+                    template: "{system_prompt}\n{user_prompt}".to_string(),
+                    required_keys: vec!["system_prompt".to_string(), "user_prompt".to_string()],
+                },
+                chat_template: None,
+                defaults: ModelDefaults {
+                    temperature: 0.7,
+                    top_p: 0.9,
+                    top_k: 40,
+                    max_tokens: 256,
+                    repetition_penalty: 1.1,
+                },
+                server_config: ServerConfig {
+                    port: Some(self.port),
+                    ..ServerConfig::default()
+                },
+            }
+        }
+    }
+
+    #[async_trait]
+    impl ModelManagerInterface for StubNode {
+        async fn load_model(&self, config: ModelConfig) -> ModelResult<()> {
+            self.models.lock().insert(config.name.clone(), config);
+            Ok(())
+        }
+
+        async fn unload_model(&self, name: &str) -> ModelResult<()> {
+            self.models
+                .lock()
+                .remove(name)
+                .map(|_| ())
+                .ok_or_else(|| ModelError::ModelNotFound(name.to_string()))
+        }
+
+        async fn get_model_status(&self, name: &str) -> ModelResult<ModelStatus> {
+            match self.models.lock().contains_key(name) {
+                true => Ok(ModelStatus::Running),
+                false => Err(ModelError::ModelNotFound(name.to_string())),
+            }
+        }
+
+        async fn list_models(&self) -> ModelResult<Vec<ModelInfo>> {
+            Ok(
+                self.models
+                    .lock()
+                    .values()
+                    .map(|config| ModelInfo {
+                        name: config.name.clone(),
+                        model_type: config.model_type.clone(),
+                        status: ModelStatus::Running,
+                        last_used: Utc::now(),
+                        server_port: config.server_config.port,
+                        replica: 0,
+                        node: None,
+                    })
+                    .collect()
+            )
+        }
+
+        async fn get_or_create_llm(
+            &self,
+            _model_name: &str,
+            _options: Option<LLMHTTPCallOptions>,
+            _auto_load: bool
+        ) -> ModelResult<LLM> {
+            Err(ModelError::ConfigError("LLMs are built by the client".to_string()))
+        }
+
+        async fn load_model_by_name(&self, name: &str) -> ModelResult<()> {
+            let config = self.config(name);
+            self.load_model(config).await
+        }
+
+        async fn get_model_config(&self, name: &str) -> ModelResult<ModelConfig> {
+            Ok(self.models.lock().get(name).cloned().unwrap_or_else(|| self.config(name)))
+        }
+
+        async fn memory_status(&self) -> ModelResult<MemoryStatus> {
+            Ok(MemoryStatus {
+                total_gb: 64.0,
+                available_gb: self.available_gb,
+                used_gb: 64.0 - self.available_gb,
+                usage_percentage: ((64.0 - self.available_gb) / 64.0) * 100.0,
+            })
+        }
+    }
+
+    async fn spawn_node(available_gb: f32, port: u16) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let node = StubNode { available_gb, port, models: Mutex::new(HashMap::new()) };
+        tokio::spawn(ModelManagerServer::new(Arc::new(node)).serve(listener));
+        format!("http://{}", addr)
+    }
+
+    async fn federation() -> FederatedModelManager {
+        FederatedModelManager::new()
+            .with_node("small", &spawn_node(8.0, 9100).await)
+            .with_node("big", &spawn_node(32.0, 9200).await)
+            .with_affinity("pinned", "small")
+    }
+
+    #[tokio::test]
+    async fn test_places_models_on_node_with_most_free_memory() {
+        let manager = federation().await;
+        manager.load_model_by_name("smolTalk").await.unwrap();
+        manager.load_model_by_name("pinned").await.unwrap();
+
+        let models = manager.list_models().await.unwrap();
+        let node_of = |name: &str| {
+            models
+                .iter()
+                .find(|model| model.name == name)
+                .and_then(|model| model.node.clone())
+        };
+        assert_eq!(models.len(), 2);
+        assert_eq!(node_of("smolTalk").as_deref(), Some("big"));
+        assert_eq!(node_of("pinned").as_deref(), Some("small"));
+    }
+
+    #[tokio::test]
+    async fn test_placement_needs_room_for_every_replica() {
+        let manager = federation().await;
+
+        let mut config = FakeModelManager::model_config("qwen-32b", 12.0);
+        config.server_config.replicas = 3;
+        assert!(matches!(manager.load_model(config).await, Err(ModelError::MemoryError(_))));
+        assert!(manager.list_models().await.unwrap().is_empty());
+
+        let mut config = FakeModelManager::model_config("qwen-14b", 12.0);
+        config.server_config.replicas = 2;
+        manager.load_model(config).await.unwrap();
+        assert_eq!(manager.list_models().await.unwrap()[0].node.as_deref(), Some("big"));
+    }
+
+    #[tokio::test]
+    async fn test_llm_points_at_hosting_node() {
+        let manager = federation().await;
+
+        let llm = manager.get_or_create_llm("pinned", None, true).await.unwrap();
+        assert_eq!(llm.options().server_url.as_deref(), Some("http://127.0.0.1:9100"));
+
+        let llm = manager.get_or_create_llm("smolTalk", None, true).await.unwrap();
+        assert_eq!(llm.options().server_url.as_deref(), Some("http://127.0.0.1:9200"));
+        assert_eq!(manager.get_model_status("smolTalk").await.unwrap(), ModelStatus::Running);
+    }
+
+    #[tokio::test]
+    async fn test_unload_and_missing_models() {
+        let manager = federation().await;
+        manager.load_model_by_name("smolTalk").await.unwrap();
+        manager.unload_model("smolTalk").await.unwrap();
+
+        assert!(matches!(
+            manager.get_model_status("smolTalk").await,
+            Err(ModelError::ModelNotFound(_))
+        ));
+        assert!(manager.list_models().await.unwrap().is_empty());
+
+        let memory = manager.memory_status().await.unwrap();
+        assert_eq!(memory.total_gb, 128.0);
+        assert_eq!(memory.available_gb, 40.0);
+    }
+}
@@ -1,4 +1,5 @@
 use async_trait::async_trait;
+use futures::future::join_all;
 use log::{ debug, error, info };
 use tokio::sync::RwLock;
 use std::collections::HashMap;
@@ -10,21 +11,18 @@ use parking_lot::Mutex;
 use super::process::ModelProcess;
 use super::config_loader::ModelRegistry;
 use super::error::{ ModelError, ModelResult };
-use super::{ ModelConfig, ModelInfo, ModelStatus, ModelType, SystemMemory };
+use super::{ ModelConfig, ModelInfo, ModelStatus, SystemMemory };
+use super::system_memory::MemoryStatus;
 use crate::llm::llm_builder::LLM;
+use crate::llm::load_balancer::LoadBalancer;
 use crate::llm::options::LLMHTTPCallOptions;
-use crate::llm::stream_processing::llamacpp_process_stream;
-use crate::llm::types::AccumulatedStream;
-
-use std::pin::Pin;
-use bytes::Bytes;
-use futures::Stream;
 use super::manager_trait::ModelManagerInterface;
-
-type StreamProcessor = Arc<dyn (Fn(AccumulatedStream) -> AccumulatedStream) + Send + Sync>;
+use super::model_kind::ModelKindRegistry;
 
 pub struct ModelManager {
-    models: Arc<RwLock<HashMap<String, ModelProcess>>>,
+    // Every model maps to one process per replica
+    models: Arc<RwLock<HashMap<String, Vec<ModelProcess>>>>,
+    load_balancers: Arc<Mutex<HashMap<String, Arc<LoadBalancer>>>>,
     registry: ModelRegistry,
     system_memory: SystemMemory,
 
@@ -36,6 +34,7 @@ impl ModelManager {
     pub fn new() -> Self {
         Self {
             models: Arc::new(RwLock::new(HashMap::new())),
+            load_balancers: Arc::new(Mutex::new(HashMap::new())),
             registry: ModelRegistry::new(),
             system_memory: SystemMemory::new(),
 
@@ -48,7 +47,7 @@ impl ModelManager {
         &'a self,
         operation: &str,
         timeout: Duration
-    ) -> ModelResult<tokio::sync::RwLockWriteGuard<'a, HashMap<String, ModelProcess>>> {
+    ) -> ModelResult<tokio::sync::RwLockWriteGuard<'a, HashMap<String, Vec<ModelProcess>>>> {
         info!("Starting lock acquisition for operation: {}", operation);
 
         // First, try to get read lock to check current state
@@ -122,12 +121,13 @@ impl ModelManager {
     }
     pub async fn load_model(&self, config: ModelConfig) -> ModelResult<()> {
         self.record_lock_event(&format!("Starting load_model for {}", config.name));
+        config.prompt_template.validate()?;
 
         // First check if model is already loaded without holding write lock
         {
             let read_guard = self.models.read().await;
-            if let Some(process) = read_guard.get(&config.name) {
-                if process.status == ModelStatus::Running {
+            if let Some(processes) = read_guard.get(&config.name) {
+                if Self::aggregate_status(processes) == ModelStatus::Running {
                     self.record_lock_event(&format!("Model {} already loaded", config.name));
                     return Ok(());
                 }
@@ -137,8 +137,12 @@ impl ModelManager {
         self.record_lock_event("Checking memory requirements");
         self.system_memory.debug_memory_info().await;
 
+        // Every replica is a full copy of the model
+        let required_gb =
+            config.memory_config.min_ram_gb * (config.server_config.replicas.max(1) as f32);
+
         // Memory management with proper lock release
-        match self.manage_memory(config.memory_config.min_ram_gb).await {
+        match self.manage_memory(required_gb).await {
             Ok(_) => {
                 info!("Memory requirements satisfied for model {}", config.name);
             }
@@ -148,6 +152,48 @@ impl ModelManager {
             }
         }
 
+        // Replicas load side by side, without holding the models lock while they do
+        let starts = config.server_config
+            .replica_ports()?
+            .into_iter()
+            .enumerate()
+            .map(|(replica, port)| {
+                let mut process = ModelProcess::replica(config.clone(), replica, port);
+                async move {
+                    let result = process.start().await;
+                    (process, result)
+                }
+            });
+
+        let mut processes: Vec<ModelProcess> = Vec::new();
+        let mut failure = None;
+        for (process, result) in join_all(starts).await {
+            match result {
+                Ok(_) => {
+                    info!(
+                        "Successfully started model process: {} (replica {}, port {:?})",
+                        config.name,
+                        process.replica,
+                        process.port
+                    );
+                    processes.push(process);
+                }
+                Err(e) => {
+                    error!("Failed to start model process: {}", e);
+                    self.record_lock_event(&format!("Failed to start model process: {}", e));
+                    failure.get_or_insert(e);
+                }
+            }
+        }
+
+        if let Some(e) = failure {
+            // Don't leave a partially started replica set behind
+            for started in processes.iter_mut() {
+                let _ = started.stop().await;
+            }
+            return Err(e);
+        }
+
         self.record_lock_event("Acquiring write lock for model insertion");
         let mut models = match
             tokio::time::timeout(std::time::Duration::from_secs(5), self.models.write()).await
@@ -155,24 +201,76 @@ impl ModelManager {
             Ok(guard) => guard,
             Err(_) => {
                 self.record_lock_event("Timeout acquiring write lock in load_model");
+                for started in processes.iter_mut() {
+                    let _ = started.stop().await;
+                }
                 return Err(ModelError::ProcessError("Timeout acquiring write lock".to_string()));
             }
         };
 
-        let mut process = ModelProcess::new(config.clone());
-        match process.start().await {
-            Ok(_) => {
-                info!("Successfully started model process: {}", config.name);
-                models.insert(config.name.clone(), process);
-                self.record_lock_event(&format!("Successfully loaded model {}", config.name));
-                Ok(())
+        // A concurrent load of the same model may have finished first
+        if let Some(existing) = models.get(&config.name) {
+            if Self::aggregate_status(existing) == ModelStatus::Running {
+                drop(models);
+                for started in processes.iter_mut() {
+                    let _ = started.stop().await;
+                }
+                self.record_lock_event(&format!("Model {} already loaded", config.name));
+                return Ok(());
             }
-            Err(e) => {
-                error!("Failed to start model process: {}", e);
-                self.record_lock_event(&format!("Failed to start model process: {}", e));
-                Err(e)
+        }
+
+        models.insert(config.name.clone(), processes);
+        self.record_lock_event(&format!("Successfully loaded model {}", config.name));
+        Ok(())
+    }
+
+    /// Restarts a single replica while the others keep serving. The replica is taken out of
+    /// the model's load balancer for the duration of the restart.
+    pub async fn restart_replica(&self, name: &str, replica: usize) -> ModelResult<()> {
+        let mut restarting = {
+            let mut models = self.models.write().await;
+            let process = models
+                .get_mut(name)
+                .and_then(|processes| processes.iter_mut().find(|p| p.replica == replica))
+                .ok_or_else(|| ModelError::ModelNotFound(format!("{} (replica {})", name, replica)))?;
+
+            let mut restarting = ModelProcess::replica(
+                process.config.clone(),
+                replica,
+                process.port
+            );
+            restarting.child = process.child.take();
+            process.status = ModelStatus::Loading;
+            restarting
+        };
+
+        let url = restarting.url();
+        let balancer = self.load_balancers.lock().get(name).cloned();
+        if let Some(balancer) = &balancer {
+            balancer.set_healthy(&url, false);
+        }
+
+        info!("Restarting replica {} of model {}", replica, name);
+        restarting.stop().await?;
+        let result = restarting.start().await;
+
+        if result.is_ok() {
+            if let Some(balancer) = &balancer {
+                balancer.set_healthy(&url, true);
             }
         }
+
+        let mut models = self.models.write().await;
+        if
+            let Some(process) = models
+                .get_mut(name)
+                .and_then(|processes| processes.iter_mut().find(|p| p.replica == replica))
+        {
+            *process = restarting;
+        }
+
+        result
     }
 
     async fn load_model_by_name(&self, name: &str) -> ModelResult<()> {
@@ -187,9 +285,12 @@ impl ModelManager {
     pub async fn unload_model(&self, name: &str) -> ModelResult<()> {
         let mut models = self.models.write().await;
 
-        if let Some(process) = models.get_mut(name) {
-            process.stop().await?;
+        if let Some(processes) = models.get_mut(name) {
+            for process in processes.iter_mut() {
+                process.stop().await?;
+            }
             models.remove(name);
+            self.load_balancers.lock().remove(name);
             Ok(())
         } else {
             Err(ModelError::ModelNotFound(name.to_string()))
@@ -200,50 +301,57 @@ impl ModelManager {
         let models = self.models.read().await;
 
         match models.get(name) {
-            Some(process) => Ok(process.status.clone()),
+            Some(processes) => Ok(Self::aggregate_status(processes)),
             None => Err(ModelError::ModelNotFound(name.to_string())),
         }
     }
 
+    /// A model is running as long as one of its replicas is.
+    fn aggregate_status(processes: &[ModelProcess]) -> ModelStatus {
+        if processes.iter().any(|process| process.status == ModelStatus::Running) {
+            return ModelStatus::Running;
+        }
+        processes
+            .first()
+            .map(|process| process.status.clone())
+            .unwrap_or(ModelStatus::Stopped)
+    }
+
     pub async fn list_models(&self) -> ModelResult<Vec<ModelInfo>> {
         let models = self.models.read().await;
 
         Ok(
             models
                 .values()
+                .flatten()
                 .map(|process| ModelInfo {
                     name: process.config.name.clone(),
                     model_type: process.config.model_type.clone(),
                     status: process.status.clone(),
                     last_used: process.last_used,
-                    server_port: process.config.server_config.port,
+                    server_port: process.port,
+                    replica: process.replica,
+                    node: None,
                 })
                 .collect()
         )
     }
 
-    fn get_processor_for_model(config: &ModelConfig) -> StreamProcessor {
-        match config.model_type {
-            ModelType::Text =>
-                match config.model_kind.as_str() {
-                    "LLaMA" =>
-                        Arc::new(move |stream: AccumulatedStream| -> AccumulatedStream {
-                            Box::pin(llamacpp_process_stream(stream))
-                        }),
-                    "Qwen" =>
-                        Arc::new(move |stream: AccumulatedStream| -> AccumulatedStream {
-                            Box::pin(qwen_process_stream(stream))
-                        }),
-                    _ =>
-                        Arc::new(move |stream: AccumulatedStream| -> AccumulatedStream {
-                            Box::pin(llamacpp_process_stream(stream))
-                        }),
-                }
-            _ =>
-                Arc::new(move |stream: AccumulatedStream| -> AccumulatedStream {
-                    Box::pin(llamacpp_process_stream(stream))
-                }),
+    /// Configuration of a loaded model, or the registry entry for a model that isn't loaded.
+    pub async fn get_model_config(&self, name: &str) -> ModelResult<ModelConfig> {
+        if let Some(processes) = self.models.read().await.get(name) {
+            if let Some(process) = processes.first() {
+                return Ok(process.config.clone());
+            }
         }
+        self.registry
+            .get_config(name)
+            .cloned()
+            .ok_or_else(|| ModelError::ModelNotFound(name.to_string()))
+    }
+
+    pub async fn memory_status(&self) -> MemoryStatus {
+        self.system_memory.get_memory_status().await
     }
 
     pub async fn get_or_create_llm(
@@ -257,9 +365,6 @@ impl ModelManager {
             ModelError::ModelNotFound(format!("Configuration not found for model: {}", model_name))
         })?;
 
-        let model_status = self.get_model_status(model_name).await;
-        // info!("Current model status: {:?}", model_status);
-
         // Only load the model immediately if auto_load is true
         if auto_load {
             let model_status = self.get_model_status(model_name).await;
@@ -293,30 +398,35 @@ impl ModelManager {
         }
 
         // Create LLM with model-specific configurations
-        let mut llm_options = options.unwrap_or_default();
-        llm_options = llm_options
-            .with_server_url(
-                format!(
-                    "http://{}:{}",
-                    config.server_config.host,
-                    config.server_config.port.unwrap_or(8000)
-                )
-            )
-            .with_prompt_template(config.prompt_template.template.clone());
+        let (llm_options, processor) = ModelKindRegistry::llm_settings(
+            config,
+            options.unwrap_or_default(),
+            config.server_config.replica_urls()?[0].clone()
+        );
+        // let manager: Arc<dyn ModelManagerInterface> = Arc::new(self.clone());
+        let mut builder = LLM::builder()
+            .with_model_manager(self.clone(), model_name.to_string(), auto_load)
+            .with_options(llm_options)
+            .with_process_response(move |stream| processor(stream));
 
-        // Apply model defaults if not overridden
-        if llm_options.temperature.is_none() {
-            llm_options = llm_options.with_temperature(config.defaults.temperature);
+        if config.server_config.replicas > 1 {
+            builder = builder.with_load_balancer(self.load_balancer_for(config)?);
         }
 
-        let processor = Self::get_processor_for_model(&config);
-        // let manager: Arc<dyn ModelManagerInterface> = Arc::new(self.clone());
+        Ok(builder.build()?)
+    }
+
+    /// Returns the shared load balancer for a replicated model, creating it on first use.
+    fn load_balancer_for(&self, config: &ModelConfig) -> ModelResult<Arc<LoadBalancer>> {
+        let urls = config.server_config.replica_urls()?;
         Ok(
-            LLM::builder()
-                .with_model_manager(self.clone(), model_name.to_string(), auto_load)
-                .with_options(llm_options)
-                .with_process_response(move |stream| processor(stream))
-                .build()
+            self.load_balancers
+                .lock()
+                .entry(config.name.clone())
+                .or_insert_with(|| {
+                    Arc::new(LoadBalancer::new(urls, config.server_config.load_balancing))
+                })
+                .clone()
         )
     }
 
@@ -369,7 +479,12 @@ impl ModelManager {
         // Convert to vec for sorting
         let mut model_times: Vec<_> = models
             .iter()
-            .map(|(k, v)| (k.clone(), v.last_used))
+            .filter_map(|(k, v)| {
+                v.iter()
+                    .map(|process| process.last_used)
+                    .max()
+                    .map(|last_used| (k.clone(), last_used))
+            })
             .collect();
 
         // Sort by last used time (oldest first)
@@ -382,18 +497,29 @@ impl ModelManager {
 
         // Unload models until we have enough memory
         for (model_name, _) in model_times {
-            if let Some(process) = models.get_mut(&model_name) {
-                let model_memory = process.config.memory_config.min_ram_gb;
+            if let Some(processes) = models.get_mut(&model_name) {
+                let model_memory: f32 = processes
+                    .iter()
+                    .map(|process| process.config.memory_config.min_ram_gb)
+                    .sum();
 
                 info!("Attempting to unload model: {}", model_name);
 
-                match process.stop().await {
+                let mut stop_result = Ok(());
+                for process in processes.iter_mut() {
+                    if let Err(e) = process.stop().await {
+                        stop_result = Err(e);
+                    }
+                }
+
+                match stop_result {
                     Ok(()) => {
                         freed_memory += model_memory;
                         unloaded_models.push(model_name.clone());
 
                         // Remove from models map
                         models.remove(&model_name);
+                        self.load_balancers.lock().remove(&model_name);
 
                         info!(
                             "Unloaded model: {} - Total freed memory: {:.1} GB",
@@ -501,12 +627,12 @@ impl ModelManagerInterface for ModelManager {
     async fn load_model_by_name(&self, name: &str) -> ModelResult<()> {
         self.load_model_by_name(name).await
     }
-}
 
-pub fn qwen_process_stream(
-    stream: Pin<Box<dyn Stream<Item = Result<Bytes, reqwest::Error>> + Send>>
-) -> Pin<Box<dyn Stream<Item = Result<Bytes, reqwest::Error>> + Send>> {
-    // Implementation similar to llamacpp_process_stream but for Qwen
-    // For now, we can use the same implementation
-    llamacpp_process_stream(stream)
+    async fn get_model_config(&self, name: &str) -> ModelResult<ModelConfig> {
+        self.get_model_config(name).await
+    }
+
+    async fn memory_status(&self) -> ModelResult<MemoryStatus> {
+        Ok(self.memory_status().await)
+    }
 }
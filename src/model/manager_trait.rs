@@ -2,7 +2,8 @@ use async_trait::async_trait;
 use crate::llm::llm_builder::LLM;
 use crate::llm::options::LLMHTTPCallOptions;
 use super::types::{ ModelConfig, ModelInfo, ModelStatus };
-use super::error::ModelResult;
+use super::system_memory::MemoryStatus;
+use super::error::{ ModelError, ModelResult };
 
 #[async_trait]
 pub trait ModelManagerInterface: Send + Sync {
@@ -17,4 +18,55 @@ pub trait ModelManagerInterface: Send + Sync {
         auto_load: bool
     ) -> ModelResult<LLM>;
     async fn load_model_by_name(&self, name: &str) -> ModelResult<()>;
+    /// Configuration of a loaded model, falling back to the registry for known models.
+    async fn get_model_config(&self, name: &str) -> ModelResult<ModelConfig> {
+        Err(ModelError::Unsupported(format!("get_model_config({})", name)))
+    }
+    /// Memory of the machine the models run on.
+    async fn memory_status(&self) -> ModelResult<MemoryStatus> {
+        Err(ModelError::Unsupported("memory_status".to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A manager written before `get_model_config` and `memory_status` existed.
+    struct MinimalManager;
+
+    #[async_trait]
+    impl ModelManagerInterface for MinimalManager {
+        async fn load_model(&self, _config: ModelConfig) -> ModelResult<()> {
+            Ok(())
+        }
+        async fn unload_model(&self, _name: &str) -> ModelResult<()> {
+            Ok(())
+        }
+        async fn get_model_status(&self, _name: &str) -> ModelResult<ModelStatus> {
+            Ok(ModelStatus::Stopped)
+        }
+        async fn list_models(&self) -> ModelResult<Vec<ModelInfo>> {
+            Ok(Vec::new())
+        }
+        async fn get_or_create_llm(
+            &self,
+            model_name: &str,
+            _options: Option<LLMHTTPCallOptions>,
+            _auto_load: bool
+        ) -> ModelResult<LLM> {
+            Err(ModelError::ModelNotFound(model_name.to_string()))
+        }
+        async fn load_model_by_name(&self, _name: &str) -> ModelResult<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_newer_methods_default_to_unsupported() {
+        let manager = MinimalManager;
+        let config = manager.get_model_config("qwen-7b").await;
+        assert!(matches!(config, Err(ModelError::Unsupported(_))));
+        assert!(matches!(manager.memory_status().await, Err(ModelError::Unsupported(_))));
+    }
 }
@@ -7,13 +7,17 @@ pub mod process;
 pub mod config_loader;
 pub mod system_memory;
 pub mod manager_trait;
+pub mod model_kind;
 
 mod client;
 mod server;
+mod federated;
 
 pub use types::*;
+pub use model_kind::{ ModelCapability, ModelKind, ModelKindRegistry, ModelKindSpec, register_model_kind };
 pub use manager::ModelManager;
 pub use client::ModelManagerClient;
 pub use server::ModelManagerServer;
+pub use federated::{ FederatedModelManager, FederatedNode };
 pub use system_memory::SystemMemory;
 pub use manager_trait::ModelManagerInterface;
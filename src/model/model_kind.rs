@@ -0,0 +1,393 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{ Arc, OnceLock };
+
+use log::info;
+use parking_lot::RwLock;
+use serde::{ Deserialize, Serialize };
+
+use super::types::{ ModelConfig, PromptTemplate };
+use crate::llm::chat::ChatTemplate;
+use crate::llm::options::LLMHTTPCallOptions;
+use crate::llm::stream_processing::llamacpp_process_stream;
+use crate::llm::types::{ AccumulatedStream, StreamProcessor };
+
+/// The model family, which decides how prompts are formatted and how output is processed.
+///
+/// Parsing is case-insensitive, so `"LLaMa"`, `"llama"` and `"LLaMA"` are the same kind.
+/// Anything unknown becomes `Custom`, which can be described with `register_model_kind`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(from = "String", into = "String")]
+pub enum ModelKind {
+    Qwen,
+    LLaMA,
+    Mistral,
+    Granite,
+    Whisper,
+    Qwen2Audio,
+    Custom(String),
+}
+
+impl ModelKind {
+    pub fn as_str(&self) -> &str {
+        match self {
+            ModelKind::Qwen => "Qwen",
+            ModelKind::LLaMA => "LLaMA",
+            ModelKind::Mistral => "Mistral",
+            ModelKind::Granite => "Granite",
+            ModelKind::Whisper => "Whisper",
+            ModelKind::Qwen2Audio => "Qwen2Audio",
+            ModelKind::Custom(name) => name,
+        }
+    }
+
+    fn registry_key(&self) -> String {
+        self.as_str().to_lowercase()
+    }
+}
+
+impl FromStr for ModelKind {
+    type Err = std::convert::Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.trim().to_lowercase().replace(['-', '_', ' '], "").as_str() {
+            "qwen" => ModelKind::Qwen,
+            "llama" => ModelKind::LLaMA,
+            "mistral" => ModelKind::Mistral,
+            "granite" => ModelKind::Granite,
+            "whisper" => ModelKind::Whisper,
+            "qwen2audio" => ModelKind::Qwen2Audio,
+            _ => ModelKind::Custom(s.trim().to_string()),
+        })
+    }
+}
+
+impl From<String> for ModelKind {
+    fn from(value: String) -> Self {
+        match value.parse() {
+            Ok(kind) => kind,
+            Err(never) => match never {},
+        }
+    }
+}
+
+impl From<&str> for ModelKind {
+    fn from(value: &str) -> Self {
+        ModelKind::from(value.to_string())
+    }
+}
+
+impl From<ModelKind> for String {
+    fn from(kind: ModelKind) -> Self {
+        kind.as_str().to_string()
+    }
+}
+
+impl fmt::Display for ModelKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub enum ModelCapability {
+    Chat,
+    Completion,
+    ToolCalling,
+    Vision,
+    Audio,
+    Embedding,
+}
+
+/// Everything a model kind contributes to the `LLM`s created for it.
+#[derive(Clone)]
+pub struct ModelKindSpec {
+    pub kind: ModelKind,
+    pub stream_processor: StreamProcessor,
+    /// Used when a `ModelConfig` leaves its prompt template empty
+    pub prompt_template: PromptTemplate,
+    /// Used by `LLM::chat` when a `ModelConfig` has no chat template of its own
+    pub chat_template: ChatTemplate,
+    pub stop_tokens: Vec<String>,
+    pub capabilities: Vec<ModelCapability>,
+}
+
+impl ModelKindSpec {
+    /// A spec using the llama.cpp stream processor and plain `system\nuser` templates.
+    pub fn new(kind: ModelKind) -> Self {
+        Self {
+            kind,
+            stream_processor: Arc::new(|stream: AccumulatedStream| -> AccumulatedStream {
+                llamacpp_process_stream(stream)
+            }),
+            prompt_template: PromptTemplate {
+                template: "{system_prompt}\n{user_prompt}".to_string(),
+                required_keys: vec!["system_prompt".to_string(), "user_prompt".to_string()],
+            },
+            chat_template: ChatTemplate::plain(),
+            stop_tokens: Vec::new(),
+            capabilities: vec![ModelCapability::Completion],
+        }
+    }
+
+    pub fn with_stream_processor<F>(mut self, processor: F) -> Self
+        where F: Fn(AccumulatedStream) -> AccumulatedStream + Send + Sync + 'static
+    {
+        self.stream_processor = Arc::new(processor);
+        self
+    }
+
+    pub fn with_prompt_template(mut self, template: &str) -> Self {
+        self.prompt_template = PromptTemplate {
+            template: template.to_string(),
+            required_keys: vec!["system_prompt".to_string(), "user_prompt".to_string()],
+        };
+        self
+    }
+
+    pub fn with_chat_template(mut self, chat_template: ChatTemplate) -> Self {
+        self.chat_template = chat_template;
+        self
+    }
+
+    pub fn with_stop_tokens(mut self, stop_tokens: &[&str]) -> Self {
+        self.stop_tokens = stop_tokens
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        self
+    }
+
+    pub fn with_capabilities(mut self, capabilities: Vec<ModelCapability>) -> Self {
+        self.capabilities = capabilities;
+        self
+    }
+
+    pub fn has_capability(&self, capability: &ModelCapability) -> bool {
+        self.capabilities.contains(capability)
+    }
+}
+
+/// Lookup table from `ModelKind` to `ModelKindSpec`, shared by `ModelManager` and
+/// `ModelManagerClient`. Built-in kinds are registered up front; users can add or
+/// override kinds with `register`.
+pub struct ModelKindRegistry {
+    specs: HashMap<String, ModelKindSpec>,
+}
+
+impl ModelKindRegistry {
+    fn with_builtin_kinds() -> Self {
+        let mut registry = Self { specs: HashMap::new() };
+
+        registry.insert(
+            ModelKindSpec::new(ModelKind::LLaMA)
+                .with_prompt_template(
+                    "<|begin_of_text|><|start_header_id|>system<|end_header_id|>\n\n{system_prompt}<|eot_id|><|start_header_id|>user<|end_header_id|>\n\n{user_prompt}<|eot_id|><|start_header_id|>assistant<|end_header_id|>\n\n"
+                )
+                .with_chat_template(ChatTemplate::llama3())
+                .with_stop_tokens(&["<|eot_id|>", "<|end_of_text|>"])
+                .with_capabilities(
+                    vec![
+                        ModelCapability::Chat,
+                        ModelCapability::Completion,
+                        ModelCapability::ToolCalling
+                    ]
+                )
+        );
+        registry.insert(
+            ModelKindSpec::new(ModelKind::Qwen)
+                .with_prompt_template(
+                    "<|im_start|>system\n{system_prompt}<|im_end|>\n<|im_start|>user\n{user_prompt}<|im_end|>\n<|im_start|>assistant\n"
+                )
+                .with_chat_template(ChatTemplate::chatml())
+                .with_stop_tokens(&["<|im_end|>", "<|endoftext|>"])
+                .with_capabilities(
+                    vec![
+                        ModelCapability::Chat,
+                        ModelCapability::Completion,
+                        ModelCapability::ToolCalling
+                    ]
+                )
+        );
+        registry.insert(
+            ModelKindSpec::new(ModelKind::Mistral)
+                .with_prompt_template("<s>[INST] {system_prompt}\n\n{user_prompt} [/INST]")
+                .with_chat_template(ChatTemplate::mistral())
+                .with_stop_tokens(&["</s>"])
+                .with_capabilities(vec![ModelCapability::Chat, ModelCapability::Completion])
+        );
+        registry.insert(
+            ModelKindSpec::new(ModelKind::Granite)
+                .with_prompt_template(
+                    "<|start_of_role|>system<|end_of_role|>{system_prompt}<|end_of_text|>\n<|start_of_role|>user<|end_of_role|>{user_prompt}<|end_of_text|>\n<|start_of_role|>assistant<|end_of_role|>"
+                )
+                .with_chat_template(ChatTemplate::granite())
+                .with_stop_tokens(&["<|end_of_text|>"])
+                .with_capabilities(
+                    vec![
+                        ModelCapability::Chat,
+                        ModelCapability::Completion,
+                        ModelCapability::ToolCalling
+                    ]
+                )
+        );
+        registry.insert(
+            ModelKindSpec::new(ModelKind::Whisper).with_capabilities(vec![ModelCapability::Audio])
+        );
+        registry.insert(
+            ModelKindSpec::new(ModelKind::Qwen2Audio)
+                .with_prompt_template(
+                    "<|im_start|>system\n{system_prompt}<|im_end|>\n<|im_start|>user\n{user_prompt}<|im_end|>\n<|im_start|>assistant\n"
+                )
+                .with_chat_template(ChatTemplate::chatml())
+                .with_stop_tokens(&["<|im_end|>"])
+                .with_capabilities(vec![ModelCapability::Chat, ModelCapability::Audio])
+        );
+
+        registry
+    }
+
+    fn global() -> &'static RwLock<ModelKindRegistry> {
+        static REGISTRY: OnceLock<RwLock<ModelKindRegistry>> = OnceLock::new();
+        REGISTRY.get_or_init(|| RwLock::new(Self::with_builtin_kinds()))
+    }
+
+    fn insert(&mut self, spec: ModelKindSpec) {
+        self.specs.insert(spec.kind.registry_key(), spec);
+    }
+
+    /// Registers a custom kind, or replaces the spec of an existing one.
+    pub fn register(spec: ModelKindSpec) {
+        info!("Registering model kind: {}", spec.kind);
+        Self::global().write().insert(spec);
+    }
+
+    /// The spec for `kind`. Unregistered kinds get the default llama.cpp spec.
+    pub fn lookup(kind: &ModelKind) -> ModelKindSpec {
+        Self::global()
+            .read()
+            .specs.get(&kind.registry_key())
+            .cloned()
+            .unwrap_or_else(|| {
+                info!("No spec registered for model kind {}, using defaults", kind);
+                ModelKindSpec::new(kind.clone())
+            })
+    }
+
+    /// Fills in everything an `LLM` for `config` needs from its kind: the prompt and chat
+    /// templates (unless the config has its own), stop tokens and sampling defaults (unless the
+    /// caller set them) and the stream processor.
+    pub(crate) fn llm_settings(
+        config: &ModelConfig,
+        options: LLMHTTPCallOptions,
+        server_url: String
+    ) -> (LLMHTTPCallOptions, StreamProcessor) {
+        let spec = Self::lookup(&config.model_kind);
+
+        let template = if config.prompt_template.template.is_empty() {
+            spec.prompt_template.template.clone()
+        } else {
+            config.prompt_template.template.clone()
+        };
+
+        let mut options = options.with_server_url(server_url).with_prompt_template(template);
+        if options.chat_template.is_none() {
+            options = options.with_chat_template(
+                config.chat_template.clone().unwrap_or(spec.chat_template)
+            );
+        }
+
+        // Apply model defaults if not overridden
+        let defaults = &config.defaults;
+        if !options.is_set("temperature") {
+            options = options.with_temperature(defaults.temperature);
+        }
+        if !options.is_set("top_p") {
+            options = options.with_top_p(defaults.top_p);
+        }
+        if !options.is_set("top_k") {
+            options = options.with_top_k(defaults.top_k);
+        }
+        if !options.is_set("max_tokens") {
+            options = options.with_max_tokens(defaults.max_tokens as u32);
+        }
+        if !options.is_set("repetition_penalty") {
+            options = options.with_repetition_penalty(defaults.repetition_penalty);
+        }
+        if options.stop_words.is_none() && !spec.stop_tokens.is_empty() {
+            options = options.with_stop_words(spec.stop_tokens.clone());
+        }
+        if options.context_size.is_none() {
+            options = options.with_context_size(config.server_config.ctx_size);
+        }
+
+        (options, spec.stream_processor)
+    }
+}
+
+/// Registers a custom model kind, see `ModelKindRegistry::register`.
+pub fn register_model_kind(spec: ModelKindSpec) {
+    ModelKindRegistry::register(spec);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_kind_parsing_is_case_insensitive() {
+        assert_eq!(ModelKind::from("LLaMa"), ModelKind::LLaMA);
+        assert_eq!(ModelKind::from("llama"), ModelKind::LLaMA);
+        assert_eq!(ModelKind::from("qwen2-audio"), ModelKind::Qwen2Audio);
+        assert_eq!(ModelKind::from("Phi"), ModelKind::Custom("Phi".to_string()));
+    }
+
+    #[test]
+    fn test_kind_serializes_as_plain_string() {
+        let kind: ModelKind = serde_json::from_str("\"LLaMa\"").unwrap();
+        assert_eq!(kind, ModelKind::LLaMA);
+        assert_eq!(serde_json::to_string(&ModelKind::Granite).unwrap(), "\"Granite\"");
+    }
+
+    #[test]
+    fn test_custom_kind_registration() {
+        let kind = ModelKind::from("phi-test");
+        assert!(ModelKindRegistry::lookup(&kind).stop_tokens.is_empty());
+
+        register_model_kind(
+            ModelKindSpec::new(kind.clone())
+                .with_prompt_template("<|system|>{system_prompt}<|user|>{user_prompt}<|assistant|>")
+                .with_stop_tokens(&["<|end|>"])
+                .with_capabilities(vec![ModelCapability::Chat])
+        );
+
+        let spec = ModelKindRegistry::lookup(&ModelKind::from("PHI-TEST"));
+        assert_eq!(spec.stop_tokens, vec!["<|end|>".to_string()]);
+        assert!(spec.has_capability(&ModelCapability::Chat));
+        assert!(!spec.has_capability(&ModelCapability::Vision));
+    }
+
+    #[test]
+    fn test_builtin_kinds_have_stop_tokens() {
+        for kind in [ModelKind::LLaMA, ModelKind::Qwen, ModelKind::Mistral, ModelKind::Granite] {
+            assert!(!ModelKindRegistry::lookup(&kind).stop_tokens.is_empty(), "{}", kind);
+        }
+    }
+
+    #[test]
+    fn test_model_defaults_apply_unless_overridden() {
+        let config = crate::testing::FakeModelManager::model_config("defaults", 1.0);
+        let options = LLMHTTPCallOptions::new().with_temperature(0.0);
+        let (options, _) = ModelKindRegistry::llm_settings(
+            &config,
+            options,
+            "http://localhost:8000".to_string()
+        );
+
+        assert_eq!(options.temperature, Some(0.0));
+        assert_eq!(options.top_k, Some(40));
+        assert_eq!(options.max_tokens, Some(256));
+        assert_eq!(options.repetition_penalty, Some(1.1));
+    }
+}
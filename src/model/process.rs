@@ -1,15 +1,21 @@
 use std::process::{ Child, Command };
-use std::thread::sleep;
 use std::time::Duration;
 use chrono::{ DateTime, Utc };
-use log::{ info, error };
+use log::{ info, error, warn };
 use tokio::sync::oneshot;
+use tokio::time::{ sleep, Instant };
 
-use super::{ ModelConfig, ModelStatus };
+use super::{ ModelConfig, ModelStatus, ModelType };
 use super::error::{ ModelError, ModelResult };
 
+/// How long a freshly spawned llama-server gets to load its model.
+const READY_TIMEOUT: Duration = Duration::from_secs(120);
+const READY_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
 pub(crate) struct ModelProcess {
     pub config: ModelConfig,
+    pub replica: usize,
+    pub port: Option<u16>,
     pub child: Option<Child>,
     pub status: ModelStatus,
     pub last_used: DateTime<Utc>,
@@ -17,9 +23,12 @@ pub(crate) struct ModelProcess {
 }
 
 impl ModelProcess {
-    pub fn new(config: ModelConfig) -> Self {
+    /// A process for one replica of a model, listening on its own port.
+    pub fn replica(config: ModelConfig, replica: usize, port: Option<u16>) -> Self {
         Self {
             config,
+            replica,
+            port,
             child: None,
             status: ModelStatus::Stopped,
             last_used: Utc::now(),
@@ -34,6 +43,74 @@ impl ModelProcess {
 
         self.status = ModelStatus::Loading;
 
+        match self.command().spawn() {
+            Ok(child) => {
+                self.child = Some(child);
+            }
+            Err(e) => {
+                self.status = ModelStatus::Error(e.to_string());
+                return Err(ModelError::ProcessError(e.to_string()));
+            }
+        }
+
+        match self.wait_until_ready().await {
+            Ok(()) => {
+                self.status = ModelStatus::Running;
+                self.last_used = Utc::now();
+                Ok(())
+            }
+            Err(e) => {
+                let _ = self.stop().await;
+                self.status = ModelStatus::Error(e.to_string());
+                Err(e)
+            }
+        }
+    }
+
+    /// Polls the server's `/health` endpoint, which answers 200 once the model has loaded.
+    async fn wait_until_ready(&mut self) -> ModelResult<()> {
+        let client = reqwest::Client::new();
+        let url = format!("{}/health", self.url());
+        let deadline = Instant::now() + READY_TIMEOUT;
+
+        loop {
+            if let Some(Ok(Some(status))) = self.child.as_mut().map(|child| child.try_wait()) {
+                return Err(
+                    ModelError::ProcessError(
+                        format!(
+                            "{} (replica {}) exited while starting: {}",
+                            self.config.name,
+                            self.replica,
+                            status
+                        )
+                    )
+                );
+            }
+
+            if let Ok(resp) = client.get(&url).send().await {
+                if resp.status().is_success() {
+                    return Ok(());
+                }
+            }
+
+            if Instant::now() >= deadline {
+                return Err(
+                    ModelError::ProcessError(
+                        format!(
+                            "{} (replica {}) was not ready after {:?}",
+                            self.config.name,
+                            self.replica,
+                            READY_TIMEOUT
+                        )
+                    )
+                );
+            }
+            sleep(READY_POLL_INTERVAL).await;
+        }
+    }
+
+    /// The llama-server invocation for this replica.
+    fn command(&self) -> Command {
         let mut cmd = Command::new("/Users/cj/.pyano/build/bin/llama-server");
 
         // Configure command based on adapter config
@@ -42,7 +119,17 @@ impl ModelProcess {
             .arg("--ctx-size")
             .arg(self.config.server_config.ctx_size.to_string());
 
-        if let Some(port) = self.config.server_config.port {
+        if let Some(mmproj) = &self.config.mmproj_path {
+            cmd.arg("--mmproj").arg(mmproj);
+        } else if self.config.model_type == ModelType::Vision {
+            warn!("Vision model {} has no mmproj_path, images will be ignored", self.config.name);
+        }
+
+        if self.config.model_type == ModelType::Embedding {
+            cmd.arg("--embedding");
+        }
+
+        if let Some(port) = self.port {
             cmd.arg("--port").arg(port.to_string());
         }
 
@@ -58,6 +145,14 @@ impl ModelProcess {
             cmd.arg("--no-mmap");
         }
 
+        if let Some(parallel) = self.config.server_config.parallel {
+            cmd.arg("--parallel").arg(parallel.to_string());
+        }
+
+        if let Some(slot_save_path) = &self.config.server_config.slot_save_path {
+            cmd.arg("--slot-save-path").arg(slot_save_path);
+        }
+
         // Add batch size
         cmd.arg("--batch-size").arg(self.config.server_config.batch_size.to_string());
 
@@ -65,21 +160,12 @@ impl ModelProcess {
         for (key, value) in &self.config.server_config.extra_args {
             cmd.arg(format!("--{}", key)).arg(value);
         }
-
-        match cmd.spawn() {
-            Ok(child) => {
-                sleep(Duration::from_secs(10));
-                self.child = Some(child);
-                self.status = ModelStatus::Running;
-                self.last_used = Utc::now();
-                Ok(())
-            }
-            Err(e) => {
-                self.status = ModelStatus::Error(e.to_string());
-                Err(ModelError::ProcessError(e.to_string()))
-            }
-        }
+        cmd
     }
+    pub fn url(&self) -> String {
+        format!("http://{}:{}", self.config.server_config.host, self.port.unwrap_or(8000))
+    }
+
     pub async fn stop(&mut self) -> ModelResult<()> {
         if let Some(mut child) = self.child.take() {
             let pid = child.id();
@@ -92,7 +178,7 @@ impl ModelProcess {
                     libc::kill(pid as i32, libc::SIGKILL);
                 }
             }
-            sleep(Duration::from_secs(5));
+            sleep(Duration::from_secs(5)).await;
             // Wait for process to exit with timeout
             let _ = tokio::time::timeout(std::time::Duration::from_secs(1), async {
                 let mut child = child;
@@ -111,3 +197,53 @@ impl ModelProcess {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::FakeModelManager;
+
+    #[test]
+    fn test_vision_model_is_started_with_projector() {
+        let mut config = FakeModelManager::model_config("llava", 1.0);
+        config.model_type = ModelType::Vision;
+        config.mmproj_path = Some("mmproj-llava.gguf".into());
+
+        let process = ModelProcess::replica(config, 0, Some(8123));
+        let args: Vec<String> = process
+            .command()
+            .get_args()
+            .map(|arg| arg.to_string_lossy().to_string())
+            .collect();
+        let mmproj = args.iter().position(|arg| arg == "--mmproj").unwrap();
+        assert_eq!(args[mmproj + 1], "mmproj-llava.gguf");
+        assert!(!args.contains(&"--embedding".to_string()));
+    }
+
+    #[test]
+    fn test_embedding_model_is_started_with_embedding_endpoint() {
+        let mut config = FakeModelManager::model_config("nomic-embed", 0.5);
+        config.model_type = ModelType::Embedding;
+
+        let process = ModelProcess::replica(config, 0, Some(8124));
+        assert!(process.command().get_args().any(|arg| arg == "--embedding"));
+    }
+
+    #[test]
+    fn test_parallel_slots_are_passed_to_server() {
+        let mut config = FakeModelManager::model_config("classifier", 1.0);
+        config.server_config.parallel = Some(4);
+        config.server_config.slot_save_path = Some("/tmp/slots".into());
+
+        let process = ModelProcess::replica(config, 0, Some(8125));
+        let args: Vec<String> = process
+            .command()
+            .get_args()
+            .map(|arg| arg.to_string_lossy().to_string())
+            .collect();
+        let parallel = args.iter().position(|arg| arg == "--parallel").unwrap();
+        assert_eq!(args[parallel + 1], "4");
+        let slot_save_path = args.iter().position(|arg| arg == "--slot-save-path").unwrap();
+        assert_eq!(args[slot_save_path + 1], "/tmp/slots");
+    }
+}
@@ -5,33 +5,34 @@ use axum::{
     Router,
     Json,
     extract::State,
-    response::IntoResponse,
+    response::{ IntoResponse, Response },
     http::StatusCode,
 };
 use std::net::SocketAddr;
 use tokio::net::TcpListener;
+use serde::Deserialize;
 use serde_json::json;
 
 use crate::model::error::{ ModelError, ModelResult };
-use super::{ ModelConfig, ModelManager };
+use super::{ ModelConfig, ModelManagerInterface };
+
+type SharedManager = Arc<dyn ModelManagerInterface>;
+
+#[derive(Deserialize)]
+struct ModelNameRequest {
+    name: String,
+}
 
 pub struct ModelManagerServer {
-    manager: Arc<ModelManager>,
+    manager: SharedManager,
 }
 
 impl ModelManagerServer {
-    pub fn new(manager: Arc<ModelManager>) -> Self {
+    pub fn new(manager: SharedManager) -> Self {
         Self { manager }
     }
 
     pub async fn run(self, addr: &str) -> ModelResult<()> {
-        let app = Router::new()
-            .route("/models/load", post(Self::handle_load_model))
-            .route("/models/unload", post(Self::handle_unload_model))
-            .route("/models/status/:name", get(Self::handle_get_status))
-            .route("/models/list", get(Self::handle_list_models))
-            .with_state(self.manager);
-
         println!("Model Manager server starting on {}", addr);
 
         // Parse the address
@@ -40,64 +41,99 @@ impl ModelManagerServer {
             .map_err(|e| ModelError::ConfigError(format!("Invalid address: {}", e)))?;
 
         // Create the listener
-        let listener = TcpListener::bind(addr).await.map_err(|e| ModelError::IoError(e))?;
+        let listener = TcpListener::bind(addr).await.map_err(ModelError::IoError)?;
+
+        self.serve(listener).await
+    }
+
+    /// Serves on an already bound listener, e.g. one bound to port 0 in tests.
+    pub async fn serve(self, listener: TcpListener) -> ModelResult<()> {
+        let app = Router::new()
+            .route("/models/load", post(Self::handle_load_model))
+            .route("/models/load_by_name", post(Self::handle_load_model_by_name))
+            .route("/models/unload", post(Self::handle_unload_model))
+            .route("/models/status/:name", get(Self::handle_get_status))
+            .route("/models/config/:name", get(Self::handle_get_config))
+            .route("/models/list", get(Self::handle_list_models))
+            .route("/system/memory", get(Self::handle_memory_status))
+            .with_state(self.manager);
 
         // Start the server
-        axum::serve(listener, app).await.map_err(|e| ModelError::IoError(e))?;
+        axum::serve(listener, app).await.map_err(ModelError::IoError)?;
 
         Ok(())
     }
 
+    fn error_response(e: ModelError) -> Response {
+        let status = match e {
+            ModelError::ModelNotFound(_) => StatusCode::NOT_FOUND,
+            ModelError::ConfigError(_) | ModelError::InvalidConfig(_) => StatusCode::BAD_REQUEST,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        (status, Json(json!({ "error": e.to_string() }))).into_response()
+    }
+
     async fn handle_load_model(
-        State(manager): State<Arc<ModelManager>>,
+        State(manager): State<SharedManager>,
         Json(config): Json<ModelConfig>
     ) -> impl IntoResponse {
         match manager.load_model(config).await {
             Ok(()) => (StatusCode::OK, Json(())).into_response(),
-            Err(e) =>
-                (
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    Json(json!({ "error": e.to_string() })),
-                ).into_response(),
+            Err(e) => Self::error_response(e),
+        }
+    }
+
+    async fn handle_load_model_by_name(
+        State(manager): State<SharedManager>,
+        Json(request): Json<ModelNameRequest>
+    ) -> impl IntoResponse {
+        match manager.load_model_by_name(&request.name).await {
+            Ok(()) => (StatusCode::OK, Json(())).into_response(),
+            Err(e) => Self::error_response(e),
         }
     }
 
     async fn handle_unload_model(
-        State(manager): State<Arc<ModelManager>>,
-        Json(name): Json<String>
+        State(manager): State<SharedManager>,
+        Json(request): Json<ModelNameRequest>
     ) -> impl IntoResponse {
-        match manager.unload_model(&name).await {
+        match manager.unload_model(&request.name).await {
             Ok(()) => (StatusCode::OK, Json(())).into_response(),
-            Err(e) =>
-                (
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    Json(json!({ "error": e.to_string() })),
-                ).into_response(),
+            Err(e) => Self::error_response(e),
         }
     }
 
     async fn handle_get_status(
-        State(manager): State<Arc<ModelManager>>,
+        State(manager): State<SharedManager>,
         axum::extract::Path(name): axum::extract::Path<String>
     ) -> impl IntoResponse {
         match manager.get_model_status(&name).await {
             Ok(status) => (StatusCode::OK, Json(status)).into_response(),
-            Err(e) =>
-                (
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    Json(json!({ "error": e.to_string() })),
-                ).into_response(),
+            Err(e) => Self::error_response(e),
         }
     }
 
-    async fn handle_list_models(State(manager): State<Arc<ModelManager>>) -> impl IntoResponse {
+    async fn handle_get_config(
+        State(manager): State<SharedManager>,
+        axum::extract::Path(name): axum::extract::Path<String>
+    ) -> impl IntoResponse {
+        match manager.get_model_config(&name).await {
+            Ok(config) => (StatusCode::OK, Json(config)).into_response(),
+            Err(e) => Self::error_response(e),
+        }
+    }
+
+    async fn handle_list_models(State(manager): State<SharedManager>) -> impl IntoResponse {
         match manager.list_models().await {
             Ok(models) => (StatusCode::OK, Json(models)).into_response(),
-            Err(e) =>
-                (
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    Json(json!({ "error": e.to_string() })),
-                ).into_response(),
+            Err(e) => Self::error_response(e),
+        }
+    }
+
+    async fn handle_memory_status(State(manager): State<SharedManager>) -> impl IntoResponse {
+        match manager.memory_status().await {
+            Ok(status) => (StatusCode::OK, Json(status)).into_response(),
+            Err(e) => Self::error_response(e),
         }
     }
 }
@@ -1,4 +1,5 @@
 use log::info;
+use serde::{ Deserialize, Serialize };
 use sysinfo::System;
 use std::sync::Arc;
 use tokio::sync::RwLock;
@@ -84,17 +85,7 @@ impl SystemMemory {
         let mut sys = self.sys.write().await;
         sys.refresh_all();
 
-        let total_kb = sys.total_memory();
-        let used_kb = sys.used_memory();
-        let available_kb = total_kb - used_kb;
-
-        let status = MemoryStatus {
-            total_gb: ((total_kb as f64) / (1024.0 * 1024.0 * 1024.0)) as f32,
-            available_gb: ((available_kb as f64) / (1024.0 * 1024.0)) as f32,
-            used_gb: ((used_kb as f64) / (1024.0 * 1024.0 * 1024.0)) as f32,
-            usage_percentage: (((used_kb as f64) / (total_kb as f64)) * 100.0) as f32,
-        };
-
+        let status = MemoryStatus::from_bytes(sys.total_memory(), sys.used_memory());
         info!("Memory status: {:?}", status);
         status
     }
@@ -118,10 +109,38 @@ impl SystemMemory {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MemoryStatus {
     pub total_gb: f32,
     pub available_gb: f32,
     pub used_gb: f32,
     pub usage_percentage: f32,
 }
+
+impl MemoryStatus {
+    /// Summarizes memory as reported by `sysinfo`, which counts in bytes.
+    pub fn from_bytes(total: u64, used: u64) -> Self {
+        let gb = |bytes: u64| ((bytes as f64) / (1024.0 * 1024.0 * 1024.0)) as f32;
+        Self {
+            total_gb: gb(total),
+            available_gb: gb(total.saturating_sub(used)),
+            used_gb: gb(used),
+            usage_percentage: (((used as f64) / (total as f64)) * 100.0) as f32,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_memory_status_is_in_gigabytes() {
+        let gib = 1024 * 1024 * 1024;
+        let status = MemoryStatus::from_bytes(16 * gib, 12 * gib);
+        assert_eq!(status.total_gb, 16.0);
+        assert_eq!(status.used_gb, 12.0);
+        assert_eq!(status.available_gb, 4.0);
+        assert_eq!(status.usage_percentage, 75.0);
+    }
+}
@@ -3,15 +3,26 @@ use std::path::PathBuf;
 use std::collections::HashMap;
 use chrono::{ DateTime, Utc };
 
+use crate::llm::chat::ChatTemplate;
+use crate::llm::load_balancer::LoadBalanceStrategy;
+use super::error::{ ModelError, ModelResult };
+use super::model_kind::ModelKind;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ModelConfig {
     pub name: String,
     pub model_path: PathBuf,
+    /// Multimodal projector of a vision model, passed to llama-server as `--mmproj`
+    #[serde(default)]
+    pub mmproj_path: Option<PathBuf>,
     pub model_type: ModelType,
-    pub model_kind: String, // e.g. "Qwen", "LLaMA"
+    pub model_kind: ModelKind, // e.g. Qwen, LLaMA
 
     pub memory_config: ModelMemoryConfig,
     pub prompt_template: PromptTemplate,
+    // Overrides the model kind's chat template for `LLM::chat`
+    #[serde(default)]
+    pub chat_template: Option<ChatTemplate>,
     pub defaults: ModelDefaults,
     pub server_config: ServerConfig,
 }
@@ -30,10 +41,68 @@ pub struct ServerConfig {
     pub use_mmap: bool,
     pub use_gpu: bool,
 
+    // Replication: number of llama-server processes to run for this model
+    // and how requests are spread across them
+    #[serde(default = "default_replicas")]
+    pub replicas: usize,
+    #[serde(default)]
+    pub load_balancing: LoadBalanceStrategy,
+    // Requests each llama-server process handles at once (`--parallel`); the context
+    // window is shared between these slots
+    #[serde(default)]
+    pub parallel: Option<usize>,
+    // Directory slot KV caches are saved to and restored from (`--slot-save-path`),
+    // required by `LLMSession::save` and `restore`
+    #[serde(default)]
+    pub slot_save_path: Option<PathBuf>,
+
     // Additional configuration
     pub extra_args: HashMap<String, String>,
 }
 
+fn default_replicas() -> usize {
+    1
+}
+
+impl ServerConfig {
+    /// Ports used by each replica. A single replica keeps the configured port as is;
+    /// multiple replicas get consecutive ports starting at the configured one (or 8000),
+    /// which must all be valid port numbers.
+    pub fn replica_ports(&self) -> ModelResult<Vec<Option<u16>>> {
+        if self.replicas <= 1 {
+            return Ok(vec![self.port]);
+        }
+        let base = self.port.unwrap_or(8000);
+        (0..self.replicas)
+            .map(|i| {
+                u16::try_from(i)
+                    .ok()
+                    .and_then(|i| base.checked_add(i))
+                    .map(Some)
+                    .ok_or_else(|| {
+                        let message = format!(
+                            "{} replicas from port {} exceed port 65535",
+                            self.replicas,
+                            base
+                        );
+                        ModelError::ConfigError(message)
+                    })
+            })
+            .collect()
+    }
+
+    /// Base URLs of every replica.
+    pub fn replica_urls(&self) -> ModelResult<Vec<String>> {
+        Ok(
+            self
+                .replica_ports()?
+                .into_iter()
+                .map(|port| format!("http://{}:{}", self.host, port.unwrap_or(8000)))
+                .collect()
+        )
+    }
+}
+
 impl Default for ServerConfig {
     fn default() -> Self {
         Self {
@@ -45,6 +114,10 @@ impl Default for ServerConfig {
             num_threads: None,
             use_mmap: true,
             use_gpu: false,
+            replicas: 1,
+            load_balancing: LoadBalanceStrategy::default(),
+            parallel: None,
+            slot_save_path: None,
             extra_args: HashMap::new(),
         }
     }
@@ -55,24 +128,11 @@ pub enum ModelType {
     Text,
     Voice,
     Vision,
+    /// Served with `--embedding`, see `embedding::llama_server::LlamaServerEmbedder`
+    Embedding,
     #[serde(untagged)] Custom(String),
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
-pub enum TextModelKind {
-    Qwen,
-    LLaMA,
-    Mistral,
-    // TODO Add more
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
-pub enum AudioModelKind {
-    Whisper,
-    Qwen2Audio,
-    // Add more as needed
-}
-
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ModelMemoryConfig {
     pub min_ram_gb: f32,
@@ -86,6 +146,45 @@ pub struct PromptTemplate {
     pub required_keys: Vec<String>,
 }
 
+impl PromptTemplate {
+    /// Checks that every required key has a `{key}` placeholder in the template.
+    /// An empty template is valid; it stands for the model kind's default.
+    pub fn validate(&self) -> ModelResult<()> {
+        if self.template.is_empty() {
+            return Ok(());
+        }
+
+        let missing: Vec<&str> = self.required_keys
+            .iter()
+            .filter(|key| !self.template.contains(&format!("{{{}}}", key)))
+            .map(|key| key.as_str())
+            .collect();
+        if missing.is_empty() {
+            Ok(())
+        } else {
+            Err(
+                ModelError::InvalidConfig(
+                    format!("Prompt template has no placeholder for: {}", missing.join(", "))
+                )
+            )
+        }
+    }
+
+    /// Substitutes `values` into the template, failing if a required key has no value.
+    pub fn render(&self, values: &HashMap<&str, &str>) -> ModelResult<String> {
+        self.validate()?;
+
+        let mut rendered = self.template.clone();
+        for key in &self.required_keys {
+            let value = values
+                .get(key.as_str())
+                .ok_or_else(|| ModelError::InvalidConfig(format!("No value for key: {}", key)))?;
+            rendered = rendered.replace(&format!("{{{}}}", key), value);
+        }
+        Ok(rendered)
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ModelDefaults {
     pub temperature: f32,
@@ -111,6 +210,11 @@ pub struct ModelInfo {
     pub status: ModelStatus,
     pub last_used: DateTime<Utc>,
     pub server_port: Option<u16>,
+    #[serde(default)]
+    pub replica: usize,
+    // Name of the node hosting the model when managed through a `FederatedModelManager`
+    #[serde(default)]
+    pub node: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
@@ -120,3 +224,19 @@ pub enum ModelStatus {
     Stopped,
     Error(String),
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_replica_ports_must_fit() {
+        let config = ServerConfig { port: Some(9000), replicas: 3, ..ServerConfig::default() };
+        assert_eq!(config.replica_ports().unwrap(), vec![Some(9000), Some(9001), Some(9002)]);
+        assert_eq!(config.replica_urls().unwrap()[2], "http://localhost:9002");
+
+        let config = ServerConfig { port: Some(65534), replicas: 3, ..ServerConfig::default() };
+        assert!(matches!(config.replica_ports(), Err(ModelError::ConfigError(_))));
+        assert!(config.replica_urls().is_err());
+    }
+}
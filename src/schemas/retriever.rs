@@ -1,12 +1,11 @@
-use std::error::Error;
-
 use async_trait::async_trait;
 
 use super::document::Document;
+use crate::error::PyanoError;
 
 #[async_trait]
 pub trait Retriever: Sync + Send {
-    async fn get_relevant_documents(&self, query: &str) -> Result<Vec<Document>, Box<dyn Error>>;
+    async fn get_relevant_documents(&self, query: &str) -> Result<Vec<Document>, PyanoError>;
 }
 
 impl<R> From<R> for Box<dyn Retriever> where R: Retriever + 'static {
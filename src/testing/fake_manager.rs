@@ -0,0 +1,304 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{ Arc, Weak };
+
+use async_trait::async_trait;
+use chrono::{ DateTime, Utc };
+use log::info;
+use parking_lot::Mutex;
+
+use super::fake_server::FakeLlamaServer;
+use crate::llm::llm_builder::LLM;
+use crate::llm::options::LLMHTTPCallOptions;
+use crate::model::error::{ ModelError, ModelResult };
+use crate::model::system_memory::MemoryStatus;
+use crate::model::{
+    ModelConfig,
+    ModelDefaults,
+    ModelInfo,
+    ModelKind,
+    ModelKindRegistry,
+    ModelManagerInterface,
+    ModelMemoryConfig,
+    ModelStatus,
+    ModelType,
+    PromptTemplate,
+    ServerConfig,
+};
+
+/// What a `FakeModelManager` did, in order, so tests can assert on loading and eviction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FakeManagerEvent {
+    Loaded(String),
+    Unloaded(String),
+    Evicted(String),
+}
+
+struct FakeModel {
+    config: ModelConfig,
+    server: Arc<FakeLlamaServer>,
+    status: ModelStatus,
+    last_used: DateTime<Utc>,
+}
+
+/// An in-memory `ModelManagerInterface` backed by `FakeLlamaServer`s instead of llama-server
+/// processes. Each known model gets its own fake server, which only answers while the model
+/// is loaded.
+///
+/// Memory is simulated with a fixed budget: loading a model that does not fit evicts the
+/// least recently used models first, like `ModelManager` does.
+pub struct FakeModelManager {
+    me: Weak<FakeModelManager>,
+    total_memory_gb: f32,
+    models: Mutex<HashMap<String, FakeModel>>,
+    events: Mutex<Vec<FakeManagerEvent>>,
+}
+
+impl FakeModelManager {
+    pub fn new(total_memory_gb: f32) -> Arc<Self> {
+        Arc::new_cyclic(|me| Self {
+            me: me.clone(),
+            total_memory_gb,
+            models: Mutex::new(HashMap::new()),
+            events: Mutex::new(Vec::new()),
+        })
+    }
+
+    /// A minimal config for a text model needing `min_ram_gb` of memory.
+    pub fn model_config(name: &str, min_ram_gb: f32) -> ModelConfig {
+        ModelConfig {
+            name: name.to_string(),
+            model_path: PathBuf::from(format!("{}.gguf", name)),
+            mmproj_path: None,
+            model_type: ModelType::Text,
+            model_kind: ModelKind::Custom("fake".to_string()),
+            memory_config: ModelMemoryConfig {
+                min_ram_gb,
+                recommended_ram_gb: min_ram_gb,
+                gpu_memory_gb: None,
+            },
+            prompt_template: PromptTemplate {
+                template: "{system_prompt}\n{user_prompt}".to_string(),
+                required_keys: vec!["system_prompt".to_string(), "user_prompt".to_string()],
+            },
+            chat_template: None,
+            defaults: ModelDefaults {
+                temperature: 0.7,
+                top_p: 0.9,
+                top_k: 40,
+                max_tokens: 256,
+                repetition_penalty: 1.1,
+            },
+            server_config: ServerConfig::default(),
+        }
+    }
+
+    /// Makes a model known without loading it and returns its (not yet answering) server.
+    /// The config's host and port are rewritten to point at the fake server.
+    pub async fn add_model(&self, mut config: ModelConfig) -> Arc<FakeLlamaServer> {
+        if let Some(server) = self.server(&config.name) {
+            return server;
+        }
+
+        let server = Arc::new(FakeLlamaServer::start_named(&config.name).await);
+        server.set_healthy(false);
+
+        config.server_config.host = "127.0.0.1".to_string();
+        config.server_config.port = Some(server.port());
+        config.server_config.replicas = 1;
+
+        self.models
+            .lock()
+            .entry(config.name.clone())
+            .or_insert(FakeModel {
+                config,
+                server: server.clone(),
+                status: ModelStatus::Stopped,
+                last_used: Utc::now(),
+            })
+            .server.clone()
+    }
+
+    /// The fake server of a known model, to script completions or inspect requests.
+    pub fn server(&self, name: &str) -> Option<Arc<FakeLlamaServer>> {
+        self.models
+            .lock()
+            .get(name)
+            .map(|model| model.server.clone())
+    }
+
+    pub fn events(&self) -> Vec<FakeManagerEvent> {
+        self.events.lock().clone()
+    }
+
+    /// Marks a model as just used, which protects it from eviction.
+    pub fn touch(&self, name: &str) {
+        if let Some(model) = self.models.lock().get_mut(name) {
+            model.last_used = Utc::now();
+        }
+    }
+
+    fn used_memory_gb(models: &HashMap<String, FakeModel>) -> f32 {
+        models
+            .values()
+            .filter(|model| model.status == ModelStatus::Running)
+            .map(|model| model.config.memory_config.min_ram_gb)
+            .sum()
+    }
+
+    fn start(&self, name: &str) -> ModelResult<()> {
+        let mut models = self.models.lock();
+
+        let required = match models.get(name) {
+            Some(model) if model.status == ModelStatus::Running => {
+                return Ok(());
+            }
+            Some(model) => model.config.memory_config.min_ram_gb,
+            None => {
+                return Err(ModelError::ModelNotFound(name.to_string()));
+            }
+        };
+        if required > self.total_memory_gb {
+            return Err(
+                ModelError::MemoryError(
+                    format!(
+                        "Model {} needs {:.1} GB, only {:.1} GB in total",
+                        name,
+                        required,
+                        self.total_memory_gb
+                    )
+                )
+            );
+        }
+
+        // Evict least recently used models until the new one fits
+        while Self::used_memory_gb(&models) + required > self.total_memory_gb {
+            let victim = models
+                .iter()
+                .filter(|(_, model)| model.status == ModelStatus::Running)
+                .min_by_key(|(_, model)| model.last_used)
+                .map(|(victim, _)| victim.clone())
+                .ok_or_else(|| ModelError::MemoryError("Nothing left to evict".to_string()))?;
+
+            info!("Evicting model {} to make room for {}", victim, name);
+            if let Some(model) = models.get_mut(&victim) {
+                model.status = ModelStatus::Stopped;
+                model.server.set_healthy(false);
+            }
+            self.events.lock().push(FakeManagerEvent::Evicted(victim));
+        }
+
+        if let Some(model) = models.get_mut(name) {
+            model.status = ModelStatus::Running;
+            model.last_used = Utc::now();
+            model.server.set_healthy(true);
+        }
+        self.events.lock().push(FakeManagerEvent::Loaded(name.to_string()));
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl ModelManagerInterface for FakeModelManager {
+    async fn load_model(&self, config: ModelConfig) -> ModelResult<()> {
+        config.prompt_template.validate()?;
+        let name = config.name.clone();
+        self.add_model(config).await;
+        self.start(&name)
+    }
+
+    async fn unload_model(&self, name: &str) -> ModelResult<()> {
+        let mut models = self.models.lock();
+        match models.get_mut(name) {
+            Some(model) if model.status == ModelStatus::Running => {
+                model.status = ModelStatus::Stopped;
+                model.server.set_healthy(false);
+                self.events.lock().push(FakeManagerEvent::Unloaded(name.to_string()));
+                Ok(())
+            }
+            _ => Err(ModelError::ModelNotFound(name.to_string())),
+        }
+    }
+
+    /// Like `ModelManager`, models that are known but not loaded are reported as not found.
+    /// Asking for a running model counts as using it, since `LLM` does so before each request.
+    async fn get_model_status(&self, name: &str) -> ModelResult<ModelStatus> {
+        let mut models = self.models.lock();
+        match models.get_mut(name) {
+            Some(model) if model.status == ModelStatus::Running => {
+                model.last_used = Utc::now();
+                Ok(ModelStatus::Running)
+            }
+            _ => Err(ModelError::ModelNotFound(name.to_string())),
+        }
+    }
+
+    async fn list_models(&self) -> ModelResult<Vec<ModelInfo>> {
+        Ok(
+            self.models
+                .lock()
+                .values()
+                .filter(|model| model.status == ModelStatus::Running)
+                .map(|model| ModelInfo {
+                    name: model.config.name.clone(),
+                    model_type: model.config.model_type.clone(),
+                    status: model.status.clone(),
+                    last_used: model.last_used,
+                    server_port: Some(model.server.port()),
+                    replica: 0,
+                    node: None,
+                })
+                .collect()
+        )
+    }
+
+    async fn get_or_create_llm(
+        &self,
+        model_name: &str,
+        options: Option<LLMHTTPCallOptions>,
+        auto_load: bool
+    ) -> ModelResult<LLM> {
+        if auto_load {
+            self.load_model_by_name(model_name).await?;
+        }
+
+        let config = self.get_model_config(model_name).await?;
+        let server_url = config.server_config.replica_urls()?[0].clone();
+        let (llm_options, processor) = ModelKindRegistry::llm_settings(
+            &config,
+            options.unwrap_or_default(),
+            server_url
+        );
+
+        let mut builder = LLM::builder()
+            .with_options(llm_options)
+            .with_process_response(move |stream| processor(stream));
+        if let Some(manager) = self.me.upgrade() {
+            builder = builder.with_model_manager(manager, model_name.to_string(), auto_load);
+        }
+
+        Ok(builder.build()?)
+    }
+
+    async fn load_model_by_name(&self, name: &str) -> ModelResult<()> {
+        self.start(name)
+    }
+
+    async fn get_model_config(&self, name: &str) -> ModelResult<ModelConfig> {
+        self.models
+            .lock()
+            .get(name)
+            .map(|model| model.config.clone())
+            .ok_or_else(|| ModelError::ModelNotFound(name.to_string()))
+    }
+
+    async fn memory_status(&self) -> ModelResult<MemoryStatus> {
+        let used_gb = Self::used_memory_gb(&self.models.lock());
+        Ok(MemoryStatus {
+            total_gb: self.total_memory_gb,
+            available_gb: self.total_memory_gb - used_gb,
+            used_gb,
+            usage_percentage: (used_gb / self.total_memory_gb) * 100.0,
+        })
+    }
+}
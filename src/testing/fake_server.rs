@@ -0,0 +1,637 @@
+use std::collections::{ HashMap, VecDeque };
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::atomic::{ AtomicBool, AtomicUsize, Ordering };
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::{
+    body::Body,
+    extract::{ Path, Query, State },
+    http::{ header, StatusCode },
+    response::{ IntoResponse, Response },
+    routing::{ get, post },
+    Json,
+    Router,
+};
+use bytes::Bytes;
+use parking_lot::Mutex;
+use serde_json::{ json, Value };
+use tokio::net::TcpListener;
+use tokio::task::JoinHandle;
+
+/// One scripted answer of the fake completion server.
+#[derive(Debug, Clone)]
+pub struct FakeCompletion {
+    pub tokens: Vec<String>,
+    /// llama.cpp's `stop_type`: `"eos"`, `"word"` or `"limit"`
+    pub stop_type: String,
+    pub stopping_word: String,
+    pub prompt_tokens: usize,
+    pub token_delay: Duration,
+    /// Log-probability of each token, reported when the request sets `n_probs`; missing
+    /// entries are 0
+    pub logprobs: Vec<f64>,
+    /// Answer with this status and an error body instead of a completion
+    pub status: Option<StatusCode>,
+    /// End a llama.cpp stream with this error event instead of the final message
+    pub stream_error: Option<String>,
+}
+
+impl FakeCompletion {
+    pub fn new(tokens: &[&str]) -> Self {
+        Self {
+            tokens: tokens
+                .iter()
+                .map(|t| t.to_string())
+                .collect(),
+            stop_type: "eos".to_string(),
+            stopping_word: String::new(),
+            prompt_tokens: 8,
+            token_delay: Duration::ZERO,
+            logprobs: Vec::new(),
+            status: None,
+            stream_error: None,
+        }
+    }
+
+    /// A completion that answers `text` as a single token.
+    pub fn text(text: &str) -> Self {
+        Self::new(&[text])
+    }
+
+    /// A request that fails with `status`, e.g. `503` while the model is loading.
+    pub fn failing(status: StatusCode) -> Self {
+        Self {
+            status: Some(status),
+            ..Self::new(&[])
+        }
+    }
+
+    pub fn with_stop_word(mut self, word: &str) -> Self {
+        self.stop_type = "word".to_string();
+        self.stopping_word = word.to_string();
+        self
+    }
+
+    pub fn with_prompt_tokens(mut self, prompt_tokens: usize) -> Self {
+        self.prompt_tokens = prompt_tokens;
+        self
+    }
+
+    pub fn with_token_delay(mut self, delay: Duration) -> Self {
+        self.token_delay = delay;
+        self
+    }
+
+    pub fn with_logprobs(mut self, logprobs: &[f64]) -> Self {
+        self.logprobs = logprobs.to_vec();
+        self
+    }
+
+    /// A stream that breaks off after the tokens with an error event, as llama.cpp sends when
+    /// generation fails midway.
+    pub fn with_stream_error(mut self, message: &str) -> Self {
+        self.stream_error = Some(message.to_string());
+        self
+    }
+
+    pub fn content(&self) -> String {
+        self.tokens.concat()
+    }
+}
+
+struct FakeServerState {
+    script: Mutex<VecDeque<FakeCompletion>>,
+    default_completion: Mutex<FakeCompletion>,
+    requests: Mutex<Vec<Value>>,
+    healthy: AtomicBool,
+    open_streams: Arc<AtomicUsize>,
+    model: String,
+    embedding_dimensions: AtomicUsize,
+    slots: AtomicUsize,
+    /// Prompt tokens cached in each slot, by `id_slot`
+    slot_tokens: Mutex<HashMap<usize, usize>>,
+    /// Saved slot files, with their token count
+    slot_files: Mutex<HashMap<String, usize>>,
+    in_flight: AtomicUsize,
+    max_in_flight: AtomicUsize,
+}
+
+/// An in-process stand-in for llama-server. It speaks the parts of the llama.cpp HTTP API
+/// that pyano uses: `/completion` (plain JSON and SSE streaming, with timings) and `/health`.
+/// The same script also answers the OpenAI-compatible `/v1/chat/completions` and Ollama's
+/// `/api/chat`, so every `LLMProvider` can be tested against it. `/embedding` answers with
+/// vectors derived from the bytes of each text, and `/slots/{id}` saves, restores and erases
+/// the prompt a request with `id_slot` left in the slot, in memory.
+///
+/// Completions are taken from a script queue first, then from a default completion. Every
+/// request body is recorded so tests can assert on what was sent.
+///
+/// # Usage
+/// ```rust,ignore
+/// let server = FakeLlamaServer::start().await;
+/// server.push_completion(FakeCompletion::new(&["Hello", " world"]));
+///
+/// let options = LLMHTTPCallOptions::new()
+///     .with_server_url(server.url())
+///     .with_prompt_template("{system_prompt}{user_prompt}".to_string())
+///     .build()?;
+/// ```
+pub struct FakeLlamaServer {
+    addr: SocketAddr,
+    state: Arc<FakeServerState>,
+    handle: JoinHandle<()>,
+}
+
+impl FakeLlamaServer {
+    /// Starts a healthy server on a free localhost port.
+    pub async fn start() -> Self {
+        Self::start_named("fake-model").await
+    }
+
+    /// Starts a server that reports `model` as its model name.
+    pub async fn start_named(model: &str) -> Self {
+        let state = Arc::new(FakeServerState {
+            script: Mutex::new(VecDeque::new()),
+            default_completion: Mutex::new(FakeCompletion::text("Hello from the fake server")),
+            requests: Mutex::new(Vec::new()),
+            healthy: AtomicBool::new(true),
+            open_streams: Arc::new(AtomicUsize::new(0)),
+            model: model.to_string(),
+            embedding_dimensions: AtomicUsize::new(8),
+            slots: AtomicUsize::new(1),
+            slot_tokens: Mutex::new(HashMap::new()),
+            slot_files: Mutex::new(HashMap::new()),
+            in_flight: AtomicUsize::new(0),
+            max_in_flight: AtomicUsize::new(0),
+        });
+
+        let app = Router::new()
+            .route("/health", get(handle_health))
+            .route("/completion", post(handle_completion))
+            .route("/v1/chat/completions", post(handle_chat_completions))
+            .route("/api/chat", post(handle_ollama_chat))
+            .route("/tokenize", post(handle_tokenize))
+            .route("/embedding", post(handle_embedding))
+            .route("/v1/models", get(handle_models))
+            .route("/props", get(handle_props))
+            .route("/slots/:id", post(handle_slot_action))
+            .with_state(state.clone());
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind fake llama-server");
+        let addr = listener.local_addr().expect("fake llama-server address");
+        let handle = tokio::spawn(async move {
+            let _ = axum::serve(listener, app).await;
+        });
+
+        Self { addr, state, handle }
+    }
+
+    pub fn url(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    pub fn port(&self) -> u16 {
+        self.addr.port()
+    }
+
+    /// Queues a completion; queued completions are served in order before the default one.
+    pub fn push_completion(&self, completion: FakeCompletion) {
+        self.state.script.lock().push_back(completion);
+    }
+
+    pub fn set_default_completion(&self, completion: FakeCompletion) {
+        *self.state.default_completion.lock() = completion;
+    }
+
+    /// An unhealthy server answers `/health` and `/completion` with 503, like a llama-server
+    /// that is still loading its model.
+    pub fn set_healthy(&self, healthy: bool) {
+        self.state.healthy.store(healthy, Ordering::SeqCst);
+    }
+
+    /// Size of the vectors `/embedding` returns, and `/v1/models` reports as `n_embd`.
+    pub fn set_embedding_dimensions(&self, dimensions: usize) {
+        self.state.embedding_dimensions.store(dimensions, Ordering::SeqCst);
+    }
+
+    /// Number of slots `/props` reports as `total_slots`. Requests beyond it are still
+    /// served, so tests can check clients stay within it with `max_concurrent_requests`.
+    pub fn set_slots(&self, slots: usize) {
+        self.state.slots.store(slots, Ordering::SeqCst);
+    }
+
+    /// Prompt tokens cached in slot `id_slot`, counted one per word.
+    pub fn slot_tokens(&self, id_slot: usize) -> usize {
+        self.state.slot_tokens.lock().get(&id_slot).copied().unwrap_or_default()
+    }
+
+    /// Most non-streaming `/completion` requests that were being answered at the same time.
+    pub fn max_concurrent_requests(&self) -> usize {
+        self.state.max_in_flight.load(Ordering::SeqCst)
+    }
+
+    /// JSON bodies of every generation and embedding request received so far.
+    pub fn requests(&self) -> Vec<Value> {
+        self.state.requests.lock().clone()
+    }
+
+    pub fn last_request(&self) -> Option<Value> {
+        self.state.requests.lock().last().cloned()
+    }
+
+    /// Streamed responses still being sent. A stream stops as soon as the client disconnects,
+    /// like llama-server stops generating and frees the slot.
+    pub fn open_streams(&self) -> usize {
+        self.state.open_streams.load(Ordering::SeqCst)
+    }
+}
+
+impl Drop for FakeLlamaServer {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
+
+fn unavailable() -> Response {
+    (
+        StatusCode::SERVICE_UNAVAILABLE,
+        Json(json!({ "error": { "code": 503, "message": "Loading model", "type": "unavailable_error" } })),
+    ).into_response()
+}
+
+async fn handle_health(State(state): State<Arc<FakeServerState>>) -> Response {
+    if state.healthy.load(Ordering::SeqCst) {
+        (StatusCode::OK, Json(json!({ "status": "ok" }))).into_response()
+    } else {
+        unavailable()
+    }
+}
+
+/// Records the request and picks the completion to serve, `Err` being an error response.
+/// `limit` is the name of the request's max-tokens field, honoured the way llama.cpp does.
+fn next_completion(
+    state: &FakeServerState,
+    request: &Value,
+    limit: Option<&Value>
+) -> Result<FakeCompletion, Box<Response>> {
+    state.requests.lock().push(request.clone());
+
+    if !state.healthy.load(Ordering::SeqCst) {
+        return Err(Box::new(unavailable()));
+    }
+
+    let mut completion = state.script
+        .lock()
+        .pop_front()
+        .unwrap_or_else(|| state.default_completion.lock().clone());
+
+    if let Some(status) = completion.status {
+        let body = json!({ "error": { "code": status.as_u16(), "message": "scripted failure" } });
+        return Err(Box::new((status, Json(body)).into_response()));
+    }
+
+    if let Some(limit) = limit.and_then(|n| n.as_i64()) {
+        if limit >= 0 && (limit as usize) < completion.tokens.len() {
+            completion.tokens.truncate(limit as usize);
+            completion.stop_type = "limit".to_string();
+            completion.stopping_word = String::new();
+        }
+    }
+
+    Ok(completion)
+}
+
+fn is_stream(request: &Value) -> bool {
+    request
+        .get("stream")
+        .and_then(|s| s.as_bool())
+        .unwrap_or(false)
+}
+
+async fn handle_completion(
+    State(state): State<Arc<FakeServerState>>,
+    Json(request): Json<Value>
+) -> Response {
+    let completion = match next_completion(&state, &request, request.get("n_predict")) {
+        Ok(completion) => completion,
+        Err(response) => {
+            return *response;
+        }
+    };
+
+    if let Some(id_slot) = request["id_slot"].as_u64() {
+        let tokens = request["prompt"].as_str().unwrap_or_default().split_whitespace().count();
+        state.slot_tokens.lock().insert(id_slot as usize, tokens);
+    }
+
+    let with_probs = request["n_probs"].as_u64().unwrap_or(0) > 0;
+    if is_stream(&request) {
+        stream_completion(&state, completion, with_probs)
+    } else {
+        // Generating takes as long as streaming the tokens would
+        let in_flight = state.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+        state.max_in_flight.fetch_max(in_flight, Ordering::SeqCst);
+        tokio::time::sleep(completion.token_delay * (completion.tokens.len() as u32)).await;
+        state.in_flight.fetch_sub(1, Ordering::SeqCst);
+
+        let mut body = final_message(&completion, &state.model);
+        body["content"] = json!(completion.content());
+        if with_probs {
+            let probs: Vec<Value> = (0..completion.tokens.len())
+                .map(|i| token_probability(&completion, i))
+                .collect();
+            body["completion_probabilities"] = json!(probs);
+        }
+        (StatusCode::OK, Json(body)).into_response()
+    }
+}
+
+/// Tokenizes one token per whitespace-separated word, which keeps counts easy to predict.
+async fn handle_tokenize(Json(request): Json<Value>) -> Response {
+    let content = request["content"].as_str().unwrap_or_default();
+    let tokens: Vec<usize> = content
+        .split_whitespace()
+        .enumerate()
+        .map(|(i, _)| i)
+        .collect();
+    (StatusCode::OK, Json(json!({ "tokens": tokens }))).into_response()
+}
+
+/// Embeds each text by summing its bytes into `embedding_dimensions` buckets, unnormalized,
+/// in the format of current llama-server versions.
+async fn handle_embedding(
+    State(state): State<Arc<FakeServerState>>,
+    Json(request): Json<Value>
+) -> Response {
+    state.requests.lock().push(request.clone());
+    if !state.healthy.load(Ordering::SeqCst) {
+        return unavailable();
+    }
+
+    let dimensions = state.embedding_dimensions.load(Ordering::SeqCst).max(1);
+    let texts: Vec<&str> = match &request["content"] {
+        Value::Array(texts) => texts.iter().filter_map(|text| text.as_str()).collect(),
+        text => text.as_str().into_iter().collect(),
+    };
+    let embeddings: Vec<Value> = texts
+        .iter()
+        .enumerate()
+        .map(|(index, text)| {
+            let mut embedding = vec![0.0f32; dimensions];
+            for (i, byte) in text.bytes().enumerate() {
+                embedding[i % dimensions] += f32::from(byte);
+            }
+            json!({ "index": index, "embedding": [embedding] })
+        })
+        .collect();
+    (StatusCode::OK, Json(json!(embeddings))).into_response()
+}
+
+async fn handle_slot_action(
+    State(state): State<Arc<FakeServerState>>,
+    Path(id_slot): Path<usize>,
+    Query(query): Query<HashMap<String, String>>,
+    Json(request): Json<Value>
+) -> Response {
+    let filename = request["filename"].as_str().unwrap_or_default().to_string();
+    let failed = |message: &str| {
+        let body = json!({ "error": { "code": 400, "message": message } });
+        (StatusCode::BAD_REQUEST, Json(body)).into_response()
+    };
+
+    let body = match query.get("action").map(String::as_str) {
+        Some("save") => {
+            let tokens = state.slot_tokens.lock().get(&id_slot).copied().unwrap_or_default();
+            state.slot_files.lock().insert(filename.clone(), tokens);
+            json!({ "id_slot": id_slot, "filename": filename, "n_saved": tokens })
+        }
+        Some("restore") => {
+            let Some(tokens) = state.slot_files.lock().get(&filename).copied() else {
+                return failed("failed to restore slot, could not read file");
+            };
+            state.slot_tokens.lock().insert(id_slot, tokens);
+            json!({ "id_slot": id_slot, "filename": filename, "n_restored": tokens })
+        }
+        Some("erase") => {
+            let tokens = state.slot_tokens.lock().remove(&id_slot).unwrap_or_default();
+            json!({ "id_slot": id_slot, "n_erased": tokens })
+        }
+        _ => {
+            return failed("Invalid action");
+        }
+    };
+    (StatusCode::OK, Json(body)).into_response()
+}
+
+async fn handle_props(State(state): State<Arc<FakeServerState>>) -> Response {
+    let total_slots = state.slots.load(Ordering::SeqCst);
+    (StatusCode::OK, Json(json!({ "total_slots": total_slots }))).into_response()
+}
+
+async fn handle_models(State(state): State<Arc<FakeServerState>>) -> Response {
+    let n_embd = state.embedding_dimensions.load(Ordering::SeqCst);
+    let body = json!({
+        "object": "list",
+        "data": [{ "id": state.model, "object": "model", "meta": { "n_embd": n_embd } }]
+    });
+    (StatusCode::OK, Json(body)).into_response()
+}
+
+/// OpenAI's `finish_reason` for a llama.cpp `stop_type`.
+fn finish_reason(completion: &FakeCompletion) -> &'static str {
+    if completion.stop_type == "limit" { "length" } else { "stop" }
+}
+
+async fn handle_chat_completions(
+    State(state): State<Arc<FakeServerState>>,
+    Json(request): Json<Value>
+) -> Response {
+    let completion = match next_completion(&state, &request, request.get("max_tokens")) {
+        Ok(completion) => completion,
+        Err(response) => {
+            return *response;
+        }
+    };
+    let usage = json!({
+        "prompt_tokens": completion.prompt_tokens,
+        "completion_tokens": completion.tokens.len(),
+        "total_tokens": completion.prompt_tokens + completion.tokens.len()
+    });
+
+    if !is_stream(&request) {
+        let body = json!({
+            "object": "chat.completion",
+            "model": state.model,
+            "choices": [{
+                "index": 0,
+                "message": { "role": "assistant", "content": completion.content() },
+                "finish_reason": finish_reason(&completion)
+            }],
+            "usage": usage
+        });
+        return (StatusCode::OK, Json(body)).into_response();
+    }
+
+    let chunk = |delta: Value, finish_reason: Option<&str>| {
+        let body = json!({
+            "object": "chat.completion.chunk",
+            "model": state.model,
+            "choices": [{ "index": 0, "delta": delta, "finish_reason": finish_reason }]
+        });
+        format!("data: {}\n\n", body)
+    };
+    let mut frames = vec![chunk(json!({ "role": "assistant" }), None)];
+    frames.extend(completion.tokens.iter().map(|token| chunk(json!({ "content": token }), None)));
+    frames.push(chunk(json!({}), Some(finish_reason(&completion))));
+    frames.push("data: [DONE]\n\n".to_string());
+
+    stream_frames(&state, frames, completion.token_delay, "text/event-stream")
+}
+
+async fn handle_ollama_chat(
+    State(state): State<Arc<FakeServerState>>,
+    Json(request): Json<Value>
+) -> Response {
+    let limit = request.get("options").and_then(|options| options.get("num_predict"));
+    let completion = match next_completion(&state, &request, limit) {
+        Ok(completion) => completion,
+        Err(response) => {
+            return *response;
+        }
+    };
+    let done_reason = if completion.stop_type == "limit" { "length" } else { "stop" };
+    let message = |content: String, done: bool| {
+        let mut body = json!({
+            "model": state.model,
+            "message": { "role": "assistant", "content": content },
+            "done": done
+        });
+        if done {
+            body["done_reason"] = json!(done_reason);
+            body["prompt_eval_count"] = json!(completion.prompt_tokens);
+            body["prompt_eval_duration"] = json!(1_000_000);
+            body["eval_count"] = json!(completion.tokens.len());
+            body["eval_duration"] = json!(completion.tokens.len().max(1) * 2_000_000);
+        }
+        body
+    };
+
+    if !is_stream(&request) {
+        return (StatusCode::OK, Json(message(completion.content(), true))).into_response();
+    }
+
+    let mut frames: Vec<String> = completion.tokens
+        .iter()
+        .map(|token| format!("{}\n", message(token.clone(), false)))
+        .collect();
+    frames.push(format!("{}\n", message(String::new(), true)));
+
+    stream_frames(&state, frames, completion.token_delay, "application/x-ndjson")
+}
+
+/// The last message of a generation, carrying stop reason, token counts and timings.
+fn final_message(completion: &FakeCompletion, model: &str) -> Value {
+    let predicted = completion.tokens.len();
+    json!({
+        "content": "",
+        "stop": true,
+        "model": model,
+        "stop_type": completion.stop_type,
+        "stopping_word": completion.stopping_word,
+        "tokens_predicted": predicted,
+        "tokens_evaluated": completion.prompt_tokens,
+        "tokens_cached": 0,
+        "truncated": false,
+        "timings": {
+            "prompt_n": completion.prompt_tokens,
+            "prompt_ms": 1.0,
+            "prompt_per_token_ms": 1.0 / (completion.prompt_tokens.max(1) as f64),
+            "prompt_per_second": (completion.prompt_tokens as f64) * 1000.0,
+            "predicted_n": predicted,
+            "predicted_ms": (predicted.max(1) as f64) * 2.0,
+            "predicted_per_token_ms": 2.0,
+            "predicted_per_second": 500.0
+        }
+    })
+}
+
+/// llama.cpp's `completion_probabilities` entry for token `index`, its only alternative.
+fn token_probability(completion: &FakeCompletion, index: usize) -> Value {
+    let token = &completion.tokens[index];
+    let logprob = completion.logprobs.get(index).copied().unwrap_or(0.0);
+    json!({
+        "id": index,
+        "token": token,
+        "logprob": logprob,
+        "top_logprobs": [{ "id": index, "token": token, "logprob": logprob }]
+    })
+}
+
+fn stream_completion(
+    state: &FakeServerState,
+    completion: FakeCompletion,
+    with_probs: bool
+) -> Response {
+    let mut frames: Vec<String> = completion.tokens
+        .iter()
+        .enumerate()
+        .map(|(i, token)| {
+            let mut frame = json!({ "content": token, "stop": false });
+            if with_probs {
+                frame["completion_probabilities"] = json!([token_probability(&completion, i)]);
+            }
+            frame.to_string()
+        })
+        .collect();
+    frames.push(match &completion.stream_error {
+        Some(message) => json!({ "error": { "code": 500, "message": message } }).to_string(),
+        None => final_message(&completion, &state.model).to_string(),
+    });
+    let frames = frames
+        .into_iter()
+        .map(|event| format!("data: {}\n\n", event))
+        .collect();
+
+    stream_frames(state, frames, completion.token_delay, "text/event-stream")
+}
+
+/// Counts a stream as open until it is dropped, either finished or abandoned by the client.
+struct OpenStream(Arc<AtomicUsize>);
+
+impl OpenStream {
+    fn new(open_streams: &Arc<AtomicUsize>) -> Self {
+        open_streams.fetch_add(1, Ordering::SeqCst);
+        Self(open_streams.clone())
+    }
+}
+
+impl Drop for OpenStream {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Streams `frames` one by one, waiting `delay` before each.
+fn stream_frames(
+    state: &FakeServerState,
+    frames: Vec<String>,
+    delay: Duration,
+    content_type: &str
+) -> Response {
+    let pending = (frames.into_iter(), OpenStream::new(&state.open_streams));
+    let stream = futures::stream::unfold(pending, move |(mut frames, open)| async move {
+        let frame = frames.next()?;
+        if !delay.is_zero() {
+            tokio::time::sleep(delay).await;
+        }
+        Some((Ok::<_, Infallible>(Bytes::from(frame)), (frames, open)))
+    });
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, content_type)
+        .body(Body::from_stream(stream))
+        .expect("valid streaming response")
+}
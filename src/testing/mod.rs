@@ -0,0 +1,83 @@
+//! Test support: a scriptable fake llama-server and an in-memory model manager, so `LLM`,
+//! `Agent` and model management can be exercised without llama-server binaries or GGUF files.
+//!
+//! Available to the crate's own tests and, with the `test-support` feature, to dependents. The
+//! end-to-end tests built on it live in `tests/` and need `--features test-support`.
+
+mod fake_manager;
+mod fake_server;
+
+pub use fake_manager::{ FakeManagerEvent, FakeModelManager };
+pub use fake_server::{ FakeCompletion, FakeLlamaServer };
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::llm::llm_builder::{ LLMBuilder, LLM };
+    use crate::llm::options::LLMHTTPCallOptions;
+    use crate::llm::resilience::RetryPolicy;
+    use crate::llm::stream_processing::llamacpp_process_stream;
+    use crate::model::ModelManagerInterface;
+    use futures::StreamExt;
+
+    fn builder_for(server: &FakeLlamaServer) -> LLMBuilder {
+        let options = LLMHTTPCallOptions::new()
+            .with_server_url(server.url())
+            .with_prompt_template("{system_prompt}\n{user_prompt}".to_string())
+            .build().unwrap();
+        LLM::builder().with_options(options).with_process_response(llamacpp_process_stream)
+    }
+
+    fn llm_for(server: &FakeLlamaServer) -> LLM {
+        builder_for(server).build().unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_streaming_completion() {
+        let server = FakeLlamaServer::start().await;
+        server.push_completion(FakeCompletion::new(&["Hello", ",", " world"]));
+
+        let llm = llm_for(&server);
+        let mut stream = llm.response_stream("Hi", "Be brief").await.unwrap();
+        let mut output = String::new();
+        while let Some(chunk) = stream.next().await {
+            output.push_str(&String::from_utf8_lossy(&chunk.unwrap()));
+        }
+
+        assert_eq!(output, "Hello, world");
+        let request = server.last_request().unwrap();
+        assert_eq!(request["stream"], true);
+        assert_eq!(request["prompt"], "Be brief\nHi");
+    }
+
+    #[tokio::test]
+    async fn test_unhealthy_server_is_unavailable() {
+        let server = FakeLlamaServer::start().await;
+        server.set_healthy(false);
+
+        let llm = builder_for(&server).with_retry_policy(RetryPolicy::none()).build().unwrap();
+        assert!(llm.response("Hi", "").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_manager_evicts_least_recently_used() {
+        let manager = FakeModelManager::new(10.0);
+        manager.load_model(FakeModelManager::model_config("small", 4.0)).await.unwrap();
+        manager.load_model(FakeModelManager::model_config("medium", 4.0)).await.unwrap();
+        manager.touch("small");
+
+        manager.load_model(FakeModelManager::model_config("large", 5.0)).await.unwrap();
+
+        assert_eq!(
+            manager.events(),
+            vec![
+                FakeManagerEvent::Loaded("small".to_string()),
+                FakeManagerEvent::Loaded("medium".to_string()),
+                FakeManagerEvent::Evicted("medium".to_string()),
+                FakeManagerEvent::Loaded("large".to_string())
+            ]
+        );
+        assert!(manager.get_model_status("medium").await.is_err());
+        assert_eq!(manager.memory_status().await.unwrap().used_gb, 9.0);
+    }
+}
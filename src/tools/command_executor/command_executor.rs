@@ -1,9 +1,8 @@
-use std::error::Error;
-
 use async_trait::async_trait;
 use serde::{ Deserialize, Serialize };
 use serde_json::{ json, Value };
 
+use crate::error::BoxError;
 use crate::tools::Tool;
 
 pub struct CommandExecutor {
@@ -127,7 +126,7 @@ impl Tool for CommandExecutor {
         }
     }
 
-    async fn run(&self, input: Value) -> Result<Value, Box<dyn Error>> {
+    async fn run(&self, input: Value) -> Result<Value, BoxError> {
         let commands: Vec<CommandInput> = serde_json::from_value(input)?;
         let mut results = Vec::new();
 
@@ -2,7 +2,7 @@
 // Original source: [Abraxas-365/langchain-rustsrc/src/tools/duckduckgo/duckduckgo_search.rs].
 // Ensure that the usage complies with the original license terms, if applicable.
 
-use std::{ collections::HashMap, error::Error };
+use std::collections::HashMap;
 
 use async_trait::async_trait;
 use reqwest::Client;
@@ -12,6 +12,7 @@ use serde_json::{ json, Value };
 use url::Url;
 use log::{ info, error };
 
+use crate::error::BoxError;
 use crate::tools::Tool;
 
 pub struct DuckDuckGoSearchResults {
@@ -34,7 +35,7 @@ impl DuckDuckGoSearchResults {
         self
     }
 
-    pub async fn search(&self, query: &str) -> Result<Vec<SearchResult>, Box<dyn Error>> {
+    pub async fn search(&self, query: &str) -> Result<Vec<SearchResult>, BoxError> {
         let mut url = Url::parse(&self.url)?;
 
         let mut query_params = HashMap::new();
@@ -118,7 +119,7 @@ impl Tool for DuckDuckGoSearchResults {
         )
     }
 
-    // async fn run(&self, input: Value) -> Result<Value, Box<dyn Error>> {
+    // async fn run(&self, input: Value) -> Result<Value, BoxError> {
     //     let query = input["query"].as_str().ok_or("Input should be a string in the 'query' field")?;
     //     info!("Searching [{}] on DuckDuckGo", query);
 
@@ -126,7 +127,7 @@ impl Tool for DuckDuckGoSearchResults {
     //     Ok(serde_json::to_value(results)?)
     // }
 
-    async fn run(&self, input: Value) -> Result<Value, Box<dyn Error>> {
+    async fn run(&self, input: Value) -> Result<Value, BoxError> {
         // Extract the query string from the input
         let query = input["query"]
             .as_str()
@@ -1,9 +1,10 @@
 use async_trait::async_trait;
 use regex::Regex;
 use scraper::{ ElementRef, Html, Selector };
-use std::{ error::Error, sync::Arc };
+use std::sync::Arc;
 use serde_json::{ json, Value };
 
+use crate::error::BoxError;
 use crate::tools::Tool;
 pub struct WebScrapper {}
 
@@ -39,7 +40,7 @@ impl Tool for WebScrapper {
         })
     }
 
-    async fn run(&self, input: Value) -> Result<Value, Box<dyn Error>> {
+    async fn run(&self, input: Value) -> Result<Value, BoxError> {
         let urls = input["urls"]
             .as_array()
             .ok_or("Input should contain a valid 'urls' field as an array of strings.")?;
@@ -87,7 +88,7 @@ impl Into<Arc<dyn Tool>> for WebScrapper {
     }
 }
 
-async fn scrape_url(url: &str) -> Result<String, Box<dyn Error>> {
+async fn scrape_url(url: &str) -> Result<String, BoxError> {
     let client = reqwest::Client::new();
     let res = client
         .get(url)
@@ -2,12 +2,13 @@
 // Original source: [Abraxas-365/langchain-rustsrc/tools/tool.rs].
 // Ensure that the usage complies with the original license terms, if applicable.
 
-use std::error::Error;
 use std::string::String;
 
 use async_trait::async_trait;
 use serde_json::{ json, Value };
 
+use crate::error::BoxError;
+
 #[async_trait]
 pub trait Tool: Send + Sync {
     /// Returns the name of the tool.
@@ -56,14 +57,14 @@ pub trait Tool: Send + Sync {
     ///
     /// This function utilizes `parse_input` to parse the input and then calls `run`.
     /// Its used by the Agent
-    async fn call(&self, input: &str) -> Result<String, Box<dyn Error>> {
+    async fn call(&self, input: &str) -> Result<String, BoxError> {
         let input = self.parse_input(input).await;
         let json_result = self.run(input).await?;
         // Convert the JSON result to a string
         serde_json::to_string(&json_result).map_err(|e| e.into())
     }
 
-    async fn json_call(&self, input: &str) -> Result<Value, Box<dyn Error>> {
+    async fn json_call(&self, input: &str) -> Result<Value, BoxError> {
         let input = self.parse_input(input).await;
         self.run(input).await
     }
@@ -72,12 +73,12 @@ pub trait Tool: Send + Sync {
     ///
     /// Example implementation:
     /// ```rust,ignore
-    /// async fn run(&self, input: Value) -> Result<String, Box<dyn Error>> {
+    /// async fn run(&self, input: Value) -> Result<String, BoxError> {
     ///     let input_str = input.as_str().ok_or("Input should be a string")?;
     ///     self.simple_search(input_str).await
     /// }
     /// ```
-    async fn run(&self, input: Value) -> Result<Value, Box<dyn Error>>;
+    async fn run(&self, input: Value) -> Result<Value, BoxError>;
 
     /// Parses the input string, which could be a JSON value or a raw string, depending on the LLM model.
     ///
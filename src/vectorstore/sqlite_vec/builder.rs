@@ -1,12 +1,14 @@
-use std::{ error::Error, str::FromStr, sync::Arc, fs };
+use std::{ str::FromStr, sync::Arc, fs };
 
 use sqlx::{ sqlite::{ SqliteConnectOptions, SqlitePoolOptions }, Pool, Sqlite };
 // main.rs
 use super::sqlite_vec::Store;
 use crate::embedding::embedder_trait::Embedder;
+use crate::error::PyanoError;
 pub struct StoreBuilder {
     pool: Option<Pool<Sqlite>>,
     connection_url: Option<String>,
+    db_name: Option<String>,
     table: String,
     embedder: Option<Arc<dyn Embedder>>,
 }
@@ -16,6 +18,7 @@ impl StoreBuilder {
         StoreBuilder {
             pool: None,
             connection_url: None,
+            db_name: None,
             table: "documents".to_string(),
             embedder: None,
         }
@@ -24,6 +27,7 @@ impl StoreBuilder {
     pub fn pool(mut self, pool: Pool<Sqlite>) -> Self {
         self.pool = Some(pool);
         self.connection_url = None;
+        self.db_name = None;
         self
     }
 
@@ -31,23 +35,31 @@ impl StoreBuilder {
         let connection_url = std::env::var("DATABASE_URL").unwrap_or("sqlite::memory:".to_string());
 
         self.connection_url = Some(connection_url.into());
+        self.db_name = None;
         self.pool = None;
         self
     }
 
+    /// Stores the documents in `~/.pyano/database/<db_name>.db`, created by `build`.
     pub fn db_name<S: Into<String>>(mut self, db_name: S) -> Self {
-        let home_directory = dirs::home_dir().unwrap();
-        let root_pyano_dir = home_directory.join(".pyano");
-        let pyano_data_dir = root_pyano_dir.join("database");
-        if !pyano_data_dir.exists() {
-            fs::create_dir_all(&pyano_data_dir).unwrap();
-        }
-        let file_path = pyano_data_dir.join(format!("{}.db", db_name.into()));
-        self.connection_url = Some(format!("sqlite://{}", file_path.display()));
+        self.db_name = Some(db_name.into());
+        self.connection_url = None;
         self.pool = None;
         self
     }
 
+    fn db_url(db_name: &str) -> Result<String, PyanoError> {
+        let home_directory = dirs
+            ::home_dir()
+            .ok_or_else(|| PyanoError::Config("Unable to get home directory".into()))?;
+        let pyano_data_dir = home_directory.join(".pyano").join("database");
+        if !pyano_data_dir.exists() {
+            fs::create_dir_all(&pyano_data_dir)?;
+        }
+        let file_path = pyano_data_dir.join(format!("{}.db", db_name));
+        Ok(format!("sqlite://{}", file_path.display()))
+    }
+
     pub fn table(mut self, table: &str) -> Self {
         self.table = table.into();
         self
@@ -59,13 +71,16 @@ impl StoreBuilder {
     }
 
     // Finalize the builder and construct the Store object
-    pub async fn build(mut self) -> Result<Store, Box<dyn Error>> {
-        if self.connection_url.is_none() {
+    pub async fn build(mut self) -> Result<Store, PyanoError> {
+        if let Some(db_name) = self.db_name.take() {
+            self.connection_url = Some(Self::db_url(&db_name)?);
+        }
+        if self.connection_url.is_none() && self.pool.is_none() {
             self = self.in_memory();
         }
 
         if self.embedder.is_none() {
-            return Err("Embedder is required".into());
+            return Err(PyanoError::Config("Embedder is required".into()));
         }
 
         Ok(Store {
@@ -75,7 +90,7 @@ impl StoreBuilder {
         })
     }
 
-    async fn get_pool(&self) -> Result<Pool<Sqlite>, Box<dyn Error>> {
+    async fn get_pool(&self) -> Result<Pool<Sqlite>, PyanoError> {
         // use sqlite_vec::sqlite3_vec_init;
         // use rusqlite::{ ffi::sqlite3_auto_extension, Result };
         // unsafe {
@@ -87,7 +102,7 @@ impl StoreBuilder {
             None => {
                 let connection_url = self.connection_url
                     .as_ref()
-                    .ok_or("Connection URL or DB is required")?;
+                    .ok_or_else(|| PyanoError::Config("Connection URL or DB is required".into()))?;
 
                 let pool: Pool<Sqlite> = SqlitePoolOptions::new().connect_with(
                     SqliteConnectOptions::from_str(connection_url)?
@@ -1,4 +1,4 @@
-use std::{ collections::HashMap, error::Error, sync::Arc };
+use std::{ collections::HashMap, sync::Arc };
 
 use async_trait::async_trait;
 use serde_json::{ json, Value };
@@ -6,6 +6,7 @@ use sqlx::{ Pool, Row, Sqlite };
 
 use crate::{
     embedding::{ self, embedder_trait::Embedder },
+    error::PyanoError,
     schemas::document::Document,
     vectorstore::{ VecStoreOptions, VectorStore },
 };
@@ -17,12 +18,15 @@ pub struct Store {
 }
 
 impl Store {
-    pub async fn initialize(&self) -> Result<(), Box<dyn Error>> {
+    pub async fn initialize(&self) -> Result<(), PyanoError> {
+        // The vector table is sized with `dimensions`, which some embedders only know once
+        // initialized
+        self.embedder.initialize().await?;
         self.create_table_if_not_exists().await?;
         Ok(())
     }
 
-    async fn create_table_if_not_exists(&self) -> Result<(), Box<dyn Error>> {
+    async fn create_table_if_not_exists(&self) -> Result<(), PyanoError> {
         let table = &self.table;
 
         sqlx
@@ -75,7 +79,7 @@ impl Store {
         Ok(())
     }
 
-    fn get_filters(&self, opt: &VecStoreOptions) -> Result<HashMap<String, Value>, Box<dyn Error>> {
+    fn get_filters(&self, opt: &VecStoreOptions) -> Result<HashMap<String, Value>, PyanoError> {
         match &opt.filters {
             Some(Value::Object(map)) => {
                 // Convert serde_json Map to HashMap<String, Value>
@@ -86,7 +90,7 @@ impl Store {
                 Ok(filters)
             }
             None => Ok(HashMap::new()), // No filters provided
-            _ => Err("Invalid filters format".into()), // Filters provided but not in the expected format
+            _ => Err(PyanoError::VectorStore("Invalid filters format".into())), // Filters provided but not in the expected format
         }
     }
 
@@ -117,7 +121,7 @@ impl VectorStore for Store {
         &self,
         docs: &[Document],
         opt: &VecStoreOptions
-    ) -> Result<Vec<String>, Box<dyn Error>> {
+    ) -> Result<Vec<String>, PyanoError> {
         let texts: Vec<&str> = docs
             .iter()
             .map(|d| d.page_content.as_str())
@@ -128,12 +132,7 @@ impl VectorStore for Store {
         let vectors = embedder.generate_embeddings_on_demand(&texts).await?;
         if vectors.len() != docs.len() {
             return Err(
-                Box::new(
-                    std::io::Error::new(
-                        std::io::ErrorKind::Other,
-                        "Number of vectors and documents do not match"
-                    )
-                )
+                PyanoError::VectorStore("Number of vectors and documents do not match".into())
             );
         }
 
@@ -174,7 +173,7 @@ impl VectorStore for Store {
         query: &str,
         limit: usize,
         opt: &VecStoreOptions
-    ) -> Result<Vec<Document>, Box<dyn Error>> {
+    ) -> Result<Vec<Document>, PyanoError> {
         let table = &self.table;
 
         let embeddings = self.embedder.generate_embeddings_on_demand(&[query]).await?;
@@ -182,7 +181,7 @@ impl VectorStore for Store {
         let query_vector = match embeddings.get(0) {
             Some(query_embeddings) => json!(query_embeddings),
             None => {
-                return Err("No embeddings returned".into());
+                return Err(PyanoError::VectorStore("No embeddings returned".into()));
             } // Handle the case where no embeddings are returned        }
         };
 
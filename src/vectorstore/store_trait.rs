@@ -1,7 +1,6 @@
-use std::error::Error;
-
 use async_trait::async_trait;
 
+use crate::error::PyanoError;
 use crate::schemas::{ retriever::Retriever, document::Document };
 
 use super::VecStoreOptions;
@@ -14,14 +13,14 @@ pub trait VectorStore: Send + Sync {
         &self,
         docs: &[Document],
         opt: &VecStoreOptions
-    ) -> Result<Vec<String>, Box<dyn Error>>;
+    ) -> Result<Vec<String>, PyanoError>;
 
     async fn similarity_search(
         &self,
         query: &str,
         limit: usize,
         opt: &VecStoreOptions
-    ) -> Result<Vec<Document>, Box<dyn Error>>;
+    ) -> Result<Vec<Document>, PyanoError>;
 }
 impl<VS> From<VS> for Box<dyn VectorStore> where VS: 'static + VectorStore {
     fn from(vector_store: VS) -> Self {
@@ -76,7 +75,7 @@ impl DocumentRetriever {
 
 #[async_trait]
 impl Retriever for DocumentRetriever {
-    async fn get_relevant_documents(&self, query: &str) -> Result<Vec<Document>, Box<dyn Error>> {
+    async fn get_relevant_documents(&self, query: &str) -> Result<Vec<Document>, PyanoError> {
         self.vstore.similarity_search(query, self.num_docs, &self.options).await
     }
 }
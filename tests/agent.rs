@@ -0,0 +1,120 @@
+mod common;
+
+use common::llm_for;
+use pyano::agent::agent_builder::AgentBuilder;
+use pyano::agent::agent_trait::AgentTrait;
+use pyano::llm::context::TruncationStrategy;
+use pyano::llm::error::LLMError;
+use pyano::llm::llm_builder::LLM;
+use pyano::llm::options::LLMHTTPCallOptions;
+use pyano::llm::types::CancellationToken;
+use pyano::testing::{ FakeCompletion, FakeLlamaServer };
+use std::time::Duration;
+
+#[tokio::test]
+async fn test_agent_invoke() {
+    let server = FakeLlamaServer::start().await;
+    server.push_completion(FakeCompletion::text("42"));
+
+    let agent = AgentBuilder::new()
+        .with_name("answerer".to_string())
+        .with_system_prompt("Answer with a number".to_string())
+        .with_user_prompt("What is six times seven?".to_string())
+        .with_stream(false)
+        .with_llm(llm_for(&server))
+        .build().unwrap();
+
+    assert_eq!(agent.invoke().await.unwrap(), "42");
+    assert_eq!(server.requests().len(), 1);
+}
+
+#[tokio::test]
+async fn test_cancelled_agent_closes_stream() {
+    let server = FakeLlamaServer::start().await;
+    server.push_completion(
+        FakeCompletion::new(&["one", "two", "three", "four"]).with_token_delay(
+            Duration::from_millis(200)
+        )
+    );
+
+    let agent = AgentBuilder::new()
+        .with_name("counter".to_string())
+        .with_system_prompt("Count".to_string())
+        .with_user_prompt("Go".to_string())
+        .with_stream(true)
+        .with_llm(llm_for(&server))
+        .build().unwrap();
+
+    let cancel = CancellationToken::new();
+    let stop = cancel.clone();
+    tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        stop.cancel();
+    });
+
+    let started = std::time::Instant::now();
+    let error = agent.invoke_with_cancellation(cancel).await.unwrap_err();
+    assert!(matches!(error.downcast_ref(), Some(LLMError::Cancelled)));
+    assert!(started.elapsed() < Duration::from_millis(600));
+
+    // The server notices the closed connection and stops streaming
+    tokio::time::sleep(Duration::from_millis(250)).await;
+    assert_eq!(server.open_streams(), 0);
+}
+
+#[tokio::test]
+async fn test_streaming_agent_reports_errors() {
+    let server = FakeLlamaServer::start().await;
+    server.push_completion(
+        FakeCompletion::new(&["Once", " upon"]).with_stream_error("Out of memory")
+    );
+
+    let agent = AgentBuilder::new()
+        .with_name("storyteller".to_string())
+        .with_system_prompt("Tell a story".to_string())
+        .with_user_prompt("Go".to_string())
+        .with_stream(true)
+        .with_llm(llm_for(&server))
+        .build().unwrap();
+
+    let error = agent.invoke().await.unwrap_err();
+    assert!(
+        matches!(error.downcast_ref(), Some(LLMError::RequestFailed(m)) if m == "Out of memory")
+    );
+}
+
+#[tokio::test]
+async fn test_agent_fits_prompt_into_context() {
+    let server = FakeLlamaServer::start().await;
+    let options = LLMHTTPCallOptions::new()
+        .with_server_url(server.url())
+        .with_prompt_template("{system_prompt}\n{user_prompt}".to_string())
+        .with_context_size(64)
+        .with_max_tokens(16)
+        .build().unwrap();
+    let llm = LLM::builder().with_options(options).build().unwrap();
+    let context = "filler ".repeat(100);
+
+    let agent = AgentBuilder::new()
+        .with_name("rag".to_string())
+        .with_system_prompt("Answer from the context".to_string())
+        .with_user_prompt(format!("{}\nWhat is the answer?", context))
+        .with_llm(llm.clone())
+        .build().unwrap();
+    agent.invoke().await.unwrap();
+
+    let prompt = server.last_request().unwrap()["prompt"].as_str().unwrap().to_string();
+    assert!(prompt.ends_with("What is the answer?"));
+    assert!(llm.count_tokens(&prompt).await <= 48);
+
+    let strict = AgentBuilder::new()
+        .with_name("strict".to_string())
+        .with_system_prompt("Answer from the context".to_string())
+        .with_user_prompt(context)
+        .with_truncation_strategy(TruncationStrategy::Fail)
+        .with_llm(llm)
+        .build().unwrap();
+    let error = strict.invoke().await.unwrap_err();
+    assert!(matches!(error.downcast_ref(), Some(LLMError::ContextOverflow { .. })));
+    assert_eq!(server.requests().len(), 1);
+}
@@ -0,0 +1,64 @@
+mod common;
+
+use common::llm_for;
+use axum::http::StatusCode;
+use pyano::llm::batch::{ BatchOptions, BatchProgress };
+use pyano::testing::{ FakeCompletion, FakeLlamaServer };
+use std::sync::Arc;
+use std::time::Duration;
+
+#[tokio::test]
+async fn test_batch_respects_slots_and_resumes() {
+    let server = FakeLlamaServer::start().await;
+    server.set_slots(2);
+    server.set_default_completion(
+        FakeCompletion::new(&["ok"]).with_token_delay(Duration::from_millis(50))
+    );
+    let llm = llm_for(&server);
+    assert_eq!(llm.slot_count().await, Some(2));
+
+    let prompts = ["a", "b", "c", "d", "e"];
+    let results = llm.batch(&prompts, 8).await.unwrap();
+    assert_eq!(results.len(), 5);
+    assert!(results.iter().all(|result| result.is_ok()));
+    assert_eq!(server.max_concurrent_requests(), 2);
+
+    // An interrupted run: the second item fails, the others are recorded
+    let results_file = std::env::temp_dir().join(
+        format!("pyano-batch-{}.jsonl", std::process::id())
+    );
+    let _ = std::fs::remove_file(&results_file);
+    server.set_default_completion(FakeCompletion::text("default"));
+    server.push_completion(FakeCompletion::text("first"));
+    server.push_completion(FakeCompletion::failing(StatusCode::BAD_REQUEST));
+    server.push_completion(FakeCompletion::text("third"));
+    let reports = Arc::new(parking_lot::Mutex::new(Vec::new()));
+    let recorded = reports.clone();
+    let options = BatchOptions::new()
+        .with_concurrency(1)
+        .with_system_prompt("Classify")
+        .with_results_file(&results_file)
+        .with_progress(move |progress| recorded.lock().push(progress));
+
+    let results = llm.batch_with_options(&prompts[..3], options.clone()).await.unwrap();
+    assert_eq!(results[0].as_ref().unwrap().content, "first");
+    assert!(results[1].is_err());
+    assert_eq!(results[2].as_ref().unwrap().content, "third");
+    assert_eq!(reports.lock().last(), Some(
+        &(BatchProgress { total: 3, completed: 2, failed: 1, skipped: 0 })
+    ));
+
+    // Resuming only sends the failed item
+    let sent = server.requests().len();
+    server.push_completion(FakeCompletion::text("second"));
+    let results = llm.batch_with_options(&prompts[..3], options).await.unwrap();
+    let contents: Vec<String> = results
+        .into_iter()
+        .map(|result| result.unwrap().content)
+        .collect();
+    assert_eq!(contents, vec!["first", "second", "third"]);
+    assert_eq!(server.requests().len(), sent + 1);
+    assert_eq!(server.last_request().unwrap()["prompt"], "Classify\nb");
+    assert!(reports.lock().last().unwrap().is_done());
+    let _ = std::fs::remove_file(&results_file);
+}
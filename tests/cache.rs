@@ -0,0 +1,88 @@
+mod common;
+
+use common::builder_for;
+use futures::StreamExt;
+use pyano::llm::cache::{ CacheConfig, ResponseCache };
+use pyano::llm::llm_builder::LLM;
+use pyano::llm::options::LLMHTTPCallOptions;
+use pyano::llm::response::LLMEvent;
+use pyano::llm::stream_processing::llamacpp_process_stream;
+use pyano::testing::{ FakeCompletion, FakeLlamaServer };
+use std::sync::Arc;
+
+#[tokio::test]
+async fn test_cache_serves_deterministic_requests() {
+    let server = FakeLlamaServer::start().await;
+    server.set_default_completion(FakeCompletion::new(&["Par", "is"]));
+    let cache = Arc::new(ResponseCache::in_memory(CacheConfig::default()).unwrap());
+
+    let options = LLMHTTPCallOptions::new()
+        .with_server_url(server.url())
+        .with_prompt_template("{system_prompt}\n{user_prompt}".to_string())
+        .with_temperature(0.0)
+        .build().unwrap();
+    let llm = LLM::builder().with_options(options).with_cache(cache.clone()).build().unwrap();
+
+    assert_eq!(llm.response("Capital?", "").await.unwrap().content, "Paris");
+    assert_eq!(llm.response("Capital?", "").await.unwrap().content, "Paris");
+    let events: Vec<LLMEvent> = llm
+        .stream("Capital?", "")
+        .await
+        .unwrap()
+        .map(|event| event.unwrap())
+        .collect().await;
+    assert_eq!(events[0], LLMEvent::token("Paris"));
+    assert_eq!(server.requests().len(), 1);
+    assert_eq!(cache.metrics().hits, 2);
+
+    // Sampling at a non-zero temperature is never cached
+    let sampling = builder_for(&server).with_cache(cache.clone()).build().unwrap();
+    sampling.response("Capital?", "").await.unwrap();
+    sampling.response("Capital?", "").await.unwrap();
+    assert_eq!(server.requests().len(), 3);
+    assert_eq!(cache.metrics().misses, 1);
+}
+
+async fn read_body(llm: &LLM) -> String {
+    let chunks: Vec<_> = llm.response_stream("Capital?", "").await.unwrap().collect().await;
+    chunks
+        .into_iter()
+        .map(|chunk| String::from_utf8(chunk.unwrap().to_vec()).unwrap())
+        .collect()
+}
+
+#[tokio::test]
+async fn test_cache_replays_byte_streams_like_the_server() {
+    let server = FakeLlamaServer::start().await;
+    server.set_default_completion(FakeCompletion::new(&["Par", "is"]));
+    let options = LLMHTTPCallOptions::new()
+        .with_server_url(server.url())
+        .with_prompt_template("{system_prompt}\n{user_prompt}".to_string())
+        .with_temperature(0.0)
+        .build().unwrap();
+
+    // Raw llama.cpp frames without a processor, the generated text with one
+    for processed in [false, true] {
+        let cache = Arc::new(ResponseCache::in_memory(CacheConfig::default()).unwrap());
+        let mut builder = LLM::builder().with_options(options.clone()).with_cache(cache.clone());
+        if processed {
+            builder = builder.with_process_response(llamacpp_process_stream);
+        }
+        let llm = builder.build().unwrap();
+
+        let live = read_body(&llm).await;
+        assert_eq!(cache.metrics().stores, 1);
+        let replayed = read_body(&llm).await;
+        assert_eq!(cache.metrics().hits, 1);
+        if processed {
+            assert_eq!(live, "Paris");
+            assert_eq!(replayed, live);
+        } else {
+            // The cache keeps the whole content as one token
+            assert!(live.starts_with(r#"data: {"content":"Par","stop":false}"#), "{}", live);
+            assert!(replayed.starts_with(r#"data: {"content":"Paris","stop":false}"#));
+            assert_eq!(replayed.rsplit("data: ").next(), live.rsplit("data: ").next());
+        }
+    }
+    assert_eq!(server.requests().len(), 2);
+}
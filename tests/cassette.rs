@@ -0,0 +1,87 @@
+mod common;
+
+use common::builder_for;
+use futures::StreamExt;
+use pyano::llm::cassette::CassetteMode;
+use pyano::llm::error::LLMError;
+use pyano::llm::llm_builder::LLM;
+use pyano::llm::options::LLMHTTPCallOptions;
+use pyano::llm::response::LLMEvent;
+use pyano::testing::{ FakeCompletion, FakeLlamaServer };
+
+#[tokio::test]
+async fn test_cassette_records_and_replays() {
+    let cassette = std::env::temp_dir().join(
+        format!("pyano-cassette-{}.json", std::process::id())
+    );
+    let server = FakeLlamaServer::start().await;
+    server.push_completion(FakeCompletion::text("Recorded answer"));
+    server.push_completion(FakeCompletion::new(&["Stre", "amed \u{1f980}"]));
+
+    let recorder = builder_for(&server)
+        .with_cassette(&cassette, CassetteMode::Record)
+        .build().unwrap();
+    let answer = recorder.response("Question", "Be brief").await.unwrap();
+    let recorded: Vec<LLMEvent> = recorder
+        .stream("Stream it", "").await
+        .unwrap()
+        .map(|event| event.unwrap())
+        .collect().await;
+    drop(server);
+
+    let options = LLMHTTPCallOptions::new()
+        .with_server_url("http://127.0.0.1:9".to_string())
+        .with_prompt_template("{system_prompt}\n{user_prompt}".to_string())
+        .build().unwrap();
+    let replayer = LLM::builder()
+        .with_options(options)
+        .with_cassette(&cassette, CassetteMode::Replay)
+        .build().unwrap();
+    let replayed = replayer.response("Question", "Be brief").await.unwrap();
+    assert_eq!(replayed.content, answer.content);
+    let events: Vec<LLMEvent> = replayer
+        .stream("Stream it", "").await
+        .unwrap()
+        .map(|event| event.unwrap())
+        .collect().await;
+    assert_eq!(events, recorded);
+
+    let error = replayer.response("Another question", "Be brief").await.unwrap_err();
+    let message = error.to_string();
+    assert!(matches!(error.downcast_ref(), Some(LLMError::CassetteMismatch(_))));
+    assert!(message.contains("prompt: recorded \"Be brief\\nQuestion\""), "{}", message);
+
+    let _ = std::fs::remove_file(&cassette);
+    let missing = LLM::builder().with_cassette(&cassette, CassetteMode::Replay).build();
+    assert!(matches!(missing, Err(LLMError::Cassette(_))));
+}
+
+#[tokio::test]
+async fn test_llms_recording_to_one_cassette_keep_each_others_interactions() {
+    let cassette = std::env::temp_dir().join(
+        format!("pyano-shared-cassette-{}.json", std::process::id())
+    );
+    let server = FakeLlamaServer::start().await;
+    server.push_completion(FakeCompletion::text("First answer"));
+    server.push_completion(FakeCompletion::text("Second answer"));
+
+    let recorder = || builder_for(&server).with_cassette(&cassette, CassetteMode::Record);
+    let first = recorder().build().unwrap();
+    let second = recorder().build().unwrap();
+    first.response("First", "").await.unwrap();
+    second.response("Second", "").await.unwrap();
+    drop(server);
+
+    let options = LLMHTTPCallOptions::new()
+        .with_server_url("http://127.0.0.1:9".to_string())
+        .with_prompt_template("{system_prompt}\n{user_prompt}".to_string())
+        .build().unwrap();
+    let replayer = LLM::builder()
+        .with_options(options)
+        .with_cassette(&cassette, CassetteMode::Replay)
+        .build().unwrap();
+    assert_eq!(replayer.response("First", "").await.unwrap().content, "First answer");
+    assert_eq!(replayer.response("Second", "").await.unwrap().content, "Second answer");
+
+    let _ = std::fs::remove_file(&cassette);
+}
@@ -0,0 +1,27 @@
+//! Fixtures shared by the integration tests, which run against `pyano::testing`'s fake
+//! llama-server. Each test file uses only some of them.
+#![allow(dead_code)]
+
+use std::time::Duration;
+
+use pyano::llm::llm_builder::{ LLMBuilder, LLM };
+use pyano::llm::options::LLMHTTPCallOptions;
+use pyano::llm::resilience::RetryPolicy;
+use pyano::llm::stream_processing::llamacpp_process_stream;
+use pyano::testing::FakeLlamaServer;
+
+pub fn builder_for(server: &FakeLlamaServer) -> LLMBuilder {
+    let options = LLMHTTPCallOptions::new()
+        .with_server_url(server.url())
+        .with_prompt_template("{system_prompt}\n{user_prompt}".to_string())
+        .build().unwrap();
+    LLM::builder().with_options(options).with_process_response(llamacpp_process_stream)
+}
+
+pub fn llm_for(server: &FakeLlamaServer) -> LLM {
+    builder_for(server).build().unwrap()
+}
+
+pub fn fast_retries() -> RetryPolicy {
+    RetryPolicy::default().with_initial_backoff(Duration::from_millis(20))
+}
@@ -0,0 +1,37 @@
+use pyano::embedding::embedder_trait::Embedder;
+use pyano::embedding::llama_server::LlamaServerEmbedder;
+use pyano::model::ModelType;
+use pyano::testing::{ FakeManagerEvent, FakeModelManager };
+
+#[tokio::test]
+async fn test_llama_server_embedder_loads_model_and_batches() {
+    let manager = FakeModelManager::new(8.0);
+    let mut config = FakeModelManager::model_config("nomic-embed", 0.5);
+    config.model_type = ModelType::Embedding;
+    let server = manager.add_model(config).await;
+    server.set_embedding_dimensions(4);
+
+    let embedder = LlamaServerEmbedder::new(manager.clone(), "nomic-embed").with_batch_size(2);
+    assert_eq!(embedder.dimensions(), 0);
+    embedder.initialize().await.unwrap();
+    assert_eq!(embedder.dimensions(), 4);
+    assert_eq!(manager.events(), vec![FakeManagerEvent::Loaded("nomic-embed".to_string())]);
+
+    let embeddings = embedder
+        .generate_embeddings_on_demand(&["first text", "second", "third one"]).await
+        .unwrap();
+    assert_eq!(embeddings.len(), 3);
+    for embedding in &embeddings {
+        assert_eq!(embedding.len(), 4);
+        let norm: f32 = embedding.iter().map(|value| value * value).sum();
+        assert!((norm - 1.0).abs() < 1e-5);
+    }
+    assert_ne!(embeddings[0], embeddings[1]);
+
+    let batches: Vec<usize> = server
+        .requests()
+        .iter()
+        .map(|request| request["content"].as_array().unwrap().len())
+        .collect();
+    assert_eq!(batches, vec![2, 1]);
+}
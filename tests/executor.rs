@@ -0,0 +1,142 @@
+use pyano::agent::agent_builder::AgentBuilder;
+use pyano::agent::executor::{ AgentExecutor, FinishReason, ToolCallFormat };
+use pyano::error::{ BoxError, PyanoError };
+use pyano::llm::chat::ChatTemplate;
+use pyano::llm::context::TruncationStrategy;
+use pyano::llm::error::LLMError;
+use pyano::llm::llm_builder::LLM;
+use pyano::llm::options::LLMHTTPCallOptions;
+use pyano::testing::{ FakeCompletion, FakeLlamaServer };
+use pyano::tools::Tool;
+use std::sync::Arc;
+
+struct AdderTool;
+
+#[async_trait::async_trait]
+impl Tool for AdderTool {
+    fn name(&self) -> String {
+        "add".to_string()
+    }
+
+    fn description(&self) -> String {
+        "Adds two numbers".to_string()
+    }
+
+    async fn run(&self, input: serde_json::Value) -> Result<serde_json::Value, BoxError> {
+        let a = input["a"].as_i64().ok_or("a is missing")?;
+        let b = input["b"].as_i64().ok_or("b is missing")?;
+        Ok(serde_json::json!(a + b))
+    }
+}
+
+#[tokio::test]
+async fn test_executor_dispatches_tool_calls() {
+    let server = FakeLlamaServer::start().await;
+    let options = LLMHTTPCallOptions::new()
+        .with_server_url(server.url())
+        .with_chat_template(ChatTemplate::chatml())
+        .build().unwrap();
+    let llm = LLM::builder().with_options(options).build().unwrap();
+    let agent = || {
+        AgentBuilder::new()
+            .with_system_prompt("You are a calculator".to_string())
+            .with_user_prompt("What is 2 + 3?".to_string())
+            .with_tools(vec![Arc::new(AdderTool)])
+            .with_llm(llm.clone())
+            .build().unwrap()
+    };
+
+    server.push_completion(
+        FakeCompletion::text(
+            "<tool_call>\n{\"name\": \"add\", \"arguments\": {\"a\": 2, \"b\": 3}}\n\
+             </tool_call>"
+        )
+    );
+    server.push_completion(
+        FakeCompletion::text("<tool_call>{\"name\": \"divide\"}</tool_call>")
+    );
+    server.push_completion(FakeCompletion::text("2 + 3 = 5"));
+    let run = AgentExecutor::from_agent(agent()).run().await.unwrap();
+    assert_eq!(run.output, "2 + 3 = 5");
+    assert_eq!(run.finish_reason, FinishReason::Answer);
+    assert_eq!(run.steps.len(), 2);
+    assert_eq!(run.steps[0].observation, "5");
+    assert!(run.steps[1].is_error);
+
+    let prompt = server.last_request().unwrap()["prompt"].as_str().unwrap().to_string();
+    assert!(prompt.contains("<tools>\n{\"name\":\"add\""));
+    assert!(prompt.contains("<tool_response>\n5\n</tool_response>"));
+    assert!(prompt.contains("Tool divide not found"));
+
+    // ReAct, failing on the first tool error
+    server.push_completion(FakeCompletion::text("Action: add\nAction Input: {\"a\": 2}"));
+    let error = AgentExecutor::from_agent(agent())
+        .with_format(ToolCallFormat::ReAct)
+        .with_break_if_error(true)
+        .run().await
+        .unwrap_err();
+    assert!(matches!(error, PyanoError::Tool(message) if message.contains("b is missing")));
+    let stop = server.last_request().unwrap()["stop"].clone();
+    assert_eq!(stop, serde_json::json!(["\nObservation:"]));
+
+    // A model that never stops calling tools
+    server.set_default_completion(
+        FakeCompletion::text("Action: add\nAction Input: {\"a\": 1, \"b\": 1}")
+    );
+    let run = AgentExecutor::from_agent(agent())
+        .with_format(ToolCallFormat::ReAct)
+        .with_max_iterations(2)
+        .run().await
+        .unwrap();
+    assert_eq!(run.finish_reason, FinishReason::MaxIterations);
+    assert_eq!(run.steps.len(), 2);
+    let prompt = server.last_request().unwrap()["prompt"].as_str().unwrap().to_string();
+    assert!(prompt.contains("Observation: 2"));
+}
+
+#[tokio::test]
+async fn test_executor_fits_conversation_into_context() {
+    let server = FakeLlamaServer::start().await;
+    let llm = |context_size: Option<usize>| {
+        let mut options = LLMHTTPCallOptions::new()
+            .with_server_url(server.url())
+            .with_chat_template(ChatTemplate::chatml())
+            .with_max_tokens(16);
+        if let Some(context_size) = context_size {
+            options = options.with_context_size(context_size);
+        }
+        LLM::builder().with_options(options.build().unwrap()).build().unwrap()
+    };
+    let agent = |llm: LLM, truncation: TruncationStrategy| {
+        AgentBuilder::new()
+            .with_system_prompt("You are a calculator".to_string())
+            .with_user_prompt("What is 2 + 3?".to_string())
+            .with_tools(vec![Arc::new(AdderTool)])
+            .with_truncation_strategy(truncation)
+            .with_llm(llm)
+            .build().unwrap()
+    };
+    let tool_call =
+        "<tool_call>\n{\"name\": \"add\", \"arguments\": {\"a\": 2, \"b\": 3}}\n</tool_call>";
+
+    // Room for the first turn, but not for the tool call and its result on top
+    server.push_completion(FakeCompletion::text("5"));
+    AgentExecutor::from_agent(agent(llm(None), TruncationStrategy::Fail)).run().await.unwrap();
+    let first_prompt = server.last_request().unwrap()["prompt"].as_str().unwrap().to_string();
+    let context_size = llm(None).count_tokens(&first_prompt).await + 16 + 20;
+
+    server.push_completion(FakeCompletion::text(tool_call));
+    let strict = agent(llm(Some(context_size)), TruncationStrategy::Fail);
+    let error = AgentExecutor::from_agent(strict).run().await.unwrap_err();
+    assert!(matches!(error, PyanoError::LLM(LLMError::ContextOverflow { .. })));
+    assert_eq!(server.requests().len(), 2);
+
+    server.push_completion(FakeCompletion::text(tool_call));
+    server.push_completion(FakeCompletion::text("2 + 3 = 5"));
+    let dropping = agent(llm(Some(context_size)), TruncationStrategy::DropOldestTurns);
+    let run = AgentExecutor::from_agent(dropping).run().await.unwrap();
+    assert_eq!(run.output, "2 + 3 = 5");
+    let prompt = server.last_request().unwrap()["prompt"].as_str().unwrap().to_string();
+    assert!(!prompt.contains("What is 2 + 3?"));
+    assert!(prompt.contains("<tool_response>\n5\n</tool_response>"));
+}
@@ -0,0 +1,140 @@
+mod common;
+
+use common::llm_for;
+use futures::StreamExt;
+use pyano::agent::agent_builder::AgentBuilder;
+use pyano::error::PyanoError;
+use pyano::llm::error::LLMError;
+use pyano::llm::llm_builder::LLM;
+use pyano::llm::options::LLMHTTPCallOptions;
+use pyano::llm::provider::OpenAIProvider;
+use pyano::llm::response::{ LLMEvent, StopReason };
+use pyano::testing::{ FakeCompletion, FakeLlamaServer };
+
+#[tokio::test]
+async fn test_typed_event_stream() {
+    let server = FakeLlamaServer::start().await;
+    server.push_completion(
+        FakeCompletion::new(&["Bonjour", " \u{1f980}"]).with_stop_word("</s>")
+    );
+
+    let events: Vec<LLMEvent> = llm_for(&server)
+        .stream("Hi", "")
+        .await
+        .unwrap()
+        .map(|event| event.unwrap())
+        .collect().await;
+
+    assert_eq!(events[0], LLMEvent::token("Bonjour"));
+    assert_eq!(events[1], LLMEvent::token(" \u{1f980}"));
+    assert!(matches!(events[2], LLMEvent::Timings(_)));
+    assert_eq!(events[3], LLMEvent::Done {
+        stop_reason: StopReason::StopWord("</s>".to_string()),
+    });
+}
+
+#[test]
+fn test_builders_report_missing_fields() {
+    let error = AgentBuilder::new().with_user_prompt("Hi".to_string()).build().err();
+    assert!(matches!(error, Some(PyanoError::Config(_))));
+
+    let error = LLMHTTPCallOptions::new().build().err().unwrap();
+    assert!(matches!(error, LLMError::InvalidOptions(_)));
+    assert!(matches!(PyanoError::from(error), PyanoError::LLM(_)));
+}
+
+#[tokio::test]
+async fn test_sampling_options_are_validated_and_honoured() {
+    let server = FakeLlamaServer::start().await;
+    server.set_default_completion(FakeCompletion::new(&["A", "B", "C"]));
+
+    let options = LLMHTTPCallOptions::new()
+        .with_server_url(server.url())
+        .with_prompt_template("{system_prompt}\n{user_prompt}".to_string())
+        .with_max_tokens(2)
+        .with_min_p(0.05)
+        .build().unwrap();
+    let llm = LLM::builder().with_options(options.clone()).build().unwrap();
+    let response = llm.response("Count", "").await.unwrap();
+    assert_eq!(response.content, "AB");
+    assert_eq!(response.stop_reason, StopReason::Length);
+
+    let error = LLM::builder().with_options(options.with_top_p(1.5)).build().err();
+    assert!(matches!(error, Some(LLMError::InvalidOptions(_))));
+    assert_eq!(server.requests().len(), 1);
+}
+
+#[tokio::test]
+async fn test_structured_output_retries_with_feedback() {
+    #[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
+    struct Answer {
+        value: u32,
+    }
+
+    let server = FakeLlamaServer::start().await;
+    server.push_completion(FakeCompletion::text("forty-two"));
+    server.push_completion(FakeCompletion::text(r#"{"value": 42}"#));
+
+    let answer: Answer = llm_for(&server)
+        .generate_structured("What is six times seven?", "")
+        .await
+        .unwrap();
+
+    assert_eq!(answer.value, 42);
+    let requests = server.requests();
+    assert_eq!(requests[0]["json_schema"]["required"], serde_json::json!(["value"]));
+    let retry_prompt = requests[1]["prompt"].as_str().unwrap();
+    assert!(retry_prompt.contains("forty-two"));
+    assert!(retry_prompt.contains("It is not valid"));
+}
+
+#[tokio::test]
+async fn test_logprobs_and_label_scores() {
+    let server = FakeLlamaServer::start().await;
+    let llm = llm_for(&server);
+    server.push_completion(FakeCompletion::new(&["Po", "sitive"]).with_logprobs(&[-0.1, -0.2]));
+    server.push_completion(FakeCompletion::new(&["Neg", "ative"]).with_logprobs(&[-2.0, -0.3]));
+
+    let scores = llm.score_labels("Great movie!", "", &["positive", "negative"]).await.unwrap();
+    assert_eq!(scores[0].label, "positive");
+    assert!((scores[0].logprob + 0.3).abs() < 1e-9);
+    assert!(scores[0].probability > 0.85 && scores[0].probability < 1.0);
+
+    let request = server.last_request().unwrap();
+    assert_eq!(request["grammar"], "root ::= \"negative\"");
+    assert_eq!(request["n_probs"], 1);
+
+    server.push_completion(FakeCompletion::new(&["Hi"]).with_logprobs(&[-0.5]));
+    let options = LLMHTTPCallOptions::new()
+        .with_server_url(server.url())
+        .with_prompt_template("{system_prompt}\n{user_prompt}".to_string())
+        .with_n_probs(2)
+        .build()
+        .unwrap();
+    let llm = LLM::builder().with_options(options).build().unwrap();
+    let events: Vec<LLMEvent> = llm
+        .stream("Greet", "").await
+        .unwrap()
+        .map(|event| event.unwrap())
+        .collect().await;
+    match &events[0] {
+        LLMEvent::Token { content, logprobs } => {
+            assert_eq!(content, "Hi");
+            assert_eq!(logprobs[0].logprob, -0.5);
+        }
+        event => panic!("Expected a token, got {:?}", event),
+    }
+}
+
+#[tokio::test]
+async fn test_count_tokens_with_tokenizer_and_fallback() {
+    let server = FakeLlamaServer::start().await;
+    assert_eq!(llm_for(&server).count_tokens("one two three").await, 3);
+
+    let options = LLMHTTPCallOptions::new().with_server_url(server.url()).build().unwrap();
+    let openai = LLM::builder()
+        .with_options(options)
+        .with_provider(OpenAIProvider::new("qwen-7b"))
+        .build().unwrap();
+    assert_eq!(openai.count_tokens("one two three").await, 4);
+}
@@ -0,0 +1,128 @@
+mod common;
+
+use common::builder_for;
+use futures::StreamExt;
+use pyano::llm::error::LLMError;
+use pyano::llm::middleware::{ LLMMiddleware, LLMRequest, MiddlewareAction };
+use pyano::llm::response::LLMEvent;
+use pyano::llm::types::EventStream;
+use pyano::testing::{ FakeCompletion, FakeLlamaServer };
+use std::sync::Arc;
+
+/// Records the hooks it runs, and answers prompts containing "ping" itself.
+struct TracingMiddleware {
+    name: &'static str,
+    trace: Arc<parking_lot::Mutex<Vec<String>>>,
+}
+
+#[async_trait::async_trait]
+impl LLMMiddleware for TracingMiddleware {
+    async fn before_request(
+        &self,
+        request: &mut LLMRequest
+    ) -> Result<MiddlewareAction, LLMError> {
+        self.trace.lock().push(format!("{} before", self.name));
+        if self.name == "redact" {
+            request.map_text(|text| text.replace("4111", "****"));
+        }
+        if self.name == "responder" && request.prompt().is_some_and(|p| p.contains("ping")) {
+            let response = pyano::llm::response::LLMResponse::from_llamacpp(
+                serde_json::json!({ "content": "pong", "stop": true })
+            )?;
+            return Ok(MiddlewareAction::Respond(Box::new(response)));
+        }
+        Ok(MiddlewareAction::Continue)
+    }
+
+    async fn after_response(
+        &self,
+        _request: &LLMRequest,
+        response: &mut pyano::llm::response::LLMResponse
+    ) -> Result<(), LLMError> {
+        self.trace.lock().push(format!("{} after", self.name));
+        response.content = format!("{}[{}]", response.content, self.name);
+        Ok(())
+    }
+
+    fn wrap_stream(&self, _request: &LLMRequest, stream: EventStream) -> EventStream {
+        let trace = self.trace.clone();
+        let name = self.name;
+        Box::pin(
+            stream.inspect(move |event| {
+                if let Ok(LLMEvent::Token { content, .. }) = event {
+                    trace.lock().push(format!("{} token {}", name, content));
+                }
+            })
+        )
+    }
+}
+
+#[tokio::test]
+async fn test_middleware_hooks_run_in_order() {
+    let server = FakeLlamaServer::start().await;
+    let trace = Arc::new(parking_lot::Mutex::new(Vec::new()));
+    let layer = |name| TracingMiddleware { name, trace: trace.clone() };
+    let llm = builder_for(&server)
+        .with_middleware(layer("redact"))
+        .with_middleware(layer("responder"))
+        .build().unwrap();
+
+    server.push_completion(FakeCompletion::text("Charged"));
+    let response = llm.response("Card 4111 1111", "").await.unwrap();
+    assert_eq!(response.content, "Charged[responder][redact]");
+    assert_eq!(server.last_request().unwrap()["prompt"], "\nCard **** 1111");
+    assert_eq!(*trace.lock(), vec![
+        "redact before",
+        "responder before",
+        "responder after",
+        "redact after"
+    ]);
+
+    // The responder answers without the server; only the layers before it see the response
+    trace.lock().clear();
+    let sent = server.requests().len();
+    let response = llm.response("ping", "").await.unwrap();
+    assert_eq!(response.content, "pong[redact]");
+    assert_eq!(server.requests().len(), sent);
+
+    trace.lock().clear();
+    server.push_completion(FakeCompletion::new(&["a", "b"]));
+    let _: Vec<_> = llm.stream("Stream", "").await.unwrap().collect().await;
+    assert_eq!(*trace.lock(), vec![
+        "redact before",
+        "responder before",
+        "responder token a",
+        "redact token a",
+        "responder token b",
+        "redact token b"
+    ]);
+}
+
+#[tokio::test]
+async fn test_middleware_wraps_byte_streams() {
+    let server = FakeLlamaServer::start().await;
+    let trace = Arc::new(parking_lot::Mutex::new(Vec::new()));
+    let layer = |name| TracingMiddleware { name, trace: trace.clone() };
+    let llm = builder_for(&server)
+        .with_middleware(layer("redact"))
+        .with_middleware(layer("responder"))
+        .build().unwrap();
+
+    server.push_completion(FakeCompletion::new(&["a", "b"]));
+    let chunks: Vec<_> = llm.response_stream("Stream", "").await.unwrap().collect().await;
+    assert_eq!(chunks.len(), 2);
+    assert_eq!(*trace.lock(), vec![
+        "redact before",
+        "responder before",
+        "responder token a",
+        "redact token a",
+        "responder token b",
+        "redact token b"
+    ]);
+
+    // The responder's answer reaches the layers before it as a stream too
+    trace.lock().clear();
+    let chunks: Vec<_> = llm.response_stream("ping", "").await.unwrap().collect().await;
+    assert_eq!(chunks[0].as_ref().unwrap().as_ref(), b"pong");
+    assert_eq!(*trace.lock(), vec!["redact before", "responder before", "redact token pong"]);
+}
@@ -0,0 +1,38 @@
+use pyano::model::ModelManagerInterface;
+use pyano::testing::{ FakeCompletion, FakeManagerEvent, FakeModelManager };
+
+#[tokio::test]
+async fn test_llm_reloads_evicted_model() {
+    let manager = FakeModelManager::new(6.0);
+    manager.add_model(FakeModelManager::model_config("first", 4.0)).await;
+    manager.add_model(FakeModelManager::model_config("second", 4.0)).await;
+
+    let llm = manager.get_or_create_llm("first", None, true).await.unwrap();
+    manager.load_model_by_name("second").await.unwrap();
+
+    manager.server("first").unwrap().push_completion(FakeCompletion::text("back again"));
+    let response = llm.response("Hi", "").await.unwrap();
+
+    assert_eq!(response.content, "back again");
+    assert_eq!(
+        manager.events().last(),
+        Some(&FakeManagerEvent::Loaded("first".to_string()))
+    );
+}
+
+#[tokio::test]
+async fn test_token_count_loads_model() {
+    let manager = FakeModelManager::new(6.0);
+    manager.add_model(FakeModelManager::model_config("first", 4.0)).await;
+    manager.add_model(FakeModelManager::model_config("second", 4.0)).await;
+
+    let llm = manager.get_or_create_llm("first", None, true).await.unwrap();
+    manager.load_model_by_name("second").await.unwrap();
+
+    // Counted by the model's tokenizer rather than estimated while it was evicted
+    assert_eq!(llm.count_tokens("one two three").await, 3);
+    assert_eq!(
+        manager.events().last(),
+        Some(&FakeManagerEvent::Loaded("first".to_string()))
+    );
+}
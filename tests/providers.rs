@@ -0,0 +1,151 @@
+mod common;
+
+use common::llm_for;
+use futures::StreamExt;
+use pyano::agent::agent_builder::AgentBuilder;
+use pyano::agent::agent_trait::AgentTrait;
+use pyano::llm::chat::ChatMessage;
+use pyano::llm::error::LLMError;
+use pyano::llm::image::ImageInput;
+use pyano::llm::llm_builder::LLM;
+use pyano::llm::options::LLMHTTPCallOptions;
+use pyano::llm::provider::{ OllamaProvider, OpenAIProvider };
+use pyano::llm::response::{ LLMEvent, StopReason };
+use pyano::testing::{ FakeCompletion, FakeLlamaServer };
+
+#[tokio::test]
+async fn test_openai_provider() {
+    let server = FakeLlamaServer::start().await;
+    server.push_completion(FakeCompletion::text("Paris"));
+    server.push_completion(FakeCompletion::new(&["A", "B", "C"]));
+
+    let options = LLMHTTPCallOptions::new()
+        .with_server_url(server.url())
+        .with_max_tokens(2)
+        .build().unwrap();
+    let llm = LLM::builder()
+        .with_options(options)
+        .with_provider(OpenAIProvider::new("qwen-7b").with_api_key("secret"))
+        .build().unwrap();
+
+    let messages = [ChatMessage::system("Be brief"), ChatMessage::user("Capital of France?")];
+    let response = llm.chat(&messages).await.unwrap();
+    assert_eq!(response.content, "Paris");
+    assert_eq!(response.stop_reason, StopReason::Eos);
+    assert_eq!(response.usage.completion_tokens, 1);
+
+    let request = server.last_request().unwrap();
+    assert_eq!(request["model"], "qwen-7b");
+    assert_eq!(request["messages"][1]["content"], "Capital of France?");
+    assert_eq!(request["max_tokens"], 2);
+
+    let events: Vec<LLMEvent> = llm
+        .stream("Count", "")
+        .await
+        .unwrap()
+        .map(|event| event.unwrap())
+        .collect().await;
+    assert_eq!(events, vec![
+        LLMEvent::token("A"),
+        LLMEvent::token("B"),
+        LLMEvent::Done { stop_reason: StopReason::Length }
+    ]);
+}
+
+#[tokio::test]
+async fn test_openai_tool_messages() {
+    let server = FakeLlamaServer::start().await;
+    server.set_default_completion(FakeCompletion::text("It is sunny"));
+
+    let options = LLMHTTPCallOptions::new().with_server_url(server.url()).build().unwrap();
+    let llm = LLM::builder()
+        .with_options(options)
+        .with_provider(OpenAIProvider::new("qwen-7b"))
+        .build().unwrap();
+
+    let messages = [
+        ChatMessage::user("Weather in Oslo?"),
+        ChatMessage::tool("weather", "sunny").with_tool_call_id("call_1"),
+        ChatMessage::tool("weather", "sunny"),
+    ];
+    llm.chat(&messages).await.unwrap();
+
+    let request = server.last_request().unwrap();
+    assert_eq!(request["messages"][1]["role"], "tool");
+    assert_eq!(request["messages"][1]["tool_call_id"], "call_1");
+    // Without an id the result can't be matched to a call, so it is sent as an observation
+    assert_eq!(request["messages"][2]["role"], "user");
+    assert_eq!(request["messages"][2]["content"], "Observation from weather: sunny");
+    assert!(request["messages"][2].get("tool_call_id").is_none());
+}
+
+#[tokio::test]
+async fn test_images_are_sent_to_each_provider() {
+    let server = FakeLlamaServer::start().await;
+    server.set_default_completion(FakeCompletion::text("A cat"));
+    let image = ImageInput::from_bytes(b"png".to_vec(), "image/png");
+
+    let llm = llm_for(&server);
+    let images = [image.clone(), image.clone()];
+    llm.response_with_images("What is this?", "", &images).await.unwrap();
+    let request = server.last_request().unwrap();
+    assert!(request["prompt"].as_str().unwrap().contains("[img-0] [img-1]\nWhat is this?"));
+    assert_eq!(request["image_data"][1], serde_json::json!({ "data": "cG5n", "id": 1 }));
+
+    let options = LLMHTTPCallOptions::new().with_server_url(server.url()).build().unwrap();
+    let llm = LLM::builder()
+        .with_options(options)
+        .with_provider(OpenAIProvider::new("llava"))
+        .build()
+        .unwrap();
+    let messages = [ChatMessage::user("What is this?").with_image(image)];
+    llm.chat(&messages).await.unwrap();
+    let content = &server.last_request().unwrap()["messages"][0]["content"];
+    assert_eq!(content[0]["text"], "What is this?");
+    assert_eq!(content[1]["image_url"]["url"], "data:image/png;base64,cG5n");
+}
+
+#[tokio::test]
+async fn test_ollama_provider() {
+    let server = FakeLlamaServer::start().await;
+    server.push_completion(FakeCompletion::text("Paris"));
+    server.push_completion(FakeCompletion::new(&["Hello", " world"]));
+
+    let options = LLMHTTPCallOptions::new().with_server_url(server.url()).build().unwrap();
+    let llm = LLM::builder()
+        .with_options(options)
+        .with_provider(OllamaProvider::new("llama3.2"))
+        .build().unwrap();
+
+    let response = llm.response("Capital of France?", "Be brief").await.unwrap();
+    assert_eq!(response.content, "Paris");
+    assert_eq!(response.usage.prompt_tokens, 8);
+    assert_eq!(response.timings.unwrap().predicted_per_second, 500.0);
+
+    let request = server.last_request().unwrap();
+    assert_eq!(request["model"], "llama3.2");
+    assert_eq!(request["messages"][0]["role"], "system");
+
+    // Options Ollama can't honour are refused before anything is sent
+    let options = LLMHTTPCallOptions::new()
+        .with_server_url(server.url())
+        .with_logit_bias(vec![(15, -100.0)])
+        .build().unwrap();
+    let biased = LLM::builder()
+        .with_options(options)
+        .with_provider(OllamaProvider::new("llama3.2"))
+        .build().unwrap();
+    let error = biased.response("Capital of France?", "").await.unwrap_err();
+    assert!(matches!(error.downcast_ref(), Some(LLMError::InvalidOptions(_))));
+    assert_eq!(server.requests().len(), 1);
+
+    let agent = AgentBuilder::new()
+        .with_name("greeter".to_string())
+        .with_system_prompt("Greet".to_string())
+        .with_user_prompt("Hi".to_string())
+        .with_stream(true)
+        .with_llm(llm)
+        .build().unwrap();
+    assert_eq!(agent.invoke().await.unwrap(), "Hello world");
+    assert_eq!(server.last_request().unwrap()["stream"], true);
+}
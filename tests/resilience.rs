@@ -0,0 +1,100 @@
+mod common;
+
+use common::{ builder_for, fast_retries };
+use axum::http::StatusCode;
+use futures::StreamExt;
+use pyano::llm::error::LLMError;
+use pyano::llm::resilience::CircuitBreakerConfig;
+use pyano::llm::response::LLMEvent;
+use pyano::testing::{ FakeCompletion, FakeLlamaServer };
+use std::time::Duration;
+
+#[tokio::test]
+async fn test_retries_server_that_is_warming_up() {
+    let server = FakeLlamaServer::start().await;
+    server.push_completion(FakeCompletion::failing(StatusCode::SERVICE_UNAVAILABLE));
+    server.push_completion(FakeCompletion::failing(StatusCode::SERVICE_UNAVAILABLE));
+    server.push_completion(FakeCompletion::text("ready"));
+
+    let llm = builder_for(&server).with_retry_policy(fast_retries()).build().unwrap();
+    assert_eq!(llm.response("Hi", "").await.unwrap().content, "ready");
+    assert_eq!(server.requests().len(), 3);
+
+    // Client errors are not retried
+    server.push_completion(FakeCompletion::failing(StatusCode::BAD_REQUEST));
+    assert!(llm.response("Hi", "").await.is_err());
+    assert_eq!(server.requests().len(), 4);
+}
+
+#[tokio::test]
+async fn test_retries_stream_without_first_token() {
+    let server = FakeLlamaServer::start().await;
+    server.push_completion(
+        FakeCompletion::new(&["slow"]).with_token_delay(Duration::from_secs(5))
+    );
+    server.push_completion(FakeCompletion::new(&["fast"]));
+
+    let llm = builder_for(&server)
+        .with_first_token_timeout(Duration::from_millis(100))
+        .with_retry_policy(fast_retries())
+        .build().unwrap();
+    let events: Vec<LLMEvent> = llm
+        .stream("Hi", "")
+        .await
+        .unwrap()
+        .map(|event| event.unwrap())
+        .collect().await;
+
+    assert_eq!(events[0], LLMEvent::token("fast"));
+    assert_eq!(server.requests().len(), 2);
+}
+
+#[tokio::test]
+async fn test_circuit_breaker_fails_fast() {
+    let server = FakeLlamaServer::start().await;
+    server.set_healthy(false);
+
+    let llm = builder_for(&server)
+        .with_retry_policy(fast_retries())
+        .with_circuit_breaker(CircuitBreakerConfig {
+            failure_threshold: 2,
+            reset_timeout: Duration::from_secs(60),
+        })
+        .build().unwrap();
+
+    let error = llm.response("Hi", "").await.unwrap_err();
+    assert!(matches!(error.downcast_ref(), Some(LLMError::CircuitOpen(_))));
+    assert_eq!(server.requests().len(), 2);
+
+    server.set_healthy(true);
+    assert!(llm.response("Hi", "").await.is_err());
+    assert_eq!(server.requests().len(), 2);
+}
+
+#[tokio::test]
+async fn test_circuit_breaker_recovers_after_client_error() {
+    let server = FakeLlamaServer::start().await;
+    server.set_healthy(false);
+
+    let llm = builder_for(&server)
+        .with_retry_policy(fast_retries())
+        .with_circuit_breaker(CircuitBreakerConfig {
+            failure_threshold: 2,
+            reset_timeout: Duration::from_millis(100),
+        })
+        .build().unwrap();
+
+    assert!(llm.response("Hi", "").await.is_err());
+    assert_eq!(server.requests().len(), 2);
+
+    // The probe gets an answer, so the circuit closes even though it is an error
+    server.set_healthy(true);
+    tokio::time::sleep(Duration::from_millis(150)).await;
+    server.push_completion(FakeCompletion::failing(StatusCode::BAD_REQUEST));
+    assert!(llm.response("Hi", "").await.is_err());
+    assert_eq!(server.requests().len(), 3);
+
+    server.push_completion(FakeCompletion::text("ready"));
+    assert_eq!(llm.response("Hi", "").await.unwrap().content, "ready");
+    assert_eq!(server.requests().len(), 4);
+}
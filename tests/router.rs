@@ -0,0 +1,98 @@
+mod common;
+
+use common::llm_for;
+use axum::http::StatusCode;
+use pyano::agent::agent_builder::AgentBuilder;
+use pyano::agent::agent_trait::AgentTrait;
+use pyano::llm::chat::ChatMessage;
+use pyano::llm::error::LLMError;
+use pyano::llm::llm_builder::LLM;
+use pyano::llm::options::LLMHTTPCallOptions;
+use pyano::llm::resilience::RetryPolicy;
+use pyano::llm::router::{ LLMRouter, RouteHints, RouteTarget };
+use pyano::testing::{ FakeCompletion, FakeLlamaServer };
+use std::time::Duration;
+
+#[tokio::test]
+async fn test_router_escalates_to_larger_model() {
+    let small_server = FakeLlamaServer::start_named("smolTalk").await;
+    let large_server = FakeLlamaServer::start_named("qwen-7b").await;
+    let small_options = LLMHTTPCallOptions::new()
+        .with_server_url(small_server.url())
+        .with_prompt_template("{system_prompt}\n{user_prompt}".to_string())
+        .with_context_size(64)
+        .with_max_tokens(16)
+        .build().unwrap();
+    let small = LLM::builder()
+        .with_options(small_options)
+        .with_retry_policy(RetryPolicy::none())
+        .build().unwrap();
+    let router = LLMRouter::new()
+        .with_model(RouteTarget::new("smolTalk", small))
+        .with_model(
+            RouteTarget::new("qwen-7b", llm_for(&large_server))
+                .with_capabilities(&["code"])
+                .with_cost_tier(1)
+        )
+        .with_attempt_timeout(Duration::from_millis(200));
+
+    let answer = router.response("Hi", "").await.unwrap();
+    assert_eq!(answer.model, "smolTalk");
+    assert!(answer.failures.is_empty());
+
+    // The small model errors
+    small_server.push_completion(FakeCompletion::failing(StatusCode::BAD_REQUEST));
+    let answer = router.response("Hi", "").await.unwrap();
+    assert_eq!(answer.model, "qwen-7b");
+    assert_eq!(answer.failures[0].model, "smolTalk");
+
+    // The small model is too slow
+    small_server.push_completion(
+        FakeCompletion::text("late").with_token_delay(Duration::from_secs(2))
+    );
+    let answer = router.response("Hi", "").await.unwrap();
+    assert_eq!(answer.model, "qwen-7b");
+
+    // The prompt does not fit the small model's context window
+    let sent = small_server.requests().len();
+    let answer = router.response(&"filler ".repeat(100), "").await.unwrap();
+    assert_eq!(answer.model, "qwen-7b");
+    assert!(answer.failures[0].reason.contains("tokens"));
+    assert_eq!(small_server.requests().len(), sent);
+
+    let coder = router.clone().with_hints(RouteHints::new().with_capability("code"));
+    assert_eq!(coder.response("Hi", "").await.unwrap().model, "qwen-7b");
+
+    let cheap = router.clone().with_hints(RouteHints::new().with_max_cost_tier(0));
+    let error = cheap.response(&"filler ".repeat(100), "").await.err().unwrap();
+    assert!(matches!(error.downcast_ref(), Some(LLMError::RoutingFailed(_))));
+}
+
+#[tokio::test]
+async fn test_routed_llm_drives_an_agent() {
+    let small_server = FakeLlamaServer::start_named("smolTalk").await;
+    let vision_server = FakeLlamaServer::start_named("llava").await;
+    let router = LLMRouter::new()
+        .with_model(RouteTarget::new("smolTalk", llm_for(&small_server)))
+        .with_model(
+            RouteTarget::new("llava", llm_for(&vision_server)).with_capabilities(&["vision"])
+        );
+
+    // Hints for a single request
+    let vision = RouteHints::new().with_capability("vision");
+    let messages = [ChatMessage::user("Describe the picture")];
+    assert_eq!(router.chat_with_hints(&messages, &vision).await.unwrap().model, "llava");
+    assert_eq!(router.chat(&messages).await.unwrap().model, "smolTalk");
+
+    // Anything taking an `LLM` can use the router
+    small_server.push_completion(FakeCompletion::failing(StatusCode::BAD_REQUEST));
+    vision_server.push_completion(FakeCompletion::text("42"));
+    let agent = AgentBuilder::new()
+        .with_name("answerer".to_string())
+        .with_system_prompt("Answer with a number".to_string())
+        .with_user_prompt("What is six times seven?".to_string())
+        .with_stream(false)
+        .with_llm(LLM::builder().with_router(router).build().unwrap())
+        .build().unwrap();
+    assert_eq!(agent.invoke().await.unwrap(), "42");
+}
@@ -0,0 +1,49 @@
+mod common;
+
+use common::builder_for;
+use pyano::llm::chat::ChatTemplate;
+use pyano::llm::error::LLMError;
+use pyano::llm::llm_builder::LLM;
+use pyano::llm::options::LLMHTTPCallOptions;
+use pyano::llm::provider::OpenAIProvider;
+use pyano::testing::{ FakeCompletion, FakeLlamaServer };
+
+#[tokio::test]
+async fn test_session_pins_slot_and_saves_cache() {
+    let server = FakeLlamaServer::start().await;
+    let options = LLMHTTPCallOptions::new()
+        .with_server_url(server.url())
+        .with_chat_template(ChatTemplate::chatml())
+        .build().unwrap();
+    let llm = LLM::builder().with_options(options).build().unwrap();
+    let mut session = llm.session(1).unwrap();
+
+    session.prefill("Answer questions about this long report").await.unwrap();
+    let prefill = server.last_request().unwrap();
+    assert_eq!(prefill["id_slot"], 1);
+    assert_eq!(prefill["n_predict"], 1);
+    assert_eq!(prefill["cache_prompt"], true);
+    assert!(server.slot_tokens(1) > 0);
+
+    let saved = session.save("report.bin").await.unwrap();
+    assert_eq!(saved.n_tokens, server.slot_tokens(1));
+
+    server.push_completion(FakeCompletion::text("Ten"));
+    session.chat("How many pages?").await.unwrap();
+    server.push_completion(FakeCompletion::text("None"));
+    let answer = session.ask("Any figures?").await.unwrap();
+    assert_eq!(answer.content, "None");
+    assert_eq!(session.messages().len(), 3);
+    let prompt = server.last_request().unwrap()["prompt"].as_str().unwrap().to_string();
+    assert!(prompt.contains("long report") && !prompt.contains("How many pages?"));
+
+    assert!(session.erase().await.unwrap() > 0);
+    assert_eq!(session.restore("report.bin").await.unwrap(), saved);
+    assert!(matches!(session.restore("missing.bin").await, Err(LLMError::RequestFailed(_))));
+    assert!(matches!(session.save("../escape.bin").await, Err(LLMError::InvalidOptions(_))));
+
+    let openai = builder_for(&server)
+        .with_provider(OpenAIProvider::new("qwen-7b"))
+        .build().unwrap();
+    assert!(openai.session(0).is_err());
+}